@@ -0,0 +1,137 @@
+/**
+ * 校验和算法
+ * 刷写文件和下载数据校验使用的 CRC、累加和及 SHA-256 计算
+ */
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const CRC16_CCITT_POLY: u16 = 0x1021;
+const CRC32_POLY_REFLECTED: u32 = 0xEDB8_8320;
+const CRC32C_POLY_REFLECTED: u32 = 0x82F6_3B78;
+
+/// 校验算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    Crc16Ccitt,
+    Crc32,
+    Crc32c,
+    Sha256,
+    Additive16,
+    Additive32,
+}
+
+impl ChecksumAlgorithm {
+    /// 计算校验值（大端字节序）
+    pub fn compute(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ChecksumAlgorithm::Crc16Ccitt => crc16_ccitt(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Crc32 => crc32(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Crc32c => crc32c(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Sha256 => sha256(data).to_vec(),
+            ChecksumAlgorithm::Additive16 => additive16(data).to_be_bytes().to_vec(),
+            ChecksumAlgorithm::Additive32 => additive32(data).to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE（多项式 0x1021，初值 0xFFFF，不反射）
+pub fn crc16_ccitt(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ CRC16_CCITT_POLY
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32（IEEE 802.3，反射多项式 0xEDB88320）
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_reflected(data, CRC32_POLY_REFLECTED)
+}
+
+/// CRC-32C（Castagnoli，反射多项式 0x82F63B78）
+pub fn crc32c(data: &[u8]) -> u32 {
+    crc32_reflected(data, CRC32C_POLY_REFLECTED)
+}
+
+fn crc32_reflected(data: &[u8], poly: u32) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ poly
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// 16 位字节累加和
+pub fn additive16(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16))
+}
+
+/// 32 位字节累加和
+pub fn additive32(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0u32, |sum, &byte| sum.wrapping_add(byte as u32))
+}
+
+/// SHA-256 摘要
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_values() {
+        assert_eq!(crc16_ccitt(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc16_ccitt(&[]), 0xFFFF);
+        assert_eq!(crc32(&[]), 0);
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(additive16(&[0xFF; 300]), 0x2AD4);
+        assert_eq!(additive32(b"123456789"), 0x01DD);
+        assert_eq!(
+            hex::encode(sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_algorithm_output_width() {
+        let data = b"123456789";
+        assert_eq!(
+            ChecksumAlgorithm::Crc16Ccitt.compute(data),
+            vec![0x29, 0xB1]
+        );
+        assert_eq!(
+            ChecksumAlgorithm::Crc32.compute(data),
+            vec![0xCB, 0xF4, 0x39, 0x26]
+        );
+        assert_eq!(ChecksumAlgorithm::Crc32c.compute(data).len(), 4);
+        assert_eq!(ChecksumAlgorithm::Sha256.compute(data).len(), 32);
+        assert_eq!(
+            ChecksumAlgorithm::Additive16.compute(data),
+            vec![0x01, 0xDD]
+        );
+        assert_eq!(
+            ChecksumAlgorithm::Additive32.compute(data),
+            vec![0x00, 0x00, 0x01, 0xDD]
+        );
+    }
+}
@@ -0,0 +1,736 @@
+/**
+ * 传输数据格式（dataFormatIdentifier）
+ * 高 4 位为压缩方法，低 4 位为加密方法。下载时按配置逐块压缩、加密数据，
+ * 方法编号由 ECU 引导程序约定，可在配置中修改
+ */
+use crate::types::{hex_bytes, UdsError, UdsResult};
+use aes::{Aes128, Aes192, Aes256};
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use ctr::Ctr128BE;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::Cursor;
+
+/// 压缩方法
+pub trait CompressionMethod: Send + Sync {
+    /// 压缩方法编号（0x1–0xF）
+    fn id(&self) -> u8;
+    fn compress(&self, data: &[u8]) -> UdsResult<Vec<u8>>;
+    fn decompress(&self, data: &[u8]) -> UdsResult<Vec<u8>>;
+
+    /// 增量压缩器，不支持时返回 None，由编码流缓存全部输入后一次压缩
+    fn stream_encoder(&self) -> Option<Box<dyn StreamEncoder + '_>> {
+        None
+    }
+}
+
+/// 加密方法
+pub trait EncryptionMethod: Send + Sync {
+    /// 加密方法编号（0x1–0xF）
+    fn id(&self) -> u8;
+    fn encrypt(&self, data: &[u8]) -> UdsResult<Vec<u8>>;
+    fn decrypt(&self, data: &[u8]) -> UdsResult<Vec<u8>>;
+
+    /// 增量加密器，不支持时返回 None，由编码流缓存全部输入后一次加密
+    fn stream_encoder(&self) -> Option<Box<dyn StreamEncoder + '_>> {
+        None
+    }
+}
+
+/// 增量编码器：依次输入数据，返回目前可以输出的编码结果，finish 为 true 时输出全部剩余数据
+pub trait StreamEncoder: Send {
+    fn update(&mut self, input: &[u8], finish: bool) -> UdsResult<Vec<u8>>;
+}
+
+type EncodeFn<'a> = Box<dyn Fn(&[u8]) -> UdsResult<Vec<u8>> + Send + 'a>;
+
+/// 不支持增量编码的方法：缓存全部输入，结束时一次编码
+struct BufferedEncoder<'a> {
+    encode: EncodeFn<'a>,
+    input: Vec<u8>,
+}
+
+impl StreamEncoder for BufferedEncoder<'_> {
+    fn update(&mut self, input: &[u8], finish: bool) -> UdsResult<Vec<u8>> {
+        self.input.extend_from_slice(input);
+        if finish {
+            (self.encode)(&std::mem::take(&mut self.input))
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// 压缩与加密组合，下载时先压缩后加密
+#[derive(Default)]
+pub struct DataCodec {
+    compression: Option<Box<dyn CompressionMethod>>,
+    encryption: Option<Box<dyn EncryptionMethod>>,
+}
+
+impl DataCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_compression(mut self, method: Box<dyn CompressionMethod>) -> Self {
+        self.compression = Some(method);
+        self
+    }
+
+    pub fn with_encryption(mut self, method: Box<dyn EncryptionMethod>) -> Self {
+        self.encryption = Some(method);
+        self
+    }
+
+    /// 未配置压缩和加密
+    pub fn is_identity(&self) -> bool {
+        self.compression.is_none() && self.encryption.is_none()
+    }
+
+    /// 对应的 dataFormatIdentifier
+    pub fn identifier(&self) -> u8 {
+        let compression = self.compression.as_ref().map_or(0, |m| m.id() & 0x0F);
+        let encryption = self.encryption.as_ref().map_or(0, |m| m.id() & 0x0F);
+        (compression << 4) | encryption
+    }
+
+    /// 压缩后加密
+    pub fn encode(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        let compressed = match &self.compression {
+            Some(method) => method.compress(data)?,
+            None => data.to_vec(),
+        };
+        match &self.encryption {
+            Some(method) => method.encrypt(&compressed),
+            None => Ok(compressed),
+        }
+    }
+
+    /// 逐块编码数据，每次取出一个 TransferData 块
+    pub fn encode_stream<'a>(&'a self, data: &'a [u8]) -> EncodeStream<'a> {
+        let mut stages: Vec<Box<dyn StreamEncoder + 'a>> = Vec::new();
+        if let Some(method) = &self.compression {
+            stages.push(method.stream_encoder().unwrap_or_else(|| {
+                Box::new(BufferedEncoder {
+                    encode: Box::new(move |data| method.compress(data)),
+                    input: Vec::new(),
+                })
+            }));
+        }
+        if let Some(method) = &self.encryption {
+            stages.push(method.stream_encoder().unwrap_or_else(|| {
+                Box::new(BufferedEncoder {
+                    encode: Box::new(move |data| method.encrypt(data)),
+                    input: Vec::new(),
+                })
+            }));
+        }
+        EncodeStream {
+            data,
+            consumed: 0,
+            produced: 0,
+            stages,
+            pending: Vec::new(),
+            pending_start: 0,
+            finished: false,
+        }
+    }
+
+    /// 解密后解压
+    pub fn decode(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        let decrypted = match &self.encryption {
+            Some(method) => method.decrypt(data)?,
+            None => data.to_vec(),
+        };
+        match &self.compression {
+            Some(method) => method.decompress(&decrypted),
+            None => Ok(decrypted),
+        }
+    }
+}
+
+/// 分块编码流：每取一块只编码所需的输入，不预先编码整个内存段
+pub struct EncodeStream<'a> {
+    data: &'a [u8],
+    consumed: usize, // 已送入编码器的原始数据字节数
+    produced: usize, // 已取出的编码数据字节数
+    stages: Vec<Box<dyn StreamEncoder + 'a>>,
+    pending: Vec<u8>,
+    pending_start: usize,
+    finished: bool,
+}
+
+impl<'a> EncodeStream<'a> {
+    /// 取出至多 max_len 字节的下一块编码数据，全部取出后返回 None
+    pub fn next_block(&mut self, max_len: usize) -> UdsResult<Option<Cow<'a, [u8]>>> {
+        let max_len = max_len.max(1);
+
+        // 未编码时直接切分原始数据，不复制
+        if self.stages.is_empty() {
+            if self.consumed == self.data.len() {
+                return Ok(None);
+            }
+            let end = (self.consumed + max_len).min(self.data.len());
+            let block = &self.data[self.consumed..end];
+            self.consumed = end;
+            self.produced = end;
+            return Ok(Some(Cow::Borrowed(block)));
+        }
+
+        while self.pending.len() - self.pending_start < max_len && !self.finished {
+            let end = (self.consumed + max_len).min(self.data.len());
+            self.finished = end == self.data.len();
+            let mut chunk = Cow::Borrowed(&self.data[self.consumed..end]);
+            for stage in &mut self.stages {
+                chunk = Cow::Owned(stage.update(&chunk, self.finished)?);
+            }
+            self.consumed = end;
+            self.pending.drain(..self.pending_start);
+            self.pending_start = 0;
+            self.pending.extend_from_slice(&chunk);
+        }
+
+        let available = self.pending.len() - self.pending_start;
+        if available == 0 {
+            return Ok(None);
+        }
+        let len = max_len.min(available);
+        let block = self.pending[self.pending_start..self.pending_start + len].to_vec();
+        self.pending_start += len;
+        self.produced += len;
+        Ok(Some(Cow::Owned(block)))
+    }
+
+    /// 已取出的编码数据字节数
+    pub fn produced(&self) -> usize {
+        self.produced
+    }
+
+    /// 已取出的编码数据对应的原始数据字节数（按已编码部分的比例估算）
+    pub fn progress(&self) -> usize {
+        let buffered = self.pending.len() - self.pending_start;
+        if buffered == 0 {
+            return self.consumed;
+        }
+        (self.consumed as u64 * self.produced as u64 / (self.produced + buffered) as u64) as usize
+    }
+}
+
+/// 压缩配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum CompressionConfig {
+    Lzss {
+        #[serde(default = "default_method_id")]
+        id: u8,
+    },
+    Lzma {
+        #[serde(default = "default_lzma_id")]
+        id: u8,
+    },
+}
+
+/// 加密配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum EncryptionConfig {
+    /// AES-CTR，密钥长度 16/24/32 字节，初始计数块 16 字节
+    AesCtr {
+        #[serde(default = "default_method_id")]
+        id: u8,
+        #[serde(with = "hex_bytes")]
+        key: Vec<u8>,
+        #[serde(with = "hex_bytes")]
+        iv: Vec<u8>,
+    },
+}
+
+fn default_method_id() -> u8 {
+    0x1
+}
+
+fn default_lzma_id() -> u8 {
+    0x2
+}
+
+/// 下载数据编码配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferEncoding {
+    pub compression: Option<CompressionConfig>,
+    pub encryption: Option<EncryptionConfig>,
+}
+
+impl TransferEncoding {
+    /// 根据配置创建编解码器
+    pub fn build(&self) -> UdsResult<DataCodec> {
+        let mut codec = DataCodec::new();
+
+        codec = match &self.compression {
+            Some(CompressionConfig::Lzss { id }) => {
+                codec.with_compression(Box::new(Lzss::new(*id)))
+            }
+            Some(CompressionConfig::Lzma { id }) => {
+                codec.with_compression(Box::new(Lzma::new(*id)))
+            }
+            None => codec,
+        };
+
+        codec = match &self.encryption {
+            Some(EncryptionConfig::AesCtr { id, key, iv }) => {
+                codec.with_encryption(Box::new(AesCtr::new(*id, key, iv)?))
+            }
+            None => codec,
+        };
+
+        Ok(codec)
+    }
+}
+
+/// LZSS 压缩
+///
+/// 每 8 个单元前有一个标志字节（低位在前，1 为原文字节，0 为匹配）。
+/// 匹配占 2 字节：12 位回溯距离减 1，4 位长度减 3（长度 3–18，窗口 4096）。
+pub struct Lzss {
+    id: u8,
+}
+
+const LZSS_WINDOW: usize = 4096;
+const LZSS_MIN_MATCH: usize = 3;
+const LZSS_MAX_MATCH: usize = 18;
+const LZSS_MAX_CHAIN: usize = 128;
+
+impl Lzss {
+    pub fn new(id: u8) -> Self {
+        Self { id }
+    }
+}
+
+/// LZSS 增量压缩，输出与一次压缩全部数据相同
+pub struct LzssEncoder {
+    buffer: Vec<u8>, // 窗口内的历史数据及未压缩的数据
+    base: usize,     // buffer[0] 的绝对位置
+    pos: usize,      // 下一个待压缩的绝对位置
+    head: Vec<usize>,
+    prev: Vec<usize>, // 以位置对窗口大小取模索引
+    group: Vec<u8>,   // 未满 8 个单元的标志组
+    units: usize,
+}
+
+impl Default for LzssEncoder {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            base: 0,
+            pos: 0,
+            head: vec![usize::MAX; LZSS_WINDOW],
+            prev: vec![usize::MAX; LZSS_WINDOW],
+            group: Vec::new(),
+            units: 0,
+        }
+    }
+}
+
+impl LzssEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, pos: usize, end: usize) {
+        if pos + LZSS_MIN_MATCH <= end {
+            let hash = lzss_hash(&self.buffer[pos - self.base..]);
+            self.prev[pos % LZSS_WINDOW] = self.head[hash];
+            self.head[hash] = pos;
+        }
+    }
+
+    /// 在窗口内查找最长匹配，返回（距离，长度）
+    fn longest_match(&self, end: usize) -> (usize, usize) {
+        let pos = self.pos;
+        let max_len = LZSS_MAX_MATCH.min(end - pos);
+        if max_len < LZSS_MIN_MATCH {
+            return (0, 0);
+        }
+
+        let data = &self.buffer;
+        let at = pos - self.base;
+        let mut best = (0, 0);
+        let mut candidate = self.head[lzss_hash(&data[at..])];
+        for _ in 0..LZSS_MAX_CHAIN {
+            if candidate == usize::MAX || pos - candidate > LZSS_WINDOW {
+                break;
+            }
+            let len = data[candidate - self.base..]
+                .iter()
+                .zip(&data[at..at + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best.1 {
+                best = (pos - candidate, len);
+                if len == max_len {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate % LZSS_WINDOW];
+        }
+
+        if best.1 >= LZSS_MIN_MATCH {
+            best
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+impl StreamEncoder for LzssEncoder {
+    fn update(&mut self, input: &[u8], finish: bool) -> UdsResult<Vec<u8>> {
+        self.buffer.extend_from_slice(input);
+        let end = self.base + self.buffer.len();
+        let mut output = Vec::with_capacity(input.len() / 2 + 16);
+
+        // 未结束时保留最长匹配及其哈希所需的后续数据，保证与一次压缩的结果相同
+        while self.pos < end && (finish || self.pos + LZSS_MAX_MATCH + LZSS_MIN_MATCH <= end) {
+            if self.units == 0 {
+                self.group.push(0);
+            }
+
+            let (distance, len) = self.longest_match(end);
+            if len >= LZSS_MIN_MATCH {
+                let code = ((distance - 1) << 4) | (len - LZSS_MIN_MATCH);
+                self.group.push((code >> 8) as u8);
+                self.group.push(code as u8);
+                for p in self.pos..self.pos + len {
+                    self.insert(p, end);
+                }
+                self.pos += len;
+            } else {
+                self.group[0] |= 1 << self.units;
+                self.group.push(self.buffer[self.pos - self.base]);
+                self.insert(self.pos, end);
+                self.pos += 1;
+            }
+
+            self.units += 1;
+            if self.units == 8 {
+                output.append(&mut self.group);
+                self.units = 0;
+            }
+        }
+        if finish {
+            output.append(&mut self.group);
+            self.units = 0;
+        }
+
+        // 只保留窗口内的历史数据，累计超过一个窗口再丢弃，避免频繁移动
+        let keep_from = self.pos.saturating_sub(LZSS_WINDOW);
+        if keep_from - self.base > LZSS_WINDOW {
+            self.buffer.drain(..keep_from - self.base);
+            self.base = keep_from;
+        }
+
+        Ok(output)
+    }
+}
+
+fn lzss_hash(data: &[u8]) -> usize {
+    ((data[0] as usize) << 8 ^ (data[1] as usize) << 4 ^ data[2] as usize) & (LZSS_WINDOW - 1)
+}
+
+impl CompressionMethod for Lzss {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn compress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        LzssEncoder::new().update(data, true)
+    }
+
+    fn decompress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() * 2);
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let flags = data[pos];
+            pos += 1;
+
+            for bit in 0..8 {
+                if pos >= data.len() {
+                    break;
+                }
+                if flags & (1 << bit) != 0 {
+                    output.push(data[pos]);
+                    pos += 1;
+                    continue;
+                }
+
+                if pos + 1 >= data.len() {
+                    return Err(UdsError::DataFormat("truncated LZSS match".to_string()));
+                }
+                let code = ((data[pos] as usize) << 8) | data[pos + 1] as usize;
+                pos += 2;
+                let distance = (code >> 4) + 1;
+                let len = (code & 0x0F) + LZSS_MIN_MATCH;
+                if distance > output.len() {
+                    return Err(UdsError::DataFormat(format!(
+                        "LZSS match distance {} exceeds output {}",
+                        distance,
+                        output.len()
+                    )));
+                }
+                let start = output.len() - distance;
+                for i in 0..len {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn stream_encoder(&self) -> Option<Box<dyn StreamEncoder + '_>> {
+        Some(Box::new(LzssEncoder::new()))
+    }
+}
+
+/// LZMA 压缩（.lzma 格式，含 13 字节头部）
+pub struct Lzma {
+    id: u8,
+}
+
+impl Lzma {
+    pub fn new(id: u8) -> Self {
+        Self { id }
+    }
+}
+
+impl CompressionMethod for Lzma {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn compress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        let mut output = Vec::new();
+        lzma_rs::lzma_compress(&mut Cursor::new(data), &mut output)
+            .map_err(|e| UdsError::DataFormat(format!("LZMA compression failed: {}", e)))?;
+        Ok(output)
+    }
+
+    fn decompress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        let mut output = Vec::new();
+        lzma_rs::lzma_decompress(&mut Cursor::new(data), &mut output)
+            .map_err(|e| UdsError::DataFormat(format!("LZMA decompression failed: {}", e)))?;
+        Ok(output)
+    }
+}
+
+/// AES-CTR 加密（大端 128 位计数器）
+pub struct AesCtr {
+    id: u8,
+    key: Vec<u8>,
+    iv: [u8; 16],
+}
+
+impl AesCtr {
+    pub fn new(id: u8, key: &[u8], iv: &[u8]) -> UdsResult<Self> {
+        if !matches!(key.len(), 16 | 24 | 32) {
+            return Err(UdsError::InvalidParameter(format!(
+                "AES key must be 16, 24 or 32 bytes, got {}",
+                key.len()
+            )));
+        }
+        let iv: [u8; 16] = iv.try_into().map_err(|_| {
+            UdsError::InvalidParameter(format!("AES-CTR IV must be 16 bytes, got {}", iv.len()))
+        })?;
+        Ok(Self {
+            id,
+            key: key.to_vec(),
+            iv,
+        })
+    }
+
+    fn cipher(&self) -> AesCtrCipher {
+        let iv = (&self.iv).into();
+        match self.key.len() {
+            16 => AesCtrCipher::Aes128(Ctr128BE::new(self.key.as_slice().into(), iv)),
+            24 => AesCtrCipher::Aes192(Ctr128BE::new(self.key.as_slice().into(), iv)),
+            _ => AesCtrCipher::Aes256(Ctr128BE::new(self.key.as_slice().into(), iv)),
+        }
+    }
+}
+
+/// AES-CTR 密钥流状态，逐块加密时计数器连续
+enum AesCtrCipher {
+    Aes128(Ctr128BE<Aes128>),
+    Aes192(Ctr128BE<Aes192>),
+    Aes256(Ctr128BE<Aes256>),
+}
+
+impl AesCtrCipher {
+    fn apply_keystream(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut buffer = data.to_vec();
+        match self {
+            Self::Aes128(cipher) => cipher.apply_keystream(&mut buffer),
+            Self::Aes192(cipher) => cipher.apply_keystream(&mut buffer),
+            Self::Aes256(cipher) => cipher.apply_keystream(&mut buffer),
+        }
+        buffer
+    }
+}
+
+impl StreamEncoder for AesCtrCipher {
+    fn update(&mut self, input: &[u8], _finish: bool) -> UdsResult<Vec<u8>> {
+        Ok(self.apply_keystream(input))
+    }
+}
+
+impl EncryptionMethod for AesCtr {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn encrypt(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        Ok(self.cipher().apply_keystream(data))
+    }
+
+    fn decrypt(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        Ok(self.cipher().apply_keystream(data))
+    }
+
+    fn stream_encoder(&self) -> Option<Box<dyn StreamEncoder + '_>> {
+        Some(Box::new(self.cipher()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data() -> Vec<u8> {
+        let mut data = b"UDS flash data block ".repeat(50);
+        data.extend((0..=255u8).cycle().take(1000));
+        data.extend(vec![0xFF; 2000]);
+        data
+    }
+
+    #[test]
+    fn test_lzss_round_trip() {
+        let lzss = Lzss::new(1);
+        for data in [
+            Vec::new(),
+            vec![0x42],
+            b"abcabcabcabc".to_vec(),
+            sample_data(),
+        ] {
+            let compressed = lzss.compress(&data).unwrap();
+            assert_eq!(lzss.decompress(&compressed).unwrap(), data);
+        }
+
+        let data = sample_data();
+        assert!(lzss.compress(&data).unwrap().len() < data.len() / 2);
+    }
+
+    #[test]
+    fn test_lzss_known_encoding() {
+        // 3 个原文字节 + 1 个匹配（距离 3，长度 6）
+        let compressed = Lzss::new(1).compress(b"abcabcabc").unwrap();
+        assert_eq!(compressed, vec![0x07, b'a', b'b', b'c', 0x00, 0x23]);
+        assert!(Lzss::new(1).decompress(&[0x00, 0x00, 0x23]).is_err());
+    }
+
+    #[test]
+    fn test_lzma_round_trip() {
+        let lzma = Lzma::new(2);
+        let data = sample_data();
+        let compressed = lzma.compress(&data).unwrap();
+        assert_eq!(lzma.decompress(&compressed).unwrap(), data);
+        assert!(lzma.decompress(&[0x00, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_aes_ctr_known_vector() {
+        // NIST SP 800-38A F.5.1 CTR-AES128.Encrypt
+        let key = hex::decode("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+        let iv = hex::decode("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff").unwrap();
+        let plain = hex::decode("6bc1bee22e409f96e93d7e117393172a").unwrap();
+        let aes = AesCtr::new(1, &key, &iv).unwrap();
+        assert_eq!(
+            hex::encode(aes.encrypt(&plain).unwrap()),
+            "874d6191b620e3261bef6864990db6ce"
+        );
+        assert!(AesCtr::new(1, &key[..10], &iv).is_err());
+        assert!(AesCtr::new(1, &key, &iv[..8]).is_err());
+    }
+
+    #[test]
+    fn test_codec_identifier_and_round_trip() {
+        let encoding = TransferEncoding {
+            compression: Some(CompressionConfig::Lzss { id: 0x1 }),
+            encryption: Some(EncryptionConfig::AesCtr {
+                id: 0x3,
+                key: vec![0x11; 32],
+                iv: vec![0x22; 16],
+            }),
+        };
+        let codec = encoding.build().unwrap();
+        assert_eq!(codec.identifier(), 0x13);
+
+        let data = sample_data();
+        let encoded = codec.encode(&data).unwrap();
+        assert_ne!(encoded, data);
+        assert_eq!(codec.decode(&encoded).unwrap(), data);
+
+        let identity = TransferEncoding::default().build().unwrap();
+        assert!(identity.is_identity());
+        assert_eq!(identity.identifier(), 0x00);
+    }
+
+    #[test]
+    fn test_encode_stream_matches_encode() {
+        let mut data = sample_data();
+        data.extend((0..20_000u32).map(|i| (i * 7 % 251) as u8));
+
+        for encoding in [
+            TransferEncoding {
+                compression: Some(CompressionConfig::Lzss { id: 0x1 }),
+                encryption: Some(EncryptionConfig::AesCtr {
+                    id: 0x1,
+                    key: vec![0x11; 16],
+                    iv: vec![0x22; 16],
+                }),
+            },
+            // LZMA 不支持增量压缩，缓存全部输入后一次压缩
+            TransferEncoding {
+                compression: Some(CompressionConfig::Lzma { id: 0x2 }),
+                encryption: None,
+            },
+            TransferEncoding::default(),
+        ] {
+            let codec = encoding.build().unwrap();
+            let expected = codec.encode(&data).unwrap();
+            for max_len in [1, 7, 256, 4093, data.len() * 2] {
+                let mut stream = codec.encode_stream(&data);
+                let mut encoded = Vec::new();
+                while let Some(block) = stream.next_block(max_len).unwrap() {
+                    assert!(!block.is_empty() && block.len() <= max_len);
+                    encoded.extend_from_slice(&block);
+                    assert!(stream.progress() <= data.len());
+                }
+                assert_eq!(encoded, expected);
+                assert_eq!(stream.produced(), expected.len());
+                assert_eq!(stream.progress(), data.len());
+            }
+        }
+
+        // LZSS 只编码当前块所需的输入
+        let codec = TransferEncoding {
+            compression: Some(CompressionConfig::Lzss { id: 0x1 }),
+            encryption: None,
+        }
+        .build()
+        .unwrap();
+        let mut stream = codec.encode_stream(&data);
+        stream.next_block(256).unwrap().unwrap();
+        assert!(stream.progress() < data.len() / 4);
+    }
+}
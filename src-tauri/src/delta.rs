@@ -0,0 +1,481 @@
+/**
+ * 差分刷写
+ * 将新镜像与参考数据（上一版本镜像或从 ECU 读回的内容）按块比较，
+ * 只下载发生变化的块；引导程序支持时以差分补丁形式下载
+ */
+use crate::data_format::{CompressionMethod, DataCodec, Lzss, LzssEncoder, StreamEncoder};
+use crate::types::{
+    DownloadOptions, MemoryAccessOptions, MemoryRange, MemorySegment, UdsError, UdsResult,
+    UploadOptions,
+};
+use crate::uds_service::UdsService;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+
+/// 差分刷写选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeltaOptions {
+    pub block_size: u64,           // 比较粒度，通常取 Flash 扇区大小
+    pub merge_gap: u64,            // 相距不超过该字节数的变化块合并为一次下载
+    pub patch: Option<DeltaPatch>, // 引导程序支持差分补丁时设置
+}
+
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            merge_gap: 0,
+            patch: None,
+        }
+    }
+}
+
+/// 差分补丁格式：新数据与参考数据异或后 LZSS 压缩，
+/// 以 method_id 作为 dataFormatIdentifier 的压缩方法下载，由引导程序还原
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaPatch {
+    #[serde(default = "default_patch_method_id")]
+    pub method_id: u8,
+}
+
+fn default_patch_method_id() -> u8 {
+    0x3
+}
+
+/// 变化块
+#[derive(Debug, Clone)]
+pub struct DeltaBlock {
+    pub segment: MemorySegment,
+    pub reference: Option<Vec<u8>>, // 参考数据未覆盖该块时为 None
+}
+
+/// 差分刷写计划
+#[derive(Debug, Clone)]
+pub struct DeltaPlan {
+    pub blocks: Vec<DeltaBlock>,
+    pub total_bytes: usize,
+    pub patch: Option<DeltaPatch>,
+}
+
+impl DeltaPlan {
+    /// 需要下载的字节数
+    pub fn changed_bytes(&self) -> usize {
+        self.blocks.iter().map(|b| b.segment.data.len()).sum()
+    }
+
+    /// 需要擦除的范围：整块下载时为全部变化块，补丁模式下只擦除没有参考数据的块
+    pub fn erase_ranges(&self) -> Vec<MemoryRange> {
+        self.blocks
+            .iter()
+            .filter(|b| self.patch.is_none() || b.reference.is_none())
+            .map(|b| MemoryRange {
+                address: b.segment.address,
+                length: b.segment.data.len() as u64,
+            })
+            .collect()
+    }
+
+    /// 变化块的编解码器，补丁模式下用差分补丁替换压缩方法
+    pub fn block_codec(
+        &self,
+        block: &DeltaBlock,
+        options: &DownloadOptions,
+    ) -> UdsResult<DataCodec> {
+        let codec = options.encoding.build()?;
+        match (&self.patch, &block.reference) {
+            (Some(patch), Some(reference)) => {
+                Ok(codec
+                    .with_compression(Box::new(XorDelta::new(patch.method_id, reference.clone()))))
+            }
+            _ => Ok(codec),
+        }
+    }
+}
+
+/// 按块比较新镜像与参考数据，返回变化块
+pub fn compute_delta(
+    segments: &[MemorySegment],
+    reference: &[MemorySegment],
+    options: &DeltaOptions,
+) -> DeltaPlan {
+    let block_size = options.block_size.max(1);
+    let mut blocks = Vec::new();
+
+    for segment in segments {
+        let slice = |start: u64, end: u64| {
+            &segment.data[(start - segment.address) as usize..(end - segment.address) as usize]
+        };
+
+        // 块边界按绝对地址对齐
+        let mut changed: Vec<(u64, u64)> = Vec::new();
+        let mut start = segment.address;
+        while start < segment.end() {
+            let end = ((start / block_size + 1) * block_size).min(segment.end());
+            if reference_bytes(reference, start, end) != Some(slice(start, end)) {
+                match changed.last_mut() {
+                    Some(last) if start - last.1 <= options.merge_gap => last.1 = end,
+                    _ => changed.push((start, end)),
+                }
+            }
+            start = end;
+        }
+
+        blocks.extend(changed.into_iter().map(|(start, end)| DeltaBlock {
+            segment: MemorySegment::new(start, slice(start, end).to_vec()),
+            reference: reference_bytes(reference, start, end).map(|r| r.to_vec()),
+        }));
+    }
+
+    DeltaPlan {
+        blocks,
+        total_bytes: segments.iter().map(|s| s.data.len()).sum(),
+        patch: options.patch.clone(),
+    }
+}
+
+/// 按保存的下载块重建差分刷写计划，续传时使用，避免与已部分写入的 ECU 内容重新比较。
+/// reference 只需覆盖仍可作为补丁参考的块，未覆盖的块整块下载
+pub fn restore_delta(
+    segments: &[MemorySegment],
+    blocks: &[MemoryRange],
+    reference: &[MemorySegment],
+    options: &DeltaOptions,
+) -> UdsResult<DeltaPlan> {
+    let blocks = blocks
+        .iter()
+        .map(|block| {
+            let (start, end) = (block.address, block.address + block.length);
+            let data = reference_bytes(segments, start, end).ok_or_else(|| {
+                UdsError::InvalidParameter(format!(
+                    "Saved delta block 0x{:X}+0x{:X} is not covered by the flash data",
+                    block.address, block.length
+                ))
+            })?;
+            Ok(DeltaBlock {
+                segment: MemorySegment::new(start, data.to_vec()),
+                reference: reference_bytes(reference, start, end).map(|r| r.to_vec()),
+            })
+        })
+        .collect::<UdsResult<Vec<_>>>()?;
+
+    Ok(DeltaPlan {
+        blocks,
+        total_bytes: segments.iter().map(|s| s.data.len()).sum(),
+        patch: options.patch.clone(),
+    })
+}
+
+/// 参考数据中完整覆盖 [start, end) 的字节
+fn reference_bytes(reference: &[MemorySegment], start: u64, end: u64) -> Option<&[u8]> {
+    reference
+        .iter()
+        .find(|r| r.address <= start && end <= r.end())
+        .map(|r| &r.data[(start - r.address) as usize..(end - r.address) as usize])
+}
+
+pub type ReferenceFuture<'a> =
+    Pin<Box<dyn Future<Output = UdsResult<Vec<MemorySegment>>> + Send + 'a>>;
+
+/// 差分刷写参考数据来源
+pub trait ReferenceSource: Send + Sync {
+    /// 读取指定范围内的参考数据，可只返回其中部分区域
+    fn read<'a>(&'a self, service: &'a mut UdsService, range: MemoryRange) -> ReferenceFuture<'a>;
+}
+
+/// 以参考镜像（例如 ECU 当前软件版本的刷写文件）作为参考数据
+pub struct ImageReference {
+    segments: Vec<MemorySegment>,
+}
+
+impl ImageReference {
+    pub fn new(segments: Vec<MemorySegment>) -> Self {
+        Self { segments }
+    }
+}
+
+impl ReferenceSource for ImageReference {
+    fn read<'a>(&'a self, _service: &'a mut UdsService, range: MemoryRange) -> ReferenceFuture<'a> {
+        let end = range.address + range.length;
+        let segments = self
+            .segments
+            .iter()
+            .filter(|s| s.address < end && range.address < s.end())
+            .map(|s| {
+                let start = s.address.max(range.address);
+                let stop = s.end().min(end);
+                MemorySegment::new(
+                    start,
+                    s.data[(start - s.address) as usize..(stop - s.address) as usize].to_vec(),
+                )
+            })
+            .collect();
+        Box::pin(async move { Ok(segments) })
+    }
+}
+
+/// 从 ECU 读回参考数据的方式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum EcuReadMethod {
+    /// RequestUpload（0x35）+ TransferData + RequestTransferExit
+    Upload(UploadOptions),
+    /// ReadMemoryByAddress（0x23），按 ECU 最大响应长度分块
+    ReadMemory(MemoryAccessOptions),
+}
+
+/// 从 ECU 读回当前内存内容作为参考数据，需在允许读内存的会话和安全等级下使用
+pub struct EcuReference {
+    method: EcuReadMethod,
+}
+
+impl EcuReference {
+    pub fn new(method: EcuReadMethod) -> Self {
+        Self { method }
+    }
+}
+
+impl ReferenceSource for EcuReference {
+    fn read<'a>(&'a self, service: &'a mut UdsService, range: MemoryRange) -> ReferenceFuture<'a> {
+        Box::pin(async move {
+            let length = usize::try_from(range.length).map_err(|_| {
+                UdsError::InvalidParameter(format!(
+                    "Reference length {} is too large",
+                    range.length
+                ))
+            })?;
+            if length == 0 {
+                return Ok(Vec::new());
+            }
+            let data = match &self.method {
+                EcuReadMethod::Upload(options) => {
+                    // 参考数据读取不可中止，中止在后续下载的数据块前生效
+                    let abort = AtomicBool::new(false);
+                    service
+                        .upload_segment(range.address, length, options, &abort, |_| {})
+                        .await?
+                }
+                EcuReadMethod::ReadMemory(options) => {
+                    service.read_memory(range.address, length, options).await?
+                }
+            };
+            log::info!(
+                "Read {} reference bytes from ECU at 0x{:X}",
+                data.len(),
+                range.address
+            );
+            Ok(vec![MemorySegment::new(range.address, data)])
+        })
+    }
+}
+
+/// 差分补丁：与参考数据异或后 LZSS 压缩
+pub struct XorDelta {
+    id: u8,
+    reference: Vec<u8>,
+}
+
+impl XorDelta {
+    pub fn new(id: u8, reference: Vec<u8>) -> Self {
+        Self { id, reference }
+    }
+
+    fn xor(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        if data.len() != self.reference.len() {
+            return Err(UdsError::DataFormat(format!(
+                "Delta patch length {} does not match reference length {}",
+                data.len(),
+                self.reference.len()
+            )));
+        }
+        Ok(data
+            .iter()
+            .zip(&self.reference)
+            .map(|(a, b)| a ^ b)
+            .collect())
+    }
+}
+
+impl CompressionMethod for XorDelta {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn compress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        Lzss::new(self.id).compress(&self.xor(data)?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> UdsResult<Vec<u8>> {
+        self.xor(&Lzss::new(self.id).decompress(data)?)
+    }
+
+    fn stream_encoder(&self) -> Option<Box<dyn StreamEncoder + '_>> {
+        Some(Box::new(XorDeltaEncoder {
+            reference: &self.reference,
+            offset: 0,
+            lzss: LzssEncoder::new(),
+        }))
+    }
+}
+
+/// 差分补丁增量编码：逐块与参考数据对应位置异或后送入 LZSS
+struct XorDeltaEncoder<'a> {
+    reference: &'a [u8],
+    offset: usize,
+    lzss: LzssEncoder,
+}
+
+impl StreamEncoder for XorDeltaEncoder<'_> {
+    fn update(&mut self, input: &[u8], finish: bool) -> UdsResult<Vec<u8>> {
+        let end = self.offset + input.len();
+        if end > self.reference.len() || (finish && end != self.reference.len()) {
+            return Err(UdsError::DataFormat(format!(
+                "Delta patch length {} does not match reference length {}",
+                end,
+                self.reference.len()
+            )));
+        }
+        let xored: Vec<u8> = input
+            .iter()
+            .zip(&self.reference[self.offset..end])
+            .map(|(a, b)| a ^ b)
+            .collect();
+        self.offset = end;
+        self.lzss.update(&xored, finish)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecu_simulator::{fast_timing, memory_handler, sim_memory, EcuSimulator};
+
+    fn options(block_size: u64, merge_gap: u64) -> DeltaOptions {
+        DeltaOptions {
+            block_size,
+            merge_gap,
+            patch: None,
+        }
+    }
+
+    #[test]
+    fn test_compute_changed_blocks() {
+        let reference = vec![MemorySegment::new(0x1000, vec![0x11; 64])];
+        let mut data = vec![0x11; 64];
+        data[20] = 0x22;
+        data[60] = 0x22;
+        let segments = vec![MemorySegment::new(0x1000, data)];
+
+        let plan = compute_delta(&segments, &reference, &options(16, 0));
+        let ranges: Vec<(u64, usize)> = plan
+            .blocks
+            .iter()
+            .map(|b| (b.segment.address, b.segment.data.len()))
+            .collect();
+        assert_eq!(ranges, vec![(0x1010, 16), (0x1030, 16)]);
+        assert_eq!(plan.changed_bytes(), 32);
+        assert_eq!(plan.total_bytes, 64);
+        assert_eq!(plan.blocks[0].reference, Some(vec![0x11; 16]));
+
+        // 间隔一个块的变化块合并下载
+        let plan = compute_delta(&segments, &reference, &options(16, 16));
+        assert_eq!(plan.blocks.len(), 1);
+        assert_eq!(plan.blocks[0].segment.address, 0x1010);
+        assert_eq!(plan.blocks[0].segment.data.len(), 48);
+    }
+
+    #[test]
+    fn test_uncovered_and_unaligned_blocks() {
+        // 参考数据只覆盖前半部分，新段起始地址未按块对齐，相邻变化块合并
+        let reference = vec![MemorySegment::new(0x1000, vec![0xAA; 0x20])];
+        let segments = vec![MemorySegment::new(0x1008, vec![0xAA; 0x30])];
+
+        let plan = compute_delta(&segments, &reference, &options(16, 0));
+        let ranges: Vec<(u64, usize, bool)> = plan
+            .blocks
+            .iter()
+            .map(|b| {
+                (
+                    b.segment.address,
+                    b.segment.data.len(),
+                    b.reference.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(ranges, vec![(0x1020, 24, false)]);
+
+        let mut plan = plan;
+        plan.patch = Some(DeltaPatch { method_id: 0x3 });
+        assert_eq!(
+            plan.erase_ranges(),
+            vec![MemoryRange {
+                address: 0x1020,
+                length: 24
+            }]
+        );
+    }
+
+    #[test]
+    fn test_xor_delta_round_trip() {
+        let reference: Vec<u8> = (0..2048u32).map(|i| (i * 7) as u8).collect();
+        let mut data = reference.clone();
+        data[100..110].copy_from_slice(&[0xEE; 10]);
+
+        let patch = XorDelta::new(0x3, reference);
+        let encoded = patch.compress(&data).unwrap();
+        assert!(encoded.len() < 300);
+        assert_eq!(patch.decompress(&encoded).unwrap(), data);
+        assert!(patch.compress(&data[..10]).is_err());
+
+        // 逐块编码与一次编码结果相同
+        let codec = DataCodec::new().with_compression(Box::new(patch));
+        let mut stream = codec.encode_stream(&data);
+        let mut streamed = Vec::new();
+        while let Some(block) = stream.next_block(64).unwrap() {
+            streamed.extend_from_slice(&block);
+        }
+        assert_eq!(streamed, encoded);
+        let mut short = codec.encode_stream(&data[..10]);
+        assert!(short.next_block(64).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ecu_reference() {
+        let memory = MemorySegment::new(0x8000, (0..0x800u32).map(|i| (i * 3) as u8).collect());
+        let mut data = memory.data[0x100..0x500].to_vec();
+        data[0x180] ^= 0xFF;
+        let segments = vec![MemorySegment::new(0x8100, data)];
+
+        for method in [
+            EcuReadMethod::Upload(UploadOptions::default()),
+            EcuReadMethod::ReadMemory(MemoryAccessOptions {
+                max_response_length: 0x101,
+                ..Default::default()
+            }),
+        ] {
+            let sim =
+                EcuSimulator::start(memory_handler(sim_memory(memory.clone()), usize::MAX)).await;
+            let mut service = sim.connect(fast_timing()).await;
+            let source = EcuReference::new(method);
+
+            let range = MemoryRange {
+                address: 0x8100,
+                length: 0x400,
+            };
+            let reference = source.read(&mut service, range).await.unwrap();
+            assert_eq!(
+                reference,
+                vec![MemorySegment::new(
+                    0x8100,
+                    memory.data[0x100..0x500].to_vec()
+                )]
+            );
+
+            let plan = compute_delta(&segments, &reference, &options(0x100, 0));
+            assert_eq!(plan.blocks.len(), 1);
+            assert_eq!(plan.blocks[0].segment.address, 0x8200);
+        }
+    }
+}
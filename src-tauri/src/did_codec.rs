@@ -0,0 +1,306 @@
+/**
+ * DID 数据编解码
+ * 按 DID 定义在物理值与数据记录字节之间转换，物理值 = 原始值 × scale + offset
+ */
+use crate::types::{UdsError, UdsResult};
+use crate::utils::bytes_to_hex;
+use serde::{Deserialize, Serialize};
+
+/// DID 数据定义
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DidDefinition {
+    pub did: u16,
+    #[serde(default)]
+    pub name: String,
+    pub encoding: DidEncoding,
+}
+
+/// 数据记录编码方式
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DidEncoding {
+    /// 大端无符号整数
+    Unsigned {
+        length: usize,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// 大端有符号整数（补码）
+    Signed {
+        length: usize,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// ASCII 文本，不足长度时以空格补齐
+    Ascii { length: usize },
+    /// 压缩 BCD，每字节两位十进制数字
+    Bcd { length: usize },
+    /// 原始字节，以十六进制字符串表示
+    Raw { length: Option<usize> },
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// 物理值：数值或文本
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PhysicalValue {
+    Number(f64),
+    Text(String),
+}
+
+impl DidDefinition {
+    /// 数据记录字节数，原始字节未指定长度时返回 None
+    pub fn length(&self) -> Option<usize> {
+        match self.encoding {
+            DidEncoding::Unsigned { length, .. }
+            | DidEncoding::Signed { length, .. }
+            | DidEncoding::Ascii { length }
+            | DidEncoding::Bcd { length } => Some(length),
+            DidEncoding::Raw { length } => length,
+        }
+    }
+
+    /// 物理值编码为数据记录
+    pub fn encode(&self, value: &PhysicalValue) -> UdsResult<Vec<u8>> {
+        match (&self.encoding, value) {
+            (
+                DidEncoding::Unsigned {
+                    length,
+                    scale,
+                    offset,
+                },
+                PhysicalValue::Number(physical),
+            ) => {
+                let raw = self.raw_value(*physical, *scale, *offset)?;
+                if *length == 0 || *length > 8 {
+                    return Err(self.out_of_range(*physical));
+                }
+                let max = (u64::MAX >> (64 - length * 8)) as f64;
+                if raw < 0.0 || raw > max {
+                    return Err(self.out_of_range(*physical));
+                }
+                Ok((raw as u64).to_be_bytes()[8 - length..].to_vec())
+            }
+            (
+                DidEncoding::Signed {
+                    length,
+                    scale,
+                    offset,
+                },
+                PhysicalValue::Number(physical),
+            ) => {
+                let raw = self.raw_value(*physical, *scale, *offset)?;
+                if *length == 0 || *length > 8 {
+                    return Err(self.out_of_range(*physical));
+                }
+                let bits = length * 8 - 1;
+                let (min, max) = (-((1u128 << bits) as f64), ((1u128 << bits) - 1) as f64);
+                if raw < min || raw > max {
+                    return Err(self.out_of_range(*physical));
+                }
+                Ok((raw as i64).to_be_bytes()[8 - length..].to_vec())
+            }
+            (DidEncoding::Ascii { length }, PhysicalValue::Text(text)) => {
+                if !text.is_ascii() || text.len() > *length {
+                    return Err(UdsError::InvalidParameter(format!(
+                        "DID 0x{:04X} expects at most {} ASCII characters, got '{}'",
+                        self.did, length, text
+                    )));
+                }
+                let mut data = text.as_bytes().to_vec();
+                data.resize(*length, b' ');
+                Ok(data)
+            }
+            (DidEncoding::Bcd { length }, PhysicalValue::Text(text)) => {
+                if !text.bytes().all(|b| b.is_ascii_digit()) || text.len() > length * 2 {
+                    return Err(UdsError::InvalidParameter(format!(
+                        "DID 0x{:04X} expects at most {} decimal digits, got '{}'",
+                        self.did,
+                        length * 2,
+                        text
+                    )));
+                }
+                let digits = format!("{:0>width$}", text, width = length * 2);
+                Ok(digits
+                    .as_bytes()
+                    .chunks(2)
+                    .map(|pair| ((pair[0] - b'0') << 4) | (pair[1] - b'0'))
+                    .collect())
+            }
+            (DidEncoding::Raw { length }, PhysicalValue::Text(text)) => {
+                let data = hex::decode(text.replace(' ', "")).map_err(|e| {
+                    UdsError::InvalidParameter(format!(
+                        "Invalid hex for DID 0x{:04X}: {}",
+                        self.did, e
+                    ))
+                })?;
+                match length {
+                    Some(length) if *length != data.len() => {
+                        Err(UdsError::InvalidParameter(format!(
+                            "DID 0x{:04X} expects {} bytes, got {}",
+                            self.did,
+                            length,
+                            data.len()
+                        )))
+                    }
+                    _ => Ok(data),
+                }
+            }
+            _ => Err(UdsError::InvalidParameter(format!(
+                "Value {:?} does not match the encoding of DID 0x{:04X}",
+                value, self.did
+            ))),
+        }
+    }
+
+    /// 数据记录解码为物理值
+    pub fn decode(&self, data: &[u8]) -> UdsResult<PhysicalValue> {
+        if let Some(length) = self.length() {
+            if data.len() != length {
+                return Err(UdsError::DataFormat(format!(
+                    "DID 0x{:04X} expects {} bytes, got {}",
+                    self.did,
+                    length,
+                    data.len()
+                )));
+            }
+        }
+        let numeric = matches!(
+            self.encoding,
+            DidEncoding::Unsigned { .. } | DidEncoding::Signed { .. }
+        );
+        if numeric && !(1..=8).contains(&data.len()) {
+            return Err(UdsError::DataFormat(format!(
+                "DID 0x{:04X} numeric value must be 1 to 8 bytes",
+                self.did
+            )));
+        }
+        let value = match &self.encoding {
+            DidEncoding::Unsigned { scale, offset, .. } => {
+                let raw = data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+                PhysicalValue::Number(raw as f64 * scale + offset)
+            }
+            DidEncoding::Signed { scale, offset, .. } => {
+                let raw = data.iter().fold(0i64, |acc, &b| (acc << 8) | b as i64);
+                // 按数据长度做符号扩展
+                let shift = 64 - data.len() * 8;
+                PhysicalValue::Number(((raw << shift) >> shift) as f64 * scale + offset)
+            }
+            DidEncoding::Ascii { .. } => {
+                PhysicalValue::Text(String::from_utf8_lossy(data).trim_end().to_string())
+            }
+            DidEncoding::Bcd { .. } => {
+                if let Some(b) = data.iter().find(|&&b| b >> 4 > 9 || b & 0x0F > 9) {
+                    return Err(UdsError::DataFormat(format!(
+                        "DID 0x{:04X} contains invalid BCD byte 0x{:02X}",
+                        self.did, b
+                    )));
+                }
+                PhysicalValue::Text(
+                    data.iter()
+                        .map(|b| format!("{}{}", b >> 4, b & 0x0F))
+                        .collect(),
+                )
+            }
+            DidEncoding::Raw { .. } => PhysicalValue::Text(bytes_to_hex(data)),
+        };
+        Ok(value)
+    }
+
+    /// 物理值换算为原始值，四舍五入到整数
+    fn raw_value(&self, physical: f64, scale: f64, offset: f64) -> UdsResult<f64> {
+        if scale == 0.0 || !physical.is_finite() {
+            return Err(self.out_of_range(physical));
+        }
+        Ok(((physical - offset) / scale).round())
+    }
+
+    fn out_of_range(&self, physical: f64) -> UdsError {
+        UdsError::InvalidParameter(format!(
+            "Value {} is out of range for DID 0x{:04X}",
+            physical, self.did
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(encoding: DidEncoding) -> DidDefinition {
+        DidDefinition {
+            did: 0x0101,
+            name: String::new(),
+            encoding,
+        }
+    }
+
+    #[test]
+    fn test_numeric_round_trip() {
+        // 冷却液温度：1 字节，0.5 °C/bit，偏移 -40
+        let temperature = definition(DidEncoding::Unsigned {
+            length: 1,
+            scale: 0.5,
+            offset: -40.0,
+        });
+        let data = temperature.encode(&PhysicalValue::Number(87.5)).unwrap();
+        assert_eq!(data, vec![0xFF]);
+        assert_eq!(
+            temperature.decode(&data).unwrap(),
+            PhysicalValue::Number(87.5)
+        );
+        assert!(temperature.encode(&PhysicalValue::Number(88.0)).is_err());
+        assert!(temperature.encode(&PhysicalValue::Number(-41.0)).is_err());
+
+        let torque = definition(DidEncoding::Signed {
+            length: 2,
+            scale: 0.1,
+            offset: 0.0,
+        });
+        let data = torque.encode(&PhysicalValue::Number(-12.5)).unwrap();
+        assert_eq!(data, vec![0xFF, 0x83]);
+        assert_eq!(torque.decode(&data).unwrap(), PhysicalValue::Number(-12.5));
+        assert!(torque.encode(&PhysicalValue::Number(3276.8)).is_err());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let name = definition(DidEncoding::Ascii { length: 8 });
+        let data = name.encode(&PhysicalValue::Text("ECU1".into())).unwrap();
+        assert_eq!(data, b"ECU1    ".to_vec());
+        assert_eq!(
+            name.decode(&data).unwrap(),
+            PhysicalValue::Text("ECU1".into())
+        );
+
+        let date = definition(DidEncoding::Bcd { length: 3 });
+        let data = date.encode(&PhysicalValue::Text("41018".into())).unwrap();
+        assert_eq!(data, vec![0x04, 0x10, 0x18]);
+        assert_eq!(
+            date.decode(&data).unwrap(),
+            PhysicalValue::Text("041018".into())
+        );
+        assert!(matches!(
+            date.decode(&[0x04, 0x1A, 0x18]),
+            Err(UdsError::DataFormat(_))
+        ));
+        assert!(matches!(
+            date.decode(&[0xF4, 0x10, 0x18]),
+            Err(UdsError::DataFormat(_))
+        ));
+
+        // 非 UTF-8 字节原样写入
+        let raw = definition(DidEncoding::Raw { length: Some(3) });
+        let data = raw.encode(&PhysicalValue::Text("FF 80 00".into())).unwrap();
+        assert_eq!(data, vec![0xFF, 0x80, 0x00]);
+        assert!(raw.encode(&PhysicalValue::Text("FF".into())).is_err());
+        assert!(name.encode(&PhysicalValue::Number(1.0)).is_err());
+    }
+}
@@ -2,18 +2,66 @@
  * DoIP 客户端 - Rust 实现
  * 用于与 ECU 进行 DoIP 通信，使用 TCP 连接替代 WebSocket
  */
+use crate::traffic::{decode_frame_parts, decode_frames, TrafficDirection, TrafficSink};
 use crate::types::{DoipClientConfig, DoipError, Result};
+use crate::uds_response::DOIP_HEADER_LEN;
 use crate::utils::{get_timestamp, print_hex};
+use bytes::Buf;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 
+/// 接收任务收到完整帧后先交给路由函数，返回 true 表示已处理（例如周期数据），
+/// 不再交给等待响应的调用方
+pub type FrameRouter = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
 pub struct DoipClient {
     config: DoipClientConfig,
-    stream: Option<TcpStream>,
-    is_connected: bool,
+    writer: Option<OwnedWriteHalf>,
+    frames: Option<mpsc::UnboundedReceiver<Result<Vec<u8>>>>, // 接收任务交给调用方的帧
+    reader: Option<JoinHandle<()>>,
+    is_connected: Arc<AtomicBool>, // 接收任务读到连接关闭或读取失败时清除
+    router: Arc<Mutex<Option<FrameRouter>>>,
+    traffic_sink: Option<TrafficSink>,
+    log_sampler: LogSampler,
+}
+
+/// 收发日志和报文事件采样：每 interval 帧记录一次，interval 为 1 时逐帧记录
+#[derive(Debug)]
+struct LogSampler {
+    interval: u64,
+    count: u64,
+    sampled: bool, // 最近一次发送是否被记录，对应的接收帧随之记录
+}
+
+impl LogSampler {
+    fn new() -> Self {
+        Self {
+            interval: 1,
+            count: 0,
+            sampled: true,
+        }
+    }
+
+    fn set_interval(&mut self, interval: u64) {
+        self.interval = interval.max(1);
+        self.count = 0;
+        self.sampled = true;
+    }
+
+    /// 本帧是否需要记录日志
+    fn sample(&mut self) -> bool {
+        self.count += 1;
+        self.sampled = self.interval == 1 || self.count % self.interval == 1;
+        self.sampled
+    }
 }
 
 impl DoipClient {
@@ -21,12 +69,32 @@ impl DoipClient {
     pub fn new(config: DoipClientConfig) -> Self {
         Self {
             config,
-            stream: None,
-            is_connected: false,
+            writer: None,
+            frames: None,
+            reader: None,
+            is_connected: Arc::new(AtomicBool::new(false)),
+            router: Arc::new(Mutex::new(None)),
+            traffic_sink: None,
+            log_sampler: LogSampler::new(),
         }
     }
 
-    /// 连接到 DoIP 服务
+    /// 设置报文事件接收端
+    pub fn set_traffic_sink(&mut self, sink: TrafficSink) {
+        self.traffic_sink = Some(sink);
+    }
+
+    /// 设置帧路由函数，连接前后均可设置
+    pub fn set_frame_router(&mut self, router: FrameRouter) {
+        *self.router.lock().unwrap() = Some(router);
+    }
+
+    /// 设置收发日志和报文事件的采样间隔，批量传输时避免逐帧格式化日志和推送事件
+    pub fn set_log_sample_interval(&mut self, interval: u64) {
+        self.log_sampler.set_interval(interval);
+    }
+
+    /// 连接到 DoIP 服务，连接后由后台任务持有读半部持续接收
     pub async fn connect(&mut self) -> Result<bool> {
         let addr = format!("{}:{}", self.config.ip_address, self.config.port);
         let socket_addr: SocketAddr = addr
@@ -37,8 +105,18 @@ impl DoipClient {
 
         match timeout(timeout_duration, TcpStream::connect(socket_addr)).await {
             Ok(Ok(stream)) => {
-                self.stream = Some(stream);
-                self.is_connected = true;
+                let (read_half, write_half) = stream.into_split();
+                let (tx, rx) = mpsc::unbounded_channel();
+                self.is_connected.store(true, Ordering::SeqCst);
+                self.reader = Some(tokio::spawn(read_frames(
+                    read_half,
+                    tx,
+                    self.router.clone(),
+                    self.traffic_sink.clone(),
+                    self.is_connected.clone(),
+                )));
+                self.writer = Some(write_half);
+                self.frames = Some(rx);
                 self.log(
                     "info",
                     &format!(
@@ -61,122 +139,105 @@ impl DoipClient {
 
     /// 发送数据
     pub async fn send(&mut self, data: &[u8]) -> Result<bool> {
-        if !self.is_connected || self.stream.is_none() {
+        self.send_parts(data, &[]).await
+    }
+
+    /// 将帧头和数据作为一帧发送，两部分以向量写发出，不拼接复制
+    pub async fn send_parts(&mut self, head: &[u8], body: &[u8]) -> Result<bool> {
+        if !self.is_socket_connected() {
             return Err(DoipError::NotConnected);
         }
 
-        let stream = self.stream.as_mut().unwrap();
+        let writer = self.writer.as_mut().unwrap();
+        let len = head.len() + body.len();
 
-        match stream.write_all(data).await {
+        match writer.write_all_buf(&mut Buf::chain(head, body)).await {
             Ok(_) => {
-                self.log("debug", &format!("Sent {} bytes", data.len()));
-
-                if data.len() < 256 {
-                    print_hex(data, 32);
+                if self.log_sampler.sample() {
+                    if body.is_empty() {
+                        self.emit_traffic(TrafficDirection::Tx, head);
+                    } else if let Some(sink) = &self.traffic_sink {
+                        // 数据块只解码开头部分，事件中的原始数据被截断
+                        sink(decode_frame_parts(TrafficDirection::Tx, head, body));
+                    }
+
+                    self.log("debug", &format!("Sent {} bytes", len));
+                    if len < 256 {
+                        print_hex(&[head, body].concat(), 32);
+                    }
                 }
 
                 Ok(true)
             }
             Err(e) => {
+                // 写入失败后连接不可再用
                 self.log("error", &format!("Send failed: {}", e));
+                self.is_connected.store(false, Ordering::SeqCst);
                 Err(DoipError::SendFailed(e.to_string()))
             }
         }
     }
 
-    /// 接收数据
-    pub async fn receive(&mut self) -> Result<Vec<u8>> {
-        if !self.is_connected || self.stream.is_none() {
-            return Err(DoipError::NotConnected);
-        }
-
-        let stream = self.stream.as_mut().unwrap();
+    /// 接收一个完整的 DoIP 帧（头部 + 负载）
+    pub async fn receive_frame(&mut self) -> Result<Vec<u8>> {
         let timeout_duration = Duration::from_millis(self.config.timeout.unwrap_or(30000));
+        self.receive_frame_timeout(timeout_duration).await
+    }
 
-        let mut buffer = vec![0u8; 4096]; // 4KB 缓冲区
-
-        match timeout(timeout_duration, stream.read(&mut buffer)).await {
-            Ok(Ok(n)) if n > 0 => {
-                buffer.truncate(n);
-                self.log("debug", &format!("Received {} bytes", n));
-
-                if n < 256 {
-                    print_hex(&buffer, 32);
-                }
-
-                Ok(buffer)
-            }
-            Ok(Ok(0)) => {
-                self.log("info", "Connection closed by peer");
-                self.is_connected = false;
-                Err(DoipError::ConnectionFailed(
-                    "Connection closed by peer".to_string(),
-                ))
-            }
-            Ok(Ok(_)) => {
-                // 处理其他大小的读取
-                self.log("debug", &format!("Received {} bytes", buffer.len()));
-                Ok(buffer)
-            }
-            Ok(Err(e)) => {
-                self.log("error", &format!("Receive failed: {}", e));
-                Err(DoipError::ReceiveFailed(e.to_string()))
-            }
-            Err(_) => {
-                self.log("error", "Receive timeout");
+    /// 在指定时间内接收一个完整的 DoIP 帧
+    pub async fn receive_frame_timeout(&mut self, timeout_duration: Duration) -> Result<Vec<u8>> {
+        match self.poll_frame(timeout_duration).await? {
+            Some(frame) => Ok(frame),
+            None => {
+                self.log("error", "Receive frame timeout");
                 Err(DoipError::Timeout)
             }
         }
     }
 
-    /// 接收指定长度的数据
-    pub async fn receive_exact(&mut self, len: usize) -> Result<Vec<u8>> {
-        if !self.is_connected || self.stream.is_none() {
+    /// 在指定时间内等待接收任务交来的一帧，超时返回 None 且不记录错误。
+    /// 连接断开前已收到的帧仍会依次返回，之后返回断开原因
+    pub async fn poll_frame(&mut self, timeout_duration: Duration) -> Result<Option<Vec<u8>>> {
+        let Some(frames) = self.frames.as_mut() else {
             return Err(DoipError::NotConnected);
-        }
-
-        let stream = self.stream.as_mut().unwrap();
-        let timeout_duration = Duration::from_millis(self.config.timeout.unwrap_or(30000));
-
-        let mut buffer = vec![0u8; len];
-
-        match timeout(timeout_duration, stream.read_exact(&mut buffer)).await {
-            Ok(Ok(_)) => {
-                self.log("debug", &format!("Received exactly {} bytes", len));
+        };
 
-                if len < 256 {
-                    print_hex(&buffer, 32);
+        match timeout(timeout_duration, frames.recv()).await {
+            Ok(Some(Ok(frame))) => {
+                // 未采样的发送对应的响应只推送否定响应
+                let negative = frame.get(DOIP_HEADER_LEN + 4) == Some(&0x7F);
+                if self.log_sampler.sampled || negative {
+                    self.log("debug", &format!("Received frame of {} bytes", frame.len()));
+                    self.emit_traffic(TrafficDirection::Rx, &frame);
                 }
-
-                Ok(buffer)
-            }
-            Ok(Err(e)) => {
-                self.log("error", &format!("Receive exact failed: {}", e));
-                Err(DoipError::ReceiveFailed(e.to_string()))
-            }
-            Err(_) => {
-                self.log("error", "Receive exact timeout");
-                Err(DoipError::Timeout)
+                Ok(Some(frame))
             }
+            Ok(Some(Err(e))) => Err(e),
+            Ok(None) => Err(DoipError::NotConnected),
+            Err(_) => Ok(None),
         }
     }
 
     /// 断开连接
     pub async fn disconnect(&mut self) -> Result<bool> {
-        if let Some(mut stream) = self.stream.take() {
-            if let Err(e) = stream.shutdown().await {
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.shutdown().await {
                 self.log("error", &format!("Shutdown failed: {}", e));
             }
         }
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
 
-        self.is_connected = false;
+        self.frames = None;
+        self.is_connected.store(false, Ordering::SeqCst);
         self.log("info", "Connection closed");
         Ok(true)
     }
 
     /// 检查连接状态
     pub fn is_socket_connected(&self) -> bool {
-        self.is_connected && self.stream.is_some()
+        self.is_connected.load(Ordering::SeqCst) && self.writer.is_some()
     }
 
     /// 获取配置
@@ -189,28 +250,129 @@ impl DoipClient {
         self.config.timeout = Some(timeout_ms);
     }
 
+    /// 推送报文事件
+    fn emit_traffic(&self, direction: TrafficDirection, data: &[u8]) {
+        emit_traffic(&self.traffic_sink, direction, data);
+    }
+
     /// 日志记录
     fn log(&self, level: &str, message: &str) {
-        let timestamp = get_timestamp();
-        match level {
-            "info" => log::info!("[{}] [DOIP] {}", timestamp, message),
-            "debug" => log::debug!("[{}] [DOIP] {}", timestamp, message),
-            "error" => log::error!("[{}] [DOIP] {}", timestamp, message),
-            _ => log::info!("[{}] [DOIP] {}", timestamp, message),
-        }
+        log_doip(level, message);
     }
 }
 
 impl Drop for DoipClient {
     fn drop(&mut self) {
-        if self.is_connected {
+        // 读半部由接收任务持有，须中止任务才能释放连接
+        if let Some(reader) = self.reader.take() {
+            reader.abort();
+        }
+        if self.is_connected.swap(false, Ordering::SeqCst) {
             self.log("info", "DoipClient dropped, cleaning up connection");
-            // 注意：在 Drop 中不能使用 async，所以这里只是标记状态
-            self.is_connected = false;
         }
     }
 }
 
+/// 接收任务：持续读取并拆分 DoIP 帧，路由函数未处理的帧交给调用方。
+/// 连接关闭或读取失败时把错误交给调用方后结束
+async fn read_frames(
+    mut reader: OwnedReadHalf,
+    frames: mpsc::UnboundedSender<Result<Vec<u8>>>,
+    router: Arc<Mutex<Option<FrameRouter>>>,
+    traffic_sink: Option<TrafficSink>,
+    is_connected: Arc<AtomicBool>,
+) {
+    let mut rx_buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let error = match reader.read(&mut chunk).await {
+            Ok(0) => {
+                log_doip("info", "Connection closed by peer");
+                DoipError::ConnectionFailed("Connection closed by peer".to_string())
+            }
+            Ok(n) => {
+                rx_buffer.extend_from_slice(&chunk[..n]);
+                loop {
+                    let frame = match take_buffered_frame(&mut rx_buffer) {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => break,
+                        Err(e) => {
+                            log_doip("error", &e.to_string());
+                            if frames.send(Err(e)).is_err() {
+                                return;
+                            }
+                            break;
+                        }
+                    };
+                    let route = router.lock().unwrap().clone();
+                    if route.is_some_and(|route| route(&frame)) {
+                        emit_traffic(&traffic_sink, TrafficDirection::Rx, &frame);
+                        continue;
+                    }
+                    if frames.send(Ok(frame)).is_err() {
+                        return;
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                log_doip("error", &format!("Receive failed: {}", e));
+                DoipError::ReceiveFailed(e.to_string())
+            }
+        };
+        is_connected.store(false, Ordering::SeqCst);
+        let _ = frames.send(Err(error));
+        return;
+    }
+}
+
+/// 从缓冲区取出一个完整帧，数据不足时返回 None
+fn take_buffered_frame(rx_buffer: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    if rx_buffer.len() < DOIP_HEADER_LEN {
+        return Ok(None);
+    }
+
+    if rx_buffer[0] != !rx_buffer[1] {
+        let header = format!("{:02X?}", &rx_buffer[..DOIP_HEADER_LEN]);
+        rx_buffer.clear();
+        return Err(DoipError::ProtocolError(format!(
+            "Invalid DoIP header: {}",
+            header
+        )));
+    }
+
+    let payload_len =
+        u32::from_be_bytes([rx_buffer[4], rx_buffer[5], rx_buffer[6], rx_buffer[7]]) as usize;
+    let frame_len = DOIP_HEADER_LEN + payload_len;
+
+    if rx_buffer.len() < frame_len {
+        return Ok(None);
+    }
+
+    let rest = rx_buffer.split_off(frame_len);
+    Ok(Some(std::mem::replace(rx_buffer, rest)))
+}
+
+/// 推送报文事件
+fn emit_traffic(sink: &Option<TrafficSink>, direction: TrafficDirection, data: &[u8]) {
+    if let Some(sink) = sink {
+        for event in decode_frames(direction, data) {
+            sink(event);
+        }
+    }
+}
+
+/// 日志记录
+fn log_doip(level: &str, message: &str) {
+    let timestamp = get_timestamp();
+    match level {
+        "info" => log::info!("[{}] [DOIP] {}", timestamp, message),
+        "debug" => log::debug!("[{}] [DOIP] {}", timestamp, message),
+        "error" => log::error!("[{}] [DOIP] {}", timestamp, message),
+        _ => log::info!("[{}] [DOIP] {}", timestamp, message),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,4 +403,18 @@ mod tests {
         let result = client.connect().await;
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_log_sampling() {
+        let mut sampler = LogSampler::new();
+        assert!((0..3).all(|_| sampler.sample()));
+
+        sampler.set_interval(4);
+        let logged: Vec<bool> = (0..9).map(|_| sampler.sample()).collect();
+        assert_eq!(
+            logged,
+            vec![true, false, false, false, true, false, false, false, true]
+        );
+        assert!(sampler.sampled);
+    }
 }
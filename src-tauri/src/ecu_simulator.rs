@@ -0,0 +1,297 @@
+/**
+ * ECU 模拟器（仅用于测试）
+ * 在本地端口上模拟 DoIP 实体，应答路由激活并按脚本应答诊断请求
+ */
+use crate::types::{ConnectionConfig, MemorySegment, UdsTimingConfig};
+use crate::uds_service::UdsService;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// 模拟 ECU 的逻辑地址
+pub const ECU_ADDRESS: u16 = 0x1001;
+
+/// 测试仪逻辑地址
+pub const TESTER_ADDRESS: u16 = 0x0E80;
+
+/// 模拟器收到诊断请求后执行的动作
+#[derive(Debug, Clone)]
+pub enum SimAction {
+    /// 从 ECU 地址发送 UDS 响应
+    Respond(Vec<u8>),
+    /// 从指定源地址发送 UDS 响应
+    RespondFrom(u16, Vec<u8>),
+    /// 等待指定毫秒数
+    Delay(u64),
+}
+
+type Handler = Box<dyn FnMut(&[u8]) -> Vec<SimAction> + Send>;
+
+/// 本地 DoIP ECU 模拟器
+pub struct EcuSimulator {
+    port: u16,
+    task: JoinHandle<()>,
+}
+
+impl EcuSimulator {
+    /// 启动模拟器，handler 根据 UDS 请求返回要执行的动作
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Vec<SimAction> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handler: Arc<Mutex<Handler>> = Arc::new(Mutex::new(Box::new(handler)));
+        let shared_writer = Arc::new(tokio::sync::Mutex::new(None));
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                // 确认帧与响应分两次写出，关闭 Nagle 避免延迟
+                let _ = stream.set_nodelay(true);
+                let (mut reader, write_half) = stream.into_split();
+                *shared_writer.lock().await = Some(write_half);
+
+                loop {
+                    let mut header = [0u8; 8];
+                    if reader.read_exact(&mut header).await.is_err() {
+                        break;
+                    }
+                    let len =
+                        u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+                    let mut payload = vec![0u8; len];
+                    if reader.read_exact(&mut payload).await.is_err() {
+                        break;
+                    }
+
+                    match u16::from_be_bytes([header[2], header[3]]) {
+                        // 路由激活请求 -> 激活成功
+                        0x0005 => {
+                            let mut response = Vec::new();
+                            response.extend_from_slice(&TESTER_ADDRESS.to_be_bytes());
+                            response.extend_from_slice(&ECU_ADDRESS.to_be_bytes());
+                            response.extend_from_slice(&[0x10, 0x00, 0x00, 0x00, 0x00]);
+                            write_frame(&shared_writer, 0x0006, &response).await;
+                        }
+                        // 诊断报文 -> 确认 + 脚本动作
+                        0x8001 => {
+                            let mut ack = Vec::new();
+                            ack.extend_from_slice(&ECU_ADDRESS.to_be_bytes());
+                            ack.extend_from_slice(&TESTER_ADDRESS.to_be_bytes());
+                            ack.push(0x00);
+                            write_frame(&shared_writer, 0x8002, &ack).await;
+
+                            let actions = (handler.lock().unwrap())(&payload[4..]);
+                            for action in actions {
+                                match action {
+                                    SimAction::Respond(uds) => {
+                                        send_uds(&shared_writer, ECU_ADDRESS, &uds).await
+                                    }
+                                    SimAction::RespondFrom(source, uds) => {
+                                        send_uds(&shared_writer, source, &uds).await
+                                    }
+                                    SimAction::Delay(ms) => {
+                                        tokio::time::sleep(Duration::from_millis(ms)).await
+                                    }
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        });
+
+        Self { port, task }
+    }
+
+    /// 模拟器的连接配置
+    pub fn connection_config(&self, timing: UdsTimingConfig) -> ConnectionConfig {
+        ConnectionConfig {
+            ip_address: "127.0.0.1".to_string(),
+            port: self.port,
+            server_address: format!("{:04X}", ECU_ADDRESS),
+            client_address: format!("{:04X}", TESTER_ADDRESS),
+            timeout: Some(2000),
+            timing: Some(timing),
+        }
+    }
+
+    /// 连接模拟器并完成路由激活
+    pub async fn connect(&self, timing: UdsTimingConfig) -> UdsService {
+        UdsService::connect(&self.connection_config(timing), None)
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for EcuSimulator {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// 测试用的短时间参数
+pub fn fast_timing() -> UdsTimingConfig {
+    UdsTimingConfig {
+        p2_client_ms: 200,
+        p2_star_client_ms: 500,
+        busy_repeat_delay_ms: 10,
+        ..UdsTimingConfig::default()
+    }
+}
+
+/// 模拟器收到的 UDS 请求记录
+pub type RequestLog = Arc<Mutex<Vec<Vec<u8>>>>;
+
+/// 空的请求记录
+pub fn request_log() -> RequestLog {
+    Arc::new(Mutex::new(Vec::new()))
+}
+
+/// 记录每个请求后交给 handler 应答
+pub fn recording<F>(
+    requests: RequestLog,
+    mut handler: F,
+) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static
+where
+    F: FnMut(&[u8]) -> Vec<SimAction> + Send + 'static,
+{
+    move |request| {
+        requests.lock().unwrap().push(request.to_vec());
+        handler(request)
+    }
+}
+
+/// 刷写相关请求的正响应：会话、种子、例程、请求下载、传输数据，其余服务回显子功能
+pub fn flash_response(request: &[u8]) -> Vec<u8> {
+    match request {
+        [0x10, session, ..] => vec![0x50, *session, 0x00, 0x32, 0x01, 0xF4],
+        [0x27, level, ..] if level % 2 == 1 => vec![0x67, *level, 0x12, 0x34, 0x56, 0x78],
+        [0x31, sub, hi, lo, ..] => vec![0x71, *sub, *hi, *lo, 0x00],
+        [0x34, ..] => vec![0x74, 0x20, 0x01, 0x02],
+        [0x36, counter, ..] => vec![0x76, *counter],
+        [sid, sub, ..] => vec![sid + 0x40, *sub],
+        [sid] => vec![sid + 0x40],
+        [] => vec![],
+    }
+}
+
+/// 记录请求并正响应所有刷写请求，以 failing 开头的请求返回否定响应（条件不满足）
+pub fn flash_handler(
+    requests: RequestLog,
+    failing: Option<Vec<u8>>,
+) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+    recording(requests, move |request| {
+        if request.is_empty() {
+            return vec![];
+        }
+        if failing.as_deref().is_some_and(|f| request.starts_with(f)) {
+            return vec![SimAction::Respond(vec![0x7F, request[0], 0x22])];
+        }
+        vec![SimAction::Respond(flash_response(request))]
+    })
+}
+
+/// 模拟器中的 ECU 内存
+pub type SimMemory = Arc<Mutex<MemorySegment>>;
+
+/// 以内存段内容创建模拟器内存
+pub fn sim_memory(segment: MemorySegment) -> SimMemory {
+    Arc::new(Mutex::new(segment))
+}
+
+/// 以 memory 模拟 ECU 内存：应答上传（0x35/0x36/0x37，每块 256 字节）、
+/// 按地址读（0x23，单次最多 max_read 字节）和按地址写（0x3D），地址和长度按请求中的 ALFID 解析
+pub fn memory_handler(
+    memory: SimMemory,
+    max_read: usize,
+) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+    let mut cursor = 0usize;
+    let mut end = 0usize;
+    move |request| {
+        let memory = &mut *memory.lock().unwrap();
+        // 请求的地址范围转换为内存中的下标
+        let range = |address: u64, size: usize| {
+            let start = address.checked_sub(memory.address)? as usize;
+            (start + size <= memory.data.len()).then_some(start..start + size)
+        };
+        let response = match request {
+            [0x35, _, rest @ ..] => match memory_request(rest).and_then(|(a, s, _)| range(a, s)) {
+                Some(r) => {
+                    (cursor, end) = (r.start, r.end);
+                    vec![0x75, 0x20, 0x01, 0x02]
+                }
+                None => vec![0x7F, 0x35, 0x31],
+            },
+            [0x36, counter] => {
+                let stop = end.min(cursor + 256);
+                let mut response = vec![0x76, *counter];
+                response.extend_from_slice(&memory.data[cursor..stop]);
+                cursor = stop;
+                response
+            }
+            [0x37] => vec![0x77],
+            [0x23, rest @ ..] => match memory_request(rest) {
+                Some((_, size, _)) if size > max_read => vec![0x7F, 0x23, 0x14],
+                Some((address, size, _)) => match range(address, size) {
+                    Some(r) => [&[0x63][..], &memory.data[r]].concat(),
+                    None => vec![0x7F, 0x23, 0x31],
+                },
+                None => vec![0x7F, 0x23, 0x13],
+            },
+            [0x3D, rest @ ..] => match memory_request(rest) {
+                Some((address, size, data)) if data.len() == size => match range(address, size) {
+                    Some(r) => {
+                        memory.data[r].copy_from_slice(data);
+                        let echo = request.len() - size;
+                        [&[0x7D][..], &request[1..echo]].concat()
+                    }
+                    None => vec![0x7F, 0x3D, 0x31],
+                },
+                _ => vec![0x7F, 0x3D, 0x13],
+            },
+            _ => vec![0x7F, request[0], 0x11],
+        };
+        vec![SimAction::Respond(response)]
+    }
+}
+
+/// 按 ALFID 解析内存请求，返回地址、长度和其后的数据
+fn memory_request(request: &[u8]) -> Option<(u64, usize, &[u8])> {
+    let (&alfid, rest) = request.split_first()?;
+    let (address_len, size_len) = ((alfid & 0x0F) as usize, (alfid >> 4) as usize);
+    if rest.len() < address_len + size_len {
+        return None;
+    }
+    let value = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    Some((
+        value(&rest[..address_len]),
+        value(&rest[address_len..address_len + size_len]) as usize,
+        &rest[address_len + size_len..],
+    ))
+}
+
+async fn send_uds(writer: &tokio::sync::Mutex<Option<OwnedWriteHalf>>, source: u16, uds: &[u8]) {
+    let mut payload = Vec::with_capacity(uds.len() + 4);
+    payload.extend_from_slice(&source.to_be_bytes());
+    payload.extend_from_slice(&TESTER_ADDRESS.to_be_bytes());
+    payload.extend_from_slice(uds);
+    write_frame(writer, 0x8001, &payload).await;
+}
+
+async fn write_frame(
+    writer: &tokio::sync::Mutex<Option<OwnedWriteHalf>>,
+    payload_type: u16,
+    payload: &[u8],
+) {
+    let mut frame = vec![0x02, 0xFD];
+    frame.extend_from_slice(&payload_type.to_be_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    if let Some(writer) = writer.lock().await.as_mut() {
+        let _ = writer.write_all(&frame).await;
+    }
+}
@@ -0,0 +1,484 @@
+/**
+ * 刷写文件解析
+ * 支持 Intel HEX 和 Motorola S-record，逐条校验记录后合并为连续内存段，
+ * 可按地址范围裁剪并填充段间空隙
+ */
+use crate::types::{ImageError, ImageResult, MemorySegment};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 刷写文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    IntelHex,
+    SRecord,
+}
+
+impl ImageFormat {
+    /// 根据首个非空字符识别文件格式
+    pub fn detect(text: &str) -> ImageResult<Self> {
+        match text.trim_start().chars().next() {
+            Some(':') => Ok(ImageFormat::IntelHex),
+            Some('S') | Some('s') => Ok(ImageFormat::SRecord),
+            Some(c) => Err(ImageError::UnsupportedFormat(format!(
+                "unexpected first character '{}'",
+                c
+            ))),
+            None => Err(ImageError::UnsupportedFormat("empty file".to_string())),
+        }
+    }
+}
+
+/// 段间空隙填充配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GapFill {
+    pub max_gap: u64, // 只填充不超过该长度的空隙
+    #[serde(default = "default_fill_byte")]
+    pub fill_byte: u8,
+}
+
+fn default_fill_byte() -> u8 {
+    0xFF
+}
+
+/// 镜像加载选项，对应刷写页面的起始/结束地址
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    pub start_address: Option<u64>,
+    pub end_address: Option<u64>, // 不含该地址
+    pub fill_gaps: Option<GapFill>,
+}
+
+/// 解析后的刷写镜像
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashImage {
+    pub segments: Vec<MemorySegment>,
+    pub entry_point: Option<u64>,
+}
+
+impl FlashImage {
+    /// 自动识别格式并解析
+    pub fn parse(text: &str) -> ImageResult<Self> {
+        match ImageFormat::detect(text)? {
+            ImageFormat::IntelHex => parse_intel_hex(text),
+            ImageFormat::SRecord => parse_srecord(text),
+        }
+    }
+
+    /// 读取并解析刷写文件，然后应用裁剪和填充选项
+    pub fn load<P: AsRef<Path>>(path: P, options: &ImageOptions) -> ImageResult<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut image = Self::parse(&text)?;
+        image.apply(options);
+        Ok(image)
+    }
+
+    /// 先按地址范围裁剪，再填充空隙
+    pub fn apply(&mut self, options: &ImageOptions) {
+        self.clip(options.start_address, options.end_address);
+        if let Some(fill) = &options.fill_gaps {
+            self.fill_gaps(fill.max_gap, fill.fill_byte);
+        }
+    }
+
+    /// 数据总字节数
+    pub fn total_bytes(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    /// 用 fill_byte 填充不超过 max_gap 的段间空隙，使相邻段合并
+    pub fn fill_gaps(&mut self, max_gap: u64, fill_byte: u8) {
+        let mut filled: Vec<MemorySegment> = Vec::with_capacity(self.segments.len());
+
+        for segment in self.segments.drain(..) {
+            match filled.last_mut() {
+                Some(last) if segment.address - last.end() <= max_gap => {
+                    let gap = (segment.address - last.end()) as usize;
+                    last.data.resize(last.data.len() + gap, fill_byte);
+                    last.data.extend_from_slice(&segment.data);
+                }
+                _ => filled.push(segment),
+            }
+        }
+
+        self.segments = filled;
+    }
+
+    /// 只保留 [start, end) 范围内的数据
+    pub fn clip(&mut self, start: Option<u64>, end: Option<u64>) {
+        let start = start.unwrap_or(0);
+        let end = end.unwrap_or(u64::MAX);
+
+        self.segments = self
+            .segments
+            .iter()
+            .filter_map(|segment| {
+                let from = segment.address.max(start);
+                let to = segment.end().min(end);
+                if from >= to {
+                    return None;
+                }
+                let offset = (from - segment.address) as usize;
+                let len = (to - from) as usize;
+                Some(MemorySegment::new(
+                    from,
+                    segment.data[offset..offset + len].to_vec(),
+                ))
+            })
+            .collect();
+    }
+}
+
+/// 解析 Intel HEX（记录类型 00–05）
+pub fn parse_intel_hex(text: &str) -> ImageResult<FlashImage> {
+    let mut records = Vec::new();
+    let mut entry_point = None;
+    let mut base_address: u64 = 0;
+    let mut last_line = 0;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw.trim();
+        if record.is_empty() {
+            continue;
+        }
+        last_line = line;
+
+        let body = record
+            .strip_prefix(':')
+            .ok_or_else(|| invalid_record(line, "missing ':' start code"))?;
+        let bytes = decode_hex(body, line)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(invalid_record(line, "record length mismatch"));
+        }
+
+        // 所有字节（含校验和）之和为 0
+        let checksum_pos = bytes.len() - 1;
+        let expected = bytes[..checksum_pos]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_add(b))
+            .wrapping_neg();
+        if bytes[checksum_pos] != expected {
+            return Err(ImageError::ChecksumMismatch {
+                line,
+                expected,
+                found: bytes[checksum_pos],
+            });
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u64;
+        let data = &bytes[4..checksum_pos];
+
+        match bytes[3] {
+            // 数据记录，地址在 64 KB 段内回绕，跨越段边界的记录不支持
+            0x00 => {
+                if offset + data.len() as u64 > 0x1_0000 {
+                    return Err(invalid_record(line, "data record crosses a 64 KB boundary"));
+                }
+                records.push(MemorySegment::new(base_address + offset, data.to_vec()))
+            }
+            // 文件结束
+            0x01 => {
+                return Ok(FlashImage {
+                    segments: merge_segments(records)?,
+                    entry_point,
+                })
+            }
+            // 扩展段地址
+            0x02 => base_address = (read_be(data, 2, line)? as u64) << 4,
+            // 起始段地址（CS:IP）
+            0x03 => {
+                let cs_ip = read_be(data, 4, line)?;
+                entry_point = Some(((cs_ip >> 16) << 4) + (cs_ip & 0xFFFF));
+            }
+            // 扩展线性地址
+            0x04 => base_address = (read_be(data, 2, line)? as u64) << 16,
+            // 起始线性地址
+            0x05 => entry_point = Some(read_be(data, 4, line)?),
+            record_type => {
+                return Err(invalid_record(
+                    line,
+                    &format!("unsupported record type 0x{:02X}", record_type),
+                ))
+            }
+        }
+    }
+
+    Err(invalid_record(last_line, "missing end-of-file record"))
+}
+
+/// 解析 Motorola S-record（S0–S9）
+pub fn parse_srecord(text: &str) -> ImageResult<FlashImage> {
+    let mut records = Vec::new();
+    let mut entry_point = None;
+    let mut data_records: u64 = 0;
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let record = raw.trim();
+        if record.is_empty() {
+            continue;
+        }
+
+        let mut chars = record.chars();
+        if !matches!(chars.next(), Some('S') | Some('s')) {
+            return Err(invalid_record(line, "missing 'S' start code"));
+        }
+        let record_type = chars
+            .next()
+            .and_then(|c| c.to_digit(10))
+            .ok_or_else(|| invalid_record(line, "invalid record type"))?;
+        let address_len = match record_type {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => {
+                return Err(invalid_record(
+                    line,
+                    &format!("unsupported record type S{}", record_type),
+                ))
+            }
+        };
+
+        let bytes = decode_hex(&record[2..], line)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            return Err(invalid_record(line, "record length mismatch"));
+        }
+        if bytes.len() < address_len + 2 {
+            return Err(invalid_record(line, "record too short for its address"));
+        }
+
+        // 计数、地址和数据之和的反码
+        let checksum_pos = bytes.len() - 1;
+        let expected = !bytes[..checksum_pos]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_add(b));
+        if bytes[checksum_pos] != expected {
+            return Err(ImageError::ChecksumMismatch {
+                line,
+                expected,
+                found: bytes[checksum_pos],
+            });
+        }
+
+        let address = read_be(&bytes[1..1 + address_len], address_len, line)?;
+        let data = &bytes[1 + address_len..checksum_pos];
+
+        match record_type {
+            // 头记录
+            0 => {}
+            1..=3 => {
+                records.push(MemorySegment::new(address, data.to_vec()));
+                data_records += 1;
+            }
+            // 数据记录计数
+            5 | 6 => {
+                if address != data_records {
+                    return Err(invalid_record(
+                        line,
+                        &format!(
+                            "record count {} does not match {} data records",
+                            address, data_records
+                        ),
+                    ));
+                }
+            }
+            // 起始地址，同时表示文件结束
+            _ => {
+                entry_point = Some(address);
+                break;
+            }
+        }
+    }
+
+    Ok(FlashImage {
+        segments: merge_segments(records)?,
+        entry_point,
+    })
+}
+
+/// 按地址排序并合并相邻记录，地址重叠时报错
+pub fn merge_segments(mut records: Vec<MemorySegment>) -> ImageResult<Vec<MemorySegment>> {
+    records.retain(|r| !r.data.is_empty());
+    records.sort_by_key(|r| r.address);
+
+    let mut merged: Vec<MemorySegment> = Vec::new();
+    for record in records {
+        match merged.last_mut() {
+            Some(last) if record.address < last.end() => {
+                return Err(ImageError::Overlap(record.address));
+            }
+            Some(last) if record.address == last.end() => {
+                last.data.extend_from_slice(&record.data);
+            }
+            _ => merged.push(record),
+        }
+    }
+
+    Ok(merged)
+}
+
+fn decode_hex(body: &str, line: usize) -> ImageResult<Vec<u8>> {
+    hex::decode(body).map_err(|e| invalid_record(line, &e.to_string()))
+}
+
+/// 读取固定长度的大端数值
+fn read_be(data: &[u8], len: usize, line: usize) -> ImageResult<u64> {
+    if data.len() != len {
+        return Err(invalid_record(
+            line,
+            &format!("expected {} data bytes, found {}", len, data.len()),
+        ));
+    }
+    Ok(data.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64))
+}
+
+fn invalid_record(line: usize, reason: &str) -> ImageError {
+    ImageError::InvalidRecord {
+        line,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTEL_HEX: &str = "\
+:020000040800F2
+:10000000000102030405060708090A0B0C0D0E0F78
+:10001000101112131415161718191A1B1C1D1E1F68
+:04010000AABBCCDDED
+:0400000508000101ED
+:00000001FF
+";
+
+    const SRECORD: &str = "\
+S00600004844521B
+S1130000000102030405060708090A0B0C0D0E0F74
+S208010000112233444C
+S5030002FA
+S9030000FC
+";
+
+    #[test]
+    fn test_parse_intel_hex() {
+        let image = FlashImage::parse(INTEL_HEX).unwrap();
+        assert_eq!(image.entry_point, Some(0x0800_0101));
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].address, 0x0800_0000);
+        assert_eq!(image.segments[0].data, (0u8..0x20).collect::<Vec<_>>());
+        assert_eq!(image.segments[1].address, 0x0800_0100);
+        assert_eq!(image.segments[1].data, vec![0xAA, 0xBB, 0xCC, 0xDD]);
+        assert_eq!(image.total_bytes(), 36);
+    }
+
+    #[test]
+    fn test_intel_hex_extended_segment_address() {
+        let text = ":020000021000EC\n:0100000042BD\n:0400000300100020C9\n:00000001FF\n";
+        let image = parse_intel_hex(text).unwrap();
+        assert_eq!(
+            image.segments,
+            vec![MemorySegment::new(0x10000, vec![0x42])]
+        );
+        assert_eq!(image.entry_point, Some(0x120));
+    }
+
+    #[test]
+    fn test_intel_hex_errors() {
+        let err = parse_intel_hex(":0100000042BE\n:00000001FF\n").unwrap_err();
+        assert!(matches!(
+            err,
+            ImageError::ChecksumMismatch {
+                line: 1,
+                expected: 0xBD,
+                found: 0xBE
+            }
+        ));
+
+        let err = parse_intel_hex(":0100000042BD\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 1, .. }));
+
+        let err = parse_intel_hex(":0200000042BD\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { .. }));
+
+        let err = parse_intel_hex(":0100000042BD\n:0100000043BC\n:00000001FF\n").unwrap_err();
+        assert!(matches!(err, ImageError::Overlap(0)));
+
+        // 数据跨越 64 KB 段边界
+        let text = ":020000040800F2\n:04FFFE001122334455\n:00000001FF\n";
+        let err = parse_intel_hex(text).unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 2, .. }));
+        let text = ":020000040800F2\n:04FFFC001122334457\n:00000001FF\n";
+        assert!(parse_intel_hex(text).is_ok());
+    }
+
+    #[test]
+    fn test_parse_srecord() {
+        let image = FlashImage::parse(SRECORD).unwrap();
+        assert_eq!(image.entry_point, Some(0));
+        assert_eq!(image.segments.len(), 2);
+        assert_eq!(image.segments[0].data, (0u8..0x10).collect::<Vec<_>>());
+        assert_eq!(
+            image.segments[1],
+            MemorySegment::new(0x01_0000, vec![0x11, 0x22, 0x33, 0x44])
+        );
+    }
+
+    #[test]
+    fn test_srecord_errors() {
+        let err = parse_srecord("S1050000420078\n").unwrap_err();
+        assert!(matches!(err, ImageError::ChecksumMismatch { line: 1, .. }));
+
+        let err = parse_srecord("S104000042B9\nS5030002FA\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 2, .. }));
+
+        let err = parse_srecord("S4030000FC\n").unwrap_err();
+        assert!(matches!(err, ImageError::InvalidRecord { line: 1, .. }));
+    }
+
+    #[test]
+    fn test_fill_gaps_and_clip() {
+        let mut image = FlashImage {
+            segments: vec![
+                MemorySegment::new(0x1000, vec![0x01; 4]),
+                MemorySegment::new(0x1008, vec![0x02; 4]),
+                MemorySegment::new(0x2000, vec![0x03; 4]),
+            ],
+            entry_point: None,
+        };
+
+        image.apply(&ImageOptions {
+            start_address: Some(0x1002),
+            end_address: Some(0x2002),
+            fill_gaps: Some(GapFill {
+                max_gap: 0x10,
+                fill_byte: 0xFF,
+            }),
+        });
+
+        assert_eq!(
+            image.segments,
+            vec![
+                MemorySegment::new(
+                    0x1002,
+                    vec![0x01, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0x02, 0x02, 0x02, 0x02]
+                ),
+                MemorySegment::new(0x2000, vec![0x03, 0x03]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            ImageFormat::detect(INTEL_HEX).unwrap(),
+            ImageFormat::IntelHex
+        );
+        assert_eq!(ImageFormat::detect(SRECORD).unwrap(), ImageFormat::SRecord);
+        assert!(ImageFormat::detect("\x7fELF").is_err());
+        assert!(ImageFormat::detect("  \n").is_err());
+    }
+}
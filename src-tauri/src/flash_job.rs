@@ -0,0 +1,1502 @@
+/**
+ * 刷写流程编排
+ * 将会话切换、安全解锁、擦除、下载、校验和复位组织为可配置的步骤列表，
+ * 支持单步超时、中止、失败回滚和进度推送
+ */
+use crate::data_format::DataCodec;
+use crate::delta::{compute_delta, restore_delta, DeltaOptions, DeltaPlan, ReferenceSource};
+use crate::flash_state::{image_digest, FlashJobState, FlashStateStore};
+use crate::types::{
+    AddressAndLengthFormat, CommonRoutines, DownloadOptions, DownloadProgress, MemoryRange,
+    MemorySegment, ResetTypes, RoutinePollConfig, SessionTypes, TransferCheckpoint, UdsError,
+    UdsResult,
+};
+use crate::uds_service::UdsService;
+use crate::verification::{verify_image, VerificationConfig, VerificationReport};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// 刷写进度推送到前端的事件名称
+pub const FLASH_PROGRESS_EVENT: &str = "flash-progress";
+
+/// 下载过程中每确认该数量的数据块保存一次断点
+const CHECKPOINT_INTERVAL_BLOCKS: usize = 32;
+
+/// 距上次保存断点超过该时间时立即保存
+const CHECKPOINT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 刷写步骤动作
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FlashAction {
+    /// 诊断会话控制
+    Session { session: u8 },
+    /// DTC 记录控制（0x01 开启，0x02 关闭）
+    ControlDtcSetting { setting_type: u8 },
+    /// 通信控制（0x00 开启收发，0x03 关闭收发）
+    CommunicationControl { control_type: u8 },
+    /// 安全解锁，level 为请求种子的级别
+    SecurityAccess { level: u8, key: u32 },
+    /// 启动例程并等待结果
+    Routine {
+        routine_id: u16,
+        #[serde(default)]
+        option_record: Vec<u8>,
+        #[serde(default)]
+        poll: RoutinePollConfig,
+    },
+    /// 按内存段逐个擦除（例程 0xFF00，选项为 ALFID + 地址 + 长度）
+    EraseMemory {
+        #[serde(default)]
+        poll: RoutinePollConfig,
+    },
+    /// 下载全部内存段
+    Download,
+    /// 按内存段逐个校验（例程选项为 ALFID + 地址 + 长度）
+    CheckMemory {
+        routine_id: u16,
+        #[serde(default)]
+        poll: RoutinePollConfig,
+    },
+    /// ECU 复位
+    EcuReset { reset_type: u8 },
+}
+
+impl FlashAction {
+    /// 续传时需要重新执行的步骤：会话、安全访问及通信/DTC 控制
+    fn restores_link(&self) -> bool {
+        matches!(
+            self,
+            FlashAction::Session { .. }
+                | FlashAction::SecurityAccess { .. }
+                | FlashAction::ControlDtcSetting { .. }
+                | FlashAction::CommunicationControl { .. }
+        )
+    }
+}
+
+/// 刷写步骤
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashStep {
+    pub name: String,
+    #[serde(flatten)]
+    pub action: FlashAction,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+impl FlashStep {
+    pub fn new(name: &str, action: FlashAction, timeout_ms: Option<u64>) -> Self {
+        Self {
+            name: name.to_string(),
+            action,
+            timeout_ms,
+        }
+    }
+}
+
+/// 刷写任务配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlashJobConfig {
+    pub steps: Vec<FlashStep>,
+    pub rollback: Vec<FlashStep>, // 任一步骤失败或中止后依次执行，错误被忽略
+    pub download: DownloadOptions,
+    pub delta: Option<DeltaOptions>, // 设置后只擦除和下载与参考数据不同的块
+    pub resume_mode: ResumeMode,
+    pub verification: VerificationConfig, // 发送前校验刷写数据
+}
+
+/// 续传方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResumeMode {
+    /// 从最后确认的数据块之后继续传输，需引导程序保留下载上下文
+    ContinueBlock,
+    /// 重新擦除并下载中断的内存段
+    #[default]
+    RestartSegment,
+}
+
+impl FlashJobConfig {
+    /// 标准编程流程，使用指定的安全级别和密钥参数
+    pub fn standard(security_level: u8, key: u32) -> Self {
+        let poll = RoutinePollConfig::default();
+        Self {
+            steps: vec![
+                FlashStep::new(
+                    "进入扩展会话",
+                    FlashAction::Session {
+                        session: SessionTypes::EXTENDED,
+                    },
+                    Some(5000),
+                ),
+                FlashStep::new(
+                    "检查编程预条件",
+                    FlashAction::Routine {
+                        routine_id: CommonRoutines::CHECK_PROGRAMMING_PRECONDITIONS,
+                        option_record: Vec::new(),
+                        poll: poll.clone(),
+                    },
+                    Some(10000),
+                ),
+                FlashStep::new(
+                    "关闭DTC记录",
+                    FlashAction::ControlDtcSetting { setting_type: 0x02 },
+                    Some(5000),
+                ),
+                FlashStep::new(
+                    "关闭通信",
+                    FlashAction::CommunicationControl { control_type: 0x03 },
+                    Some(5000),
+                ),
+                FlashStep::new(
+                    "进入编程会话",
+                    FlashAction::Session {
+                        session: SessionTypes::PROGRAMMING,
+                    },
+                    Some(10000),
+                ),
+                FlashStep::new(
+                    "安全解锁",
+                    FlashAction::SecurityAccess {
+                        level: security_level,
+                        key,
+                    },
+                    Some(10000),
+                ),
+                FlashStep::new(
+                    "擦除内存",
+                    FlashAction::EraseMemory { poll: poll.clone() },
+                    Some(120000),
+                ),
+                FlashStep::new("下载数据", FlashAction::Download, None),
+                FlashStep::new(
+                    "校验内存",
+                    FlashAction::CheckMemory {
+                        routine_id: CommonRoutines::CHECK_MEMORY,
+                        poll: poll.clone(),
+                    },
+                    Some(60000),
+                ),
+                FlashStep::new(
+                    "检查编程依赖",
+                    FlashAction::Routine {
+                        routine_id: CommonRoutines::CHECK_PROGRAMMING_DEPENDENCIES,
+                        option_record: Vec::new(),
+                        poll,
+                    },
+                    Some(60000),
+                ),
+                FlashStep::new(
+                    "ECU复位",
+                    FlashAction::EcuReset {
+                        reset_type: ResetTypes::HARD_RESET,
+                    },
+                    Some(5000),
+                ),
+            ],
+            rollback: vec![
+                FlashStep::new(
+                    "恢复通信",
+                    FlashAction::CommunicationControl { control_type: 0x00 },
+                    Some(5000),
+                ),
+                FlashStep::new(
+                    "恢复DTC记录",
+                    FlashAction::ControlDtcSetting { setting_type: 0x01 },
+                    Some(5000),
+                ),
+                FlashStep::new(
+                    "返回默认会话",
+                    FlashAction::Session {
+                        session: SessionTypes::DEFAULT,
+                    },
+                    Some(5000),
+                ),
+            ],
+            download: DownloadOptions::default(),
+            delta: None,
+            resume_mode: ResumeMode::default(),
+            verification: VerificationConfig::default(),
+        }
+    }
+}
+
+impl Default for FlashJobConfig {
+    fn default() -> Self {
+        Self::standard(0x01, 0)
+    }
+}
+
+/// 刷写步骤状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashStepState {
+    Started,
+    Completed,
+    Failed,
+    RollingBack,
+}
+
+/// 刷写进度
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashProgress {
+    pub step_index: usize,
+    pub step_count: usize,
+    pub step_name: String,
+    pub state: FlashStepState,
+    pub percent: f64,
+    pub message: Option<String>,
+    pub download: Option<DownloadProgress>,
+}
+
+/// 刷写任务报告
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashReport {
+    pub segment_count: usize,
+    pub total_bytes: usize,
+    pub downloaded_bytes: usize, // 差分刷写时只统计变化块
+    pub verification: VerificationReport,
+}
+
+/// 断点写入节流，文件写入在阻塞线程池中进行
+#[derive(Default)]
+struct CheckpointWriter {
+    pending: Option<FlashJobState>, // 尚未写入的最新断点
+    blocks: usize,                  // 上次写入后确认的数据块数
+    last_write: Option<Instant>,
+    writing: Option<JoinHandle<()>>,
+}
+
+/// 刷写任务
+pub struct FlashJob {
+    config: FlashJobConfig,
+    segments: Vec<MemorySegment>,
+    erase_ranges: Option<Vec<MemoryRange>>,
+    segment_checksums: Vec<Vec<u8>>,
+    reference: Option<Box<dyn ReferenceSource>>,
+    delta_plan: Mutex<Option<Arc<DeltaPlan>>>,
+    state_store: Option<FlashStateStore>,
+    checkpoints: Mutex<CheckpointWriter>,
+    resume: Option<FlashJobState>,
+    image_digest: u32,
+    abort: Arc<AtomicBool>,
+}
+
+impl FlashJob {
+    pub fn new(config: FlashJobConfig, segments: Vec<MemorySegment>) -> Self {
+        Self {
+            config,
+            image_digest: image_digest(&segments),
+            segments,
+            erase_ranges: None,
+            segment_checksums: Vec::new(),
+            reference: None,
+            delta_plan: Mutex::new(None),
+            state_store: None,
+            checkpoints: Mutex::new(CheckpointWriter::default()),
+            resume: None,
+            abort: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 使用文件提供的擦除范围代替按内存段擦除
+    pub fn with_erase_ranges(mut self, ranges: Vec<MemoryRange>) -> Self {
+        self.erase_ranges = Some(ranges);
+        self
+    }
+
+    /// 每个内存段的校验值，校验内存时附加在例程选项之后
+    pub fn with_segment_checksums(mut self, checksums: Vec<Vec<u8>>) -> Self {
+        self.segment_checksums = checksums;
+        self
+    }
+
+    /// 差分刷写的参考数据来源
+    pub fn with_reference(mut self, reference: Box<dyn ReferenceSource>) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// 下载过程中保存断点，任务成功后删除
+    pub fn with_state_store(mut self, store: FlashStateStore) -> Self {
+        self.state_store = Some(store);
+        self
+    }
+
+    /// 从断点续传：下载步骤之前只重新建立会话和安全访问，下载从断点所在内存段继续
+    pub fn with_resume_state(mut self, state: FlashJobState) -> Self {
+        self.resume = Some(state);
+        self
+    }
+
+    /// 内存段数量
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// 待下载的总字节数
+    pub fn total_bytes(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    /// 使用外部中止标志（例如在 Tauri 状态中共享）
+    pub fn with_abort_flag(mut self, abort: Arc<AtomicBool>) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// 中止标志，置位后任务在下一个步骤或数据块前停止
+    pub fn abort_handle(&self) -> Arc<AtomicBool> {
+        self.abort.clone()
+    }
+
+    /// 执行刷写任务，失败或中止时执行回滚步骤并返回原始错误。
+    /// 刷写数据校验失败时不向 ECU 发送任何请求
+    pub async fn run<F>(
+        &self,
+        service: &mut UdsService,
+        mut on_progress: F,
+    ) -> UdsResult<FlashReport>
+    where
+        F: FnMut(FlashProgress) + Send,
+    {
+        let step_count = self.config.steps.len();
+
+        let verification = verify_image(&self.segments, &self.config.verification)?;
+        if !verification.passed() {
+            log::error!(
+                "Flash data verification failed: {}",
+                verification.failures().join("; ")
+            );
+            return Err(UdsError::ImageVerificationFailed(Box::new(verification)));
+        }
+
+        if let Some(state) = &self.resume {
+            if state.image_digest != self.image_digest {
+                return Err(UdsError::InvalidParameter(
+                    "Resume state does not match the flash data".to_string(),
+                ));
+            }
+            if state.delta_blocks.is_some() != self.config.delta.is_some() {
+                return Err(UdsError::InvalidParameter(
+                    "Resume state does not match the delta configuration".to_string(),
+                ));
+            }
+            log::info!(
+                "Resuming flash job at step {} address 0x{:X}",
+                state.step_index,
+                state.segment_address
+            );
+        }
+
+        for (index, step) in self.config.steps.iter().enumerate() {
+            let report = |state, message: Option<String>, download: Option<DownloadProgress>| {
+                let fraction = match (&download, state) {
+                    (_, FlashStepState::Completed) => 1.0,
+                    (Some(p), _) if p.total_bytes > 0 => {
+                        p.transferred_bytes as f64 / p.total_bytes as f64
+                    }
+                    _ => 0.0,
+                };
+                FlashProgress {
+                    step_index: index,
+                    step_count,
+                    step_name: step.name.clone(),
+                    state,
+                    percent: (index as f64 + fraction) * 100.0 / step_count as f64,
+                    message,
+                    download,
+                }
+            };
+
+            let skipped = self
+                .resume
+                .as_ref()
+                .is_some_and(|state| index < state.step_index && !step.action.restores_link());
+
+            let result = if self.abort.load(Ordering::SeqCst) {
+                Err(UdsError::Aborted)
+            } else if skipped {
+                on_progress(report(
+                    FlashStepState::Completed,
+                    Some("断点续传，已跳过".to_string()),
+                    None,
+                ));
+                continue;
+            } else {
+                on_progress(report(FlashStepState::Started, None, None));
+                self.run_step(service, step, &mut |p| {
+                    on_progress(report(FlashStepState::Started, None, Some(p)))
+                })
+                .await
+            };
+
+            match result {
+                Ok(()) => on_progress(report(FlashStepState::Completed, None, None)),
+                Err(e) => {
+                    log::error!("Flash step '{}' failed: {}", step.name, e);
+                    on_progress(report(FlashStepState::Failed, Some(e.to_string()), None));
+                    if index > 0 || !matches!(e, UdsError::Aborted) {
+                        self.rollback(service, &mut on_progress).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        if let Some(store) = &self.state_store {
+            if let Err(e) = store.clear() {
+                log::error!("Failed to clear flash state: {}", e);
+            }
+        }
+
+        let downloaded_bytes = match self.delta_plan.lock().unwrap().as_ref() {
+            Some(plan) => plan.changed_bytes(),
+            None => self.total_bytes(),
+        };
+        Ok(FlashReport {
+            segment_count: self.segment_count(),
+            total_bytes: self.total_bytes(),
+            downloaded_bytes,
+            verification,
+        })
+    }
+
+    /// 执行回滚步骤，忽略其中的错误
+    async fn rollback<F>(&self, service: &mut UdsService, on_progress: &mut F)
+    where
+        F: FnMut(FlashProgress) + Send,
+    {
+        let step_count = self.config.rollback.len();
+
+        for (index, step) in self.config.rollback.iter().enumerate() {
+            on_progress(FlashProgress {
+                step_index: index,
+                step_count,
+                step_name: step.name.clone(),
+                state: FlashStepState::RollingBack,
+                percent: index as f64 * 100.0 / step_count as f64,
+                message: None,
+                download: None,
+            });
+            if let Err(e) = self.run_step(service, step, &mut |_| {}).await {
+                log::error!("Rollback step '{}' failed: {}", step.name, e);
+            }
+        }
+    }
+
+    /// 执行单个步骤，超时后返回步骤超时错误
+    async fn run_step(
+        &self,
+        service: &mut UdsService,
+        step: &FlashStep,
+        on_download: &mut (dyn FnMut(DownloadProgress) + Send),
+    ) -> UdsResult<()> {
+        let action = self.run_action(service, &step.action, on_download);
+
+        match step.timeout_ms {
+            Some(ms) => tokio::time::timeout(Duration::from_millis(ms), action)
+                .await
+                .map_err(|_| UdsError::StepTimeout(step.name.clone()))?,
+            None => action.await,
+        }
+    }
+
+    async fn run_action(
+        &self,
+        service: &mut UdsService,
+        action: &FlashAction,
+        on_download: &mut (dyn FnMut(DownloadProgress) + Send),
+    ) -> UdsResult<()> {
+        match action {
+            FlashAction::Session { session } => {
+                service.start_session(*session).await?;
+            }
+            FlashAction::ControlDtcSetting { setting_type } => {
+                service.control_dtc_setting(*setting_type).await?;
+            }
+            FlashAction::CommunicationControl { control_type } => {
+                service.communication_control(*control_type).await?;
+            }
+            FlashAction::SecurityAccess { level, key } => {
+                service.security_unlock(*level, *key).await?;
+            }
+            FlashAction::Routine {
+                routine_id,
+                option_record,
+                poll,
+            } => {
+                service
+                    .run_routine(*routine_id, option_record, poll)
+                    .await?;
+            }
+            FlashAction::EraseMemory { poll } => {
+                let ranges = self.erase_ranges(service).await?;
+                self.erase(service, &ranges, poll).await?;
+            }
+            FlashAction::Download => {
+                // 差分刷写时只下载变化块
+                let plan = self.delta_plan(service).await?;
+                let units = match &plan {
+                    Some(plan) => plan
+                        .blocks
+                        .iter()
+                        .map(|b| Ok((&b.segment, plan.block_codec(b, &self.config.download)?)))
+                        .collect::<UdsResult<Vec<_>>>()?,
+                    None => self
+                        .segments
+                        .iter()
+                        .map(|s| Ok((s, self.config.download.encoding.build()?)))
+                        .collect::<UdsResult<Vec<_>>>()?,
+                };
+
+                let segments: Vec<&MemorySegment> = units.iter().map(|(s, _)| *s).collect();
+                let (start, mut resume) = self.resume_point(service, &segments).await?;
+                let total_bytes = segments.iter().map(|s| s.data.len()).sum();
+                let mut done_bytes = segments[..start].iter().map(|s| s.data.len()).sum();
+
+                for (index, (segment, codec)) in units.iter().enumerate().skip(start) {
+                    let checkpoint = resume.take();
+                    self.save_state(index, segment.address, checkpoint).await;
+                    self.download(
+                        service,
+                        index,
+                        segment,
+                        codec,
+                        checkpoint,
+                        (total_bytes, done_bytes),
+                        on_download,
+                    )
+                    .await?;
+                    done_bytes += segment.data.len();
+                }
+                let mut state = self.job_state(units.len(), 0, None);
+                state.download_complete = true;
+                self.store_state(state).await;
+            }
+            FlashAction::CheckMemory { routine_id, poll } => {
+                for (index, segment) in self.segments.iter().enumerate() {
+                    let mut option_record =
+                        self.range_option_record(segment.address, segment.data.len() as u64)?;
+                    // 配置了校验算法时使用其校验值，否则使用文件提供的校验值
+                    match self.config.verification.checksum {
+                        Some(algorithm) => {
+                            option_record.extend(algorithm.compute(&segment.data));
+                        }
+                        None => {
+                            if let Some(checksum) = self.segment_checksums.get(index) {
+                                option_record.extend_from_slice(checksum);
+                            }
+                        }
+                    }
+                    service
+                        .run_routine(*routine_id, &option_record, poll)
+                        .await?;
+                }
+            }
+            FlashAction::EcuReset { reset_type } => {
+                service.ecu_reset(*reset_type).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 下载一个内存段，进度换算为本次下载的总体进度。
+    /// 确认的数据块按间隔保存断点，结束或中断时保存最后确认的断点
+    #[allow(clippy::too_many_arguments)]
+    async fn download(
+        &self,
+        service: &mut UdsService,
+        index: usize,
+        segment: &MemorySegment,
+        codec: &DataCodec,
+        resume: Option<TransferCheckpoint>,
+        (total_bytes, done_bytes): (usize, usize),
+        on_download: &mut (dyn FnMut(DownloadProgress) + Send),
+    ) -> UdsResult<()> {
+        let result = service
+            .download_segment_checkpointed(
+                segment.address,
+                &segment.data,
+                &self.config.download,
+                codec,
+                resume,
+                &self.abort,
+                |p, checkpoint| {
+                    self.record_checkpoint(index, segment.address, checkpoint);
+                    on_download(DownloadProgress {
+                        total_bytes,
+                        transferred_bytes: done_bytes + p.transferred_bytes,
+                        ..p
+                    })
+                },
+            )
+            .await;
+        self.flush_state().await;
+        result.map(|_| ())
+    }
+
+    /// 续传起点：返回开始下载的内存段和传输断点。
+    /// 不能继续传输时重新擦除中断内存段所在的擦除范围，并从该范围内的第一个内存段开始
+    async fn resume_point(
+        &self,
+        service: &mut UdsService,
+        segments: &[&MemorySegment],
+    ) -> UdsResult<(usize, Option<TransferCheckpoint>)> {
+        let Some(state) = &self.resume else {
+            return Ok((0, None));
+        };
+        if state.download_complete {
+            return Ok((segments.len(), None));
+        }
+        let index = segments
+            .iter()
+            .position(|s| s.address == state.segment_address)
+            .ok_or_else(|| {
+                UdsError::InvalidParameter(format!(
+                    "Resume address 0x{:X} is not in the download list",
+                    state.segment_address
+                ))
+            })?;
+
+        // 补丁模式下中断块的参考数据已被部分覆盖，只能擦除后整块重写
+        let patch_mode = self
+            .config
+            .delta
+            .as_ref()
+            .is_some_and(|d| d.patch.is_some());
+        if let (ResumeMode::ContinueBlock, Some(checkpoint), false) =
+            (self.config.resume_mode, state.checkpoint, patch_mode)
+        {
+            return Ok((index, Some(checkpoint)));
+        }
+
+        let overlaps = |range: &MemoryRange, segment: &MemorySegment| {
+            range.address < segment.end() && segment.address < range.address + range.length
+        };
+        let interrupted = segments[index];
+        let ranges: Vec<MemoryRange> = self
+            .erase_ranges(service)
+            .await?
+            .into_iter()
+            .filter(|r| overlaps(r, interrupted))
+            .collect();
+        let start = segments
+            .iter()
+            .position(|s| ranges.iter().any(|r| overlaps(r, s)))
+            .unwrap_or(index)
+            .min(index);
+
+        let erase_poll = self
+            .config
+            .steps
+            .iter()
+            .find_map(|step| match &step.action {
+                FlashAction::EraseMemory { poll } => Some(poll),
+                _ => None,
+            });
+        if let Some(poll) = erase_poll {
+            log::info!(
+                "Restarting download at segment {} after erasing {} ranges",
+                start,
+                ranges.len()
+            );
+            self.erase(service, &ranges, poll).await?;
+        }
+        Ok((start, None))
+    }
+
+    fn job_state(
+        &self,
+        segment_index: usize,
+        segment_address: u64,
+        checkpoint: Option<TransferCheckpoint>,
+    ) -> FlashJobState {
+        let step_index = self
+            .config
+            .steps
+            .iter()
+            .position(|s| matches!(s.action, FlashAction::Download))
+            .unwrap_or(0);
+        let delta_blocks = self.delta_plan.lock().unwrap().as_ref().map(|plan| {
+            plan.blocks
+                .iter()
+                .map(|b| MemoryRange {
+                    address: b.segment.address,
+                    length: b.segment.data.len() as u64,
+                })
+                .collect()
+        });
+        FlashJobState {
+            delta_blocks,
+            ..FlashJobState::new(
+                self.image_digest,
+                step_index,
+                segment_index,
+                segment_address,
+                checkpoint,
+            )
+        }
+    }
+
+    /// 立即保存断点，写入失败只记录日志
+    async fn save_state(
+        &self,
+        segment_index: usize,
+        segment_address: u64,
+        checkpoint: Option<TransferCheckpoint>,
+    ) {
+        if self.state_store.is_none() {
+            return;
+        }
+        let state = self.job_state(segment_index, segment_address, checkpoint);
+        self.store_state(state).await;
+    }
+
+    async fn store_state(&self, state: FlashJobState) {
+        if self.state_store.is_none() {
+            return;
+        }
+        self.checkpoints.lock().unwrap().pending = Some(state);
+        self.flush_state().await;
+    }
+
+    /// 记录数据块断点，距上次写入达到块数或时间间隔且没有进行中的写入时后台写入
+    fn record_checkpoint(
+        &self,
+        segment_index: usize,
+        segment_address: u64,
+        checkpoint: TransferCheckpoint,
+    ) {
+        let Some(store) = &self.state_store else {
+            return;
+        };
+        let state = self.job_state(segment_index, segment_address, Some(checkpoint));
+        let mut writer = self.checkpoints.lock().unwrap();
+        writer.pending = Some(state);
+        writer.blocks += 1;
+
+        let due = writer.blocks >= CHECKPOINT_INTERVAL_BLOCKS
+            || writer
+                .last_write
+                .is_none_or(|t| t.elapsed() >= CHECKPOINT_INTERVAL);
+        let idle = writer.writing.as_ref().is_none_or(|w| w.is_finished());
+        if due && idle {
+            if let Some(state) = writer.pending.take() {
+                writer.writing = Some(spawn_save(store.clone(), state));
+                writer.blocks = 0;
+                writer.last_write = Some(Instant::now());
+            }
+        }
+    }
+
+    /// 等待进行中的写入完成，并写入尚未保存的最新断点
+    async fn flush_state(&self) {
+        let Some(store) = &self.state_store else {
+            return;
+        };
+        let writing = self.checkpoints.lock().unwrap().writing.take();
+        if let Some(writing) = writing {
+            let _ = writing.await;
+        }
+        let pending = {
+            let mut writer = self.checkpoints.lock().unwrap();
+            writer.blocks = 0;
+            writer.last_write = Some(Instant::now());
+            writer.pending.take()
+        };
+        if let Some(state) = pending {
+            let _ = spawn_save(store.clone(), state).await;
+        }
+    }
+
+    /// 擦除范围：差分刷写时为变化块，否则为文件提供的范围或各内存段
+    async fn erase_ranges(&self, service: &mut UdsService) -> UdsResult<Vec<MemoryRange>> {
+        Ok(
+            match (self.delta_plan(service).await?, &self.erase_ranges) {
+                (Some(plan), _) => plan.erase_ranges(),
+                (None, Some(ranges)) => ranges.clone(),
+                (None, None) => self
+                    .segments
+                    .iter()
+                    .map(|s| MemoryRange {
+                        address: s.address,
+                        length: s.data.len() as u64,
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    async fn erase(
+        &self,
+        service: &mut UdsService,
+        ranges: &[MemoryRange],
+        poll: &RoutinePollConfig,
+    ) -> UdsResult<()> {
+        for range in ranges {
+            let option_record = self.range_option_record(range.address, range.length)?;
+            service
+                .run_routine(CommonRoutines::ERASE_MEMORY, &option_record, poll)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// 差分刷写计划，首次使用时读取参考数据并计算
+    async fn delta_plan(&self, service: &mut UdsService) -> UdsResult<Option<Arc<DeltaPlan>>> {
+        let Some(options) = &self.config.delta else {
+            return Ok(None);
+        };
+        let cached = self.delta_plan.lock().unwrap().clone();
+        if cached.is_some() {
+            return Ok(cached);
+        }
+
+        let source = self.reference.as_ref().ok_or_else(|| {
+            UdsError::InvalidParameter("Delta flashing requires a reference source".to_string())
+        })?;
+        let mut reference = Vec::new();
+        let plan = match self.resume.as_ref() {
+            // 续传时沿用中断前的计划，ECU 内容已部分写入，不能重新比较。
+            // 补丁模式下只读取尚未开始下载的块的参考数据
+            Some(state) => {
+                let blocks = state.delta_blocks.as_deref().unwrap_or_default();
+                if options.patch.is_some() && !state.download_complete {
+                    for block in blocks.iter().filter(|b| b.address > state.segment_address) {
+                        reference.extend(source.read(service, *block).await?);
+                    }
+                }
+                restore_delta(&self.segments, blocks, &reference, options)?
+            }
+            None => {
+                for segment in &self.segments {
+                    let range = MemoryRange {
+                        address: segment.address,
+                        length: segment.data.len() as u64,
+                    };
+                    reference.extend(source.read(service, range).await?);
+                }
+                compute_delta(&self.segments, &reference, options)
+            }
+        };
+
+        let plan = Arc::new(plan);
+        log::info!(
+            "Delta flashing: {} of {} bytes changed in {} blocks{}",
+            plan.changed_bytes(),
+            plan.total_bytes,
+            plan.blocks.len(),
+            if plan.patch.is_some() {
+                " (patch mode)"
+            } else {
+                ""
+            }
+        );
+        *self.delta_plan.lock().unwrap() = Some(plan.clone());
+        Ok(Some(plan))
+    }
+
+    /// 内存区域的例程选项：ALFID + 地址 + 长度
+    fn range_option_record(&self, address: u64, length: u64) -> UdsResult<Vec<u8>> {
+        AddressAndLengthFormat::from_identifier(self.config.download.address_and_length_format)?
+            .encode(address, length)
+    }
+}
+
+/// 在阻塞线程池中写入断点文件，写入失败只记录日志
+fn spawn_save(store: FlashStateStore, state: FlashJobState) -> JoinHandle<()> {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = store.save(&state) {
+            log::error!("Failed to save flash state: {}", e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{crc32, ChecksumAlgorithm};
+    use crate::data_format::CompressionMethod;
+    use crate::delta::{DeltaPatch, EcuReadMethod, EcuReference, ImageReference, XorDelta};
+    use crate::ecu_simulator::{
+        fast_timing, flash_handler, flash_response, memory_handler, recording, request_log,
+        sim_memory, EcuSimulator, RequestLog, SimAction, SimMemory,
+    };
+    use crate::types::MemoryAccessOptions;
+
+    fn segments() -> Vec<MemorySegment> {
+        vec![
+            MemorySegment::new(0x0800_0000, vec![0xAA; 600]),
+            MemorySegment::new(0x0801_0000, vec![0x55; 100]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_standard_flash_sequence() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let job = FlashJob::new(FlashJobConfig::default(), segments());
+        let mut progress = Vec::new();
+        job.run(&mut service, |p| progress.push(p)).await.unwrap();
+
+        let sids: Vec<u8> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| r[0])
+            .filter(|&sid| sid != 0x36)
+            .collect();
+        assert_eq!(
+            sids,
+            vec![
+                0x10, 0x31, 0x85, 0x28, 0x10, 0x27, 0x27, 0x31, 0x31, 0x34, 0x37, 0x34, 0x37, 0x31,
+                0x31, 0x31, 0x11
+            ]
+        );
+
+        let last = progress.last().unwrap();
+        assert_eq!(last.state, FlashStepState::Completed);
+        assert_eq!(last.percent, 100.0);
+        let download = progress
+            .iter()
+            .filter_map(|p| p.download.as_ref())
+            .next_back()
+            .unwrap();
+        assert_eq!(download.transferred_bytes, 700);
+        assert_eq!(download.total_bytes, 700);
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_runs_rollback() {
+        let requests = request_log();
+        // 擦除内存例程被拒绝
+        let failing = Some(vec![0x31, 0x01, 0xFF, 0x00]);
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), failing)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let job = FlashJob::new(FlashJobConfig::default(), segments());
+        let mut states = Vec::new();
+        let err = job
+            .run(&mut service, |p| states.push(p.state))
+            .await
+            .unwrap_err();
+        assert!(err.nrc().is_some());
+
+        let requests = requests.lock().unwrap();
+        let tail: Vec<&[u8]> = requests[requests.len() - 3..]
+            .iter()
+            .map(|r| r.as_slice())
+            .collect();
+        assert_eq!(
+            tail,
+            vec![&[0x28, 0x00, 0x03][..], &[0x85, 0x01], &[0x10, 0x01]]
+        );
+        assert!(!requests.iter().any(|r| r[0] == 0x34));
+        assert_eq!(
+            states
+                .iter()
+                .filter(|&&s| s == FlashStepState::RollingBack)
+                .count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort_stops_download() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let job = FlashJob::new(FlashJobConfig::default(), segments());
+        let abort = job.abort_handle();
+        let err = job
+            .run(&mut service, |p| {
+                // 第一个数据块完成后请求中止
+                if p.download.is_some() {
+                    abort.store(true, Ordering::SeqCst);
+                }
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::Aborted));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.iter().filter(|r| r[0] == 0x36).count(), 1);
+        assert!(!requests.iter().any(|r| r[0] == 0x37));
+        assert_eq!(requests.last().unwrap(), &vec![0x10, 0x01]);
+    }
+
+    #[tokio::test]
+    async fn test_step_timeout() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let config = FlashJobConfig {
+            steps: vec![FlashStep::new(
+                "慢速例程",
+                FlashAction::Routine {
+                    routine_id: 0x0203,
+                    option_record: Vec::new(),
+                    // 例程一直处于运行中
+                    poll: RoutinePollConfig {
+                        in_progress_info: vec![0x00],
+                        interval_ms: 10,
+                        ..RoutinePollConfig::default()
+                    },
+                },
+                Some(100),
+            )],
+            rollback: Vec::new(),
+            download: DownloadOptions::default(),
+            delta: None,
+            resume_mode: ResumeMode::default(),
+            verification: VerificationConfig::default(),
+        };
+        let err = FlashJob::new(config, Vec::new())
+            .run(&mut service, |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::StepTimeout(name) if name == "慢速例程"));
+    }
+
+    #[tokio::test]
+    async fn test_erase_ranges_and_checksums_from_file() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let poll = RoutinePollConfig::default();
+        let config = FlashJobConfig {
+            steps: vec![
+                FlashStep::new(
+                    "擦除",
+                    FlashAction::EraseMemory { poll: poll.clone() },
+                    None,
+                ),
+                FlashStep::new(
+                    "校验",
+                    FlashAction::CheckMemory {
+                        routine_id: CommonRoutines::CHECK_MEMORY,
+                        poll,
+                    },
+                    None,
+                ),
+            ],
+            rollback: Vec::new(),
+            download: DownloadOptions::default(),
+            delta: None,
+            resume_mode: ResumeMode::default(),
+            verification: VerificationConfig::default(),
+        };
+        let job = FlashJob::new(config, vec![MemorySegment::new(0x8000, vec![0x01; 4])])
+            .with_erase_ranges(vec![MemoryRange {
+                address: 0x8000,
+                length: 0x10000,
+            }])
+            .with_segment_checksums(vec![vec![0xBE, 0xEF]]);
+        job.run(&mut service, |_| {}).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(
+            requests[0],
+            vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x00, 0x00, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00]
+        );
+        assert_eq!(
+            requests[1],
+            vec![
+                0x31, 0x01, 0x02, 0x02, 0x44, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x04, 0xBE,
+                0xEF
+            ]
+        );
+    }
+
+    /// 只包含擦除、下载和校验步骤的差分刷写配置
+    fn delta_config(patch: Option<DeltaPatch>) -> FlashJobConfig {
+        let poll = RoutinePollConfig::default();
+        FlashJobConfig {
+            steps: vec![
+                FlashStep::new(
+                    "擦除",
+                    FlashAction::EraseMemory { poll: poll.clone() },
+                    None,
+                ),
+                FlashStep::new("下载", FlashAction::Download, None),
+                FlashStep::new(
+                    "校验",
+                    FlashAction::CheckMemory {
+                        routine_id: CommonRoutines::CHECK_MEMORY,
+                        poll,
+                    },
+                    None,
+                ),
+            ],
+            rollback: Vec::new(),
+            download: DownloadOptions::default(),
+            delta: Some(DeltaOptions {
+                block_size: 0x100,
+                merge_gap: 0,
+                patch,
+            }),
+            resume_mode: ResumeMode::default(),
+            verification: VerificationConfig::default(),
+        }
+    }
+
+    fn delta_segments() -> (Vec<MemorySegment>, Vec<MemorySegment>) {
+        let reference = vec![MemorySegment::new(0x8000, vec![0x11; 0x400])];
+        let mut data = vec![0x11; 0x400];
+        data[0x180] = 0x22;
+        (vec![MemorySegment::new(0x8000, data)], reference)
+    }
+
+    #[tokio::test]
+    async fn test_delta_flash_downloads_changed_blocks() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let (segments, reference) = delta_segments();
+        let job = FlashJob::new(delta_config(None), segments)
+            .with_reference(Box::new(ImageReference::new(reference)));
+        let mut progress = Vec::new();
+        job.run(&mut service, |p| progress.extend(p.download))
+            .await
+            .unwrap();
+
+        let requests = requests.lock().unwrap();
+        // 只擦除和下载 0x8100 开始的一个块，校验仍覆盖整个内存段
+        assert_eq!(
+            requests[0],
+            vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(
+            requests[1],
+            vec![0x34, 0x00, 0x44, 0x00, 0x00, 0x81, 0x00, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(
+            requests.last().unwrap()[4..],
+            [0x44, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x04, 0x00]
+        );
+        let download = progress.last().unwrap();
+        assert_eq!(download.total_bytes, 0x100);
+        assert_eq!(download.transferred_bytes, 0x100);
+    }
+
+    #[tokio::test]
+    async fn test_delta_patch_mode() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let (segments, reference) = delta_segments();
+        let job = FlashJob::new(delta_config(Some(DeltaPatch { method_id: 0x3 })), segments)
+            .with_reference(Box::new(ImageReference::new(reference)));
+        job.run(&mut service, |_| {}).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        // 补丁模式不擦除，由引导程序应用补丁
+        assert!(!requests
+            .iter()
+            .any(|r| r.starts_with(&[0x31, 0x01, 0xFF, 0x00])));
+        assert_eq!(requests[0][1], 0x30);
+
+        let patch: Vec<u8> = requests
+            .iter()
+            .filter(|r| r[0] == 0x36)
+            .flat_map(|r| r[2..].to_vec())
+            .collect();
+        let mut expected = vec![0x11; 0x100];
+        expected[0x80] = 0x22;
+        let codec = XorDelta::new(0x3, vec![0x11; 0x100]);
+        assert_eq!(codec.decompress(&patch).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_delta_without_reference_fails() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let (segments, _) = delta_segments();
+        let err = FlashJob::new(delta_config(None), segments)
+            .run(&mut service, |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::InvalidParameter(_)));
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    /// 在第一个内存段的第 3 个数据块处中断，返回保存的断点
+    async fn interrupted_state(store: &FlashStateStore) -> FlashJobState {
+        let requests = request_log();
+        let failing = Some(vec![0x36, 0x03]);
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), failing)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let job =
+            FlashJob::new(FlashJobConfig::default(), segments()).with_state_store(store.clone());
+        job.run(&mut service, |_| {}).await.unwrap_err();
+        store.load().unwrap().unwrap()
+    }
+
+    fn temp_store(name: &str) -> FlashStateStore {
+        FlashStateStore::new(std::env::temp_dir().join(format!(
+            "uni-diag-{}-{}.json",
+            name,
+            std::process::id()
+        )))
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_writes_are_throttled() {
+        let store = temp_store("checkpoint-throttle");
+        let job =
+            FlashJob::new(FlashJobConfig::default(), segments()).with_state_store(store.clone());
+        job.save_state(0, 0x0800_0000, None).await;
+
+        let checkpoint = |counter: u8| TransferCheckpoint {
+            block_sequence_counter: counter,
+            confirmed_bytes: counter as usize * 0x100,
+            max_block_length: 0x102,
+        };
+        // 间隔内的数据块只记录不写入
+        for counter in 1..CHECKPOINT_INTERVAL_BLOCKS as u8 {
+            job.record_checkpoint(0, 0x0800_0000, checkpoint(counter));
+        }
+        assert_eq!(store.load().unwrap().unwrap().checkpoint, None);
+
+        job.record_checkpoint(0, 0x0800_0000, checkpoint(CHECKPOINT_INTERVAL_BLOCKS as u8));
+        job.record_checkpoint(0, 0x0800_0000, checkpoint(0x21));
+        job.flush_state().await;
+        assert_eq!(
+            store.load().unwrap().unwrap().checkpoint,
+            Some(checkpoint(0x21))
+        );
+        store.clear().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_resume_continues_after_confirmed_block() {
+        let store = temp_store("resume-continue");
+        let state = interrupted_state(&store).await;
+        assert_eq!(state.step_index, 7);
+        assert_eq!(state.segment_index, 0);
+        assert_eq!(
+            state.checkpoint,
+            Some(TransferCheckpoint {
+                block_sequence_counter: 0x02,
+                confirmed_bytes: 512,
+                max_block_length: 0x102,
+            })
+        );
+
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+        let config = FlashJobConfig {
+            resume_mode: ResumeMode::ContinueBlock,
+            ..FlashJobConfig::default()
+        };
+        let job = FlashJob::new(config, segments())
+            .with_state_store(store.clone())
+            .with_resume_state(state);
+        job.run(&mut service, |_| {}).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        let sids: Vec<u8> = requests.iter().map(|r| r[0]).collect();
+        // 只重新建立会话和安全访问，不再检查预条件和擦除
+        assert_eq!(
+            sids,
+            vec![
+                0x10, 0x85, 0x28, 0x10, 0x27, 0x27, 0x36, 0x37, 0x34, 0x36, 0x37, 0x31, 0x31, 0x31,
+                0x11
+            ]
+        );
+        assert_eq!(requests[6][1], 0x03);
+        assert_eq!(requests[6].len(), 2 + 88);
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_resume_restarts_interrupted_segment() {
+        let store = temp_store("resume-restart");
+        let state = interrupted_state(&store).await;
+
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+        let job = FlashJob::new(FlashJobConfig::default(), segments())
+            .with_state_store(store.clone())
+            .with_resume_state(state.clone());
+        job.run(&mut service, |_| {}).await.unwrap();
+
+        {
+            let requests = requests.lock().unwrap();
+            // 重新擦除并下载第一个内存段
+            assert_eq!(
+                requests[6],
+                vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x58]
+            );
+            assert_eq!(requests[7][0], 0x34);
+            assert_eq!(requests[8][..2], [0x36, 0x01]);
+            assert_eq!(requests.iter().filter(|r| r[0] == 0x34).count(), 2);
+        }
+
+        // 刷写数据变化后拒绝续传
+        let mut changed = segments();
+        changed[1].data[0] = 0x00;
+        let err = FlashJob::new(FlashJobConfig::default(), changed)
+            .with_resume_state(state)
+            .run(&mut service, |_| {})
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::InvalidParameter(_)));
+    }
+
+    /// 按地址读（0x23）返回模拟内存内容，下载的数据写入模拟内存，以 failing 开头的请求返回否定响应
+    fn ecu_memory_handler(
+        memory: SimMemory,
+        requests: RequestLog,
+        failing: Option<Vec<u8>>,
+    ) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+        let mut read = memory_handler(memory.clone(), usize::MAX);
+        let mut cursor = 0usize;
+        recording(requests, move |request| {
+            if failing.as_deref().is_some_and(|f| request.starts_with(f)) {
+                return vec![SimAction::Respond(vec![0x7F, request[0], 0x22])];
+            }
+            match request {
+                [0x23, ..] => return read(request),
+                [0x34, _, 0x44, a0, a1, a2, a3, ..] => {
+                    let base = memory.lock().unwrap().address;
+                    cursor = (u32::from_be_bytes([*a0, *a1, *a2, *a3]) as u64 - base) as usize;
+                }
+                [0x36, _, data @ ..] => {
+                    memory.lock().unwrap().data[cursor..cursor + data.len()].copy_from_slice(data);
+                    cursor += data.len();
+                }
+                _ => {}
+            }
+            vec![SimAction::Respond(flash_response(request))]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resume_delta_with_ecu_reference() {
+        let store = temp_store("resume-delta");
+        let memory = sim_memory(MemorySegment::new(0x8000, vec![0x11; 0x400]));
+        let mut data = vec![0x11; 0x400];
+        data[0x100..0x200].fill(0x22);
+        data[0x300..0x400].fill(0x33);
+        let segments = vec![MemorySegment::new(0x8000, data.clone())];
+        let reference = || {
+            Box::new(EcuReference::new(EcuReadMethod::ReadMemory(
+                MemoryAccessOptions::default(),
+            )))
+        };
+
+        // 第一个变化块写入后，请求下载第二个变化块时中断
+        let requests = request_log();
+        let failing = vec![0x34, 0x00, 0x44, 0x00, 0x00, 0x83];
+        let sim =
+            EcuSimulator::start(ecu_memory_handler(memory.clone(), requests, Some(failing))).await;
+        let mut service = sim.connect(fast_timing()).await;
+        FlashJob::new(delta_config(None), segments.clone())
+            .with_reference(reference())
+            .with_state_store(store.clone())
+            .run(&mut service, |_| {})
+            .await
+            .unwrap_err();
+        let state = store.load().unwrap().unwrap();
+        let block = |address| MemoryRange {
+            address,
+            length: 0x100,
+        };
+        assert_eq!(state.delta_blocks, Some(vec![block(0x8100), block(0x8300)]));
+        assert_eq!((state.segment_index, state.segment_address), (1, 0x8300));
+        assert!(!state.download_complete);
+
+        // ECU 上第一个块已是新数据，重新比较只剩一个块，续传须沿用保存的计划
+        let requests = request_log();
+        let sim =
+            EcuSimulator::start(ecu_memory_handler(memory.clone(), requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+        let job = FlashJob::new(delta_config(None), segments)
+            .with_reference(reference())
+            .with_state_store(store.clone())
+            .with_resume_state(state);
+        let report = job.run(&mut service, |_| {}).await.unwrap();
+        assert_eq!(report.downloaded_bytes, 0x200);
+
+        let requests = requests.lock().unwrap();
+        assert!(!requests.iter().any(|r| r[0] == 0x23));
+        assert_eq!(
+            requests[0],
+            vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x00, 0x00, 0x83, 0x00, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(
+            requests[1],
+            vec![0x34, 0x00, 0x44, 0x00, 0x00, 0x83, 0x00, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(requests.iter().filter(|r| r[0] == 0x34).count(), 1);
+        assert_eq!(memory.lock().unwrap().data, data);
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_verification_before_transfer() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let mut config = FlashJobConfig {
+            verification: VerificationConfig {
+                checksum: Some(ChecksumAlgorithm::Crc32),
+                expected_checksums: vec![
+                    "00000000".to_string(),
+                    hex::encode(crc32(&[0x55; 100]).to_be_bytes()),
+                ],
+                signature: None,
+            },
+            ..FlashJobConfig::default()
+        };
+        let err = FlashJob::new(config.clone(), segments())
+            .run(&mut service, |_| {})
+            .await
+            .unwrap_err();
+        // 校验失败时错误中附带校验报告
+        let UdsError::ImageVerificationFailed(failed) = err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert_eq!(failed.segments[0].matched, Some(false));
+        assert_eq!(failed.failures().len(), 1);
+        assert!(requests.lock().unwrap().is_empty());
+
+        let expected = crc32(&[0xAA; 600]).to_be_bytes();
+        config.verification.expected_checksums[0] = hex::encode(expected);
+        let report = FlashJob::new(config, segments())
+            .run(&mut service, |_| {})
+            .await
+            .unwrap();
+        assert!(report.verification.passed());
+        assert_eq!(report.verification.segments.len(), 2);
+        assert_eq!(report.downloaded_bytes, 700);
+
+        // 校验内存例程携带 CRC32 校验值
+        let requests = requests.lock().unwrap();
+        let check = requests
+            .iter()
+            .find(|r| r.starts_with(&[0x31, 0x01, 0x02, 0x02]))
+            .unwrap();
+        assert_eq!(check[check.len() - 4..], expected);
+    }
+}
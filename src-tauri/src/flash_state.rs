@@ -0,0 +1,144 @@
+/**
+ * 刷写断点保存
+ * 下载过程中记录当前内存段、块序号和已确认字节数，
+ * 连接中断后据此续传或重新刷写当前内存段
+ */
+use crate::checksum::crc32;
+use crate::types::{MemoryRange, MemorySegment, TransferCheckpoint};
+use crate::utils::get_timestamp;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 刷写断点状态
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlashJobState {
+    pub image_digest: u32,    // 刷写数据摘要，续传时校验数据未变化
+    pub step_index: usize,    // 下载步骤在步骤列表中的位置
+    pub segment_index: usize, // 正在下载的块在下载列表中的位置，仅用于显示
+    pub segment_address: u64, // 正在下载的块地址，续传按地址定位
+    pub checkpoint: Option<TransferCheckpoint>,
+    #[serde(default)]
+    pub delta_blocks: Option<Vec<MemoryRange>>, // 差分刷写计划的下载块，续传时按此重建计划
+    #[serde(default)]
+    pub download_complete: bool,
+    pub updated_at: String,
+}
+
+impl FlashJobState {
+    pub fn new(
+        image_digest: u32,
+        step_index: usize,
+        segment_index: usize,
+        segment_address: u64,
+        checkpoint: Option<TransferCheckpoint>,
+    ) -> Self {
+        Self {
+            image_digest,
+            step_index,
+            segment_index,
+            segment_address,
+            checkpoint,
+            delta_blocks: None,
+            download_complete: false,
+            updated_at: get_timestamp(),
+        }
+    }
+}
+
+/// 刷写数据摘要：各内存段地址和数据 CRC32 的 CRC32
+pub fn image_digest(segments: &[MemorySegment]) -> u32 {
+    let mut summary = Vec::with_capacity(segments.len() * 12);
+    for segment in segments {
+        summary.extend_from_slice(&segment.address.to_be_bytes());
+        summary.extend_from_slice(&crc32(&segment.data).to_be_bytes());
+    }
+    crc32(&summary)
+}
+
+/// 刷写断点文件
+#[derive(Debug, Clone)]
+pub struct FlashStateStore {
+    path: PathBuf,
+}
+
+impl FlashStateStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// 读取断点，文件不存在时返回 None
+    pub fn load(&self) -> io::Result<Option<FlashJobState>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 保存断点，先写临时文件再替换，避免中断时留下不完整的文件
+    pub fn save(&self, state: &FlashJobState) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let text = serde_json::to_string_pretty(state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, text)?;
+        fs::rename(&temp, &self.path)
+    }
+
+    /// 删除断点
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("uni-diag-state-{}", std::process::id()));
+        let store = FlashStateStore::new(dir.join("flash_state.json"));
+        assert_eq!(store.load().unwrap(), None);
+
+        let state = FlashJobState::new(
+            0x1234_5678,
+            7,
+            1,
+            0x0801_0000,
+            Some(TransferCheckpoint {
+                block_sequence_counter: 0x05,
+                confirmed_bytes: 1280,
+                max_block_length: 258,
+            }),
+        );
+        store.save(&state).unwrap();
+        assert_eq!(store.load().unwrap(), Some(state));
+
+        store.clear().unwrap();
+        store.clear().unwrap();
+        assert_eq!(store.load().unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_image_digest() {
+        let segments = vec![MemorySegment::new(0x1000, vec![0x01, 0x02])];
+        let moved = vec![MemorySegment::new(0x2000, vec![0x01, 0x02])];
+        let changed = vec![MemorySegment::new(0x1000, vec![0x01, 0x03])];
+        assert_eq!(image_digest(&segments), image_digest(&segments.clone()));
+        assert_ne!(image_digest(&segments), image_digest(&moved));
+        assert_ne!(image_digest(&segments), image_digest(&changed));
+    }
+}
@@ -0,0 +1,255 @@
+/**
+ * 刷写文件生成
+ * 将内存段列表输出为 Intel HEX（扩展线性地址记录）或 Motorola S-record（按地址宽度选择 S1/S2/S3），
+ * 供内存转储和镜像编辑保存使用
+ */
+use crate::flash_image::{FlashImage, ImageFormat};
+use crate::types::{ImageError, ImageResult, MemorySegment};
+use std::path::Path;
+
+/// 刷写文件生成器
+#[derive(Debug, Clone, Copy)]
+pub struct ImageWriter {
+    format: ImageFormat,
+    record_length: usize,
+}
+
+impl ImageWriter {
+    /// 默认每条数据记录 16 字节
+    pub fn new(format: ImageFormat) -> Self {
+        Self {
+            format,
+            record_length: 16,
+        }
+    }
+
+    /// 设置每条数据记录的最大字节数，超出格式上限时取上限
+    pub fn with_record_length(mut self, record_length: usize) -> Self {
+        self.record_length = record_length.max(1);
+        self
+    }
+
+    /// 生成文件内容
+    pub fn write(&self, image: &FlashImage) -> ImageResult<String> {
+        match self.format {
+            ImageFormat::IntelHex => self.write_intel_hex(image),
+            ImageFormat::SRecord => self.write_srecord(image),
+        }
+    }
+
+    /// 生成并写入文件
+    pub fn save<P: AsRef<Path>>(&self, path: P, image: &FlashImage) -> ImageResult<()> {
+        std::fs::write(path, self.write(image)?)?;
+        Ok(())
+    }
+
+    fn write_intel_hex(&self, image: &FlashImage) -> ImageResult<String> {
+        check_address_range(image, 0xFFFF_FFFF)?;
+        let record_length = self.record_length.min(0xFF);
+
+        let mut text = String::new();
+        let mut upper = None;
+        for segment in &image.segments {
+            // 数据记录不跨越 64 KB 边界，地址高 16 位变化时插入扩展线性地址记录
+            for (address, data) in records(segment, record_length, Some(0x1_0000)) {
+                if upper != Some(address >> 16) {
+                    upper = Some(address >> 16);
+                    text.push_str(&hex_record(
+                        0x04,
+                        0x0000,
+                        &((address >> 16) as u16).to_be_bytes(),
+                    ));
+                }
+                text.push_str(&hex_record(0x00, address as u16, data));
+            }
+        }
+        if let Some(entry_point) = image.entry_point {
+            text.push_str(&hex_record(
+                0x05,
+                0x0000,
+                &(entry_point as u32).to_be_bytes(),
+            ));
+        }
+        text.push_str(&hex_record(0x01, 0x0000, &[]));
+        Ok(text)
+    }
+
+    fn write_srecord(&self, image: &FlashImage) -> ImageResult<String> {
+        check_address_range(image, 0xFFFF_FFFF)?;
+        let max_address = image
+            .segments
+            .iter()
+            .map(|s| s.end().saturating_sub(1))
+            .chain(image.entry_point)
+            .max()
+            .unwrap_or(0);
+        // 数据记录类型、地址字节数和对应的结束记录类型
+        let (data_type, address_len, end_type) = match max_address {
+            0..=0xFFFF => (1, 2, 9),
+            0x1_0000..=0xFF_FFFF => (2, 3, 8),
+            _ => (3, 4, 7),
+        };
+        let record_length = self.record_length.min(0xFF - address_len - 1);
+
+        let mut text = s_record(0, &[0; 2], &[]);
+        let mut count = 0u64;
+        for segment in &image.segments {
+            for (address, data) in records(segment, record_length, None) {
+                text.push_str(&s_record(
+                    data_type,
+                    &address_bytes(address, address_len),
+                    data,
+                ));
+                count += 1;
+            }
+        }
+        // 数据记录计数，超出 S6 范围时省略
+        match count {
+            0..=0xFFFF => text.push_str(&s_record(5, &address_bytes(count, 2), &[])),
+            0x1_0000..=0xFF_FFFF => text.push_str(&s_record(6, &address_bytes(count, 3), &[])),
+            _ => {}
+        }
+        let entry_point = image.entry_point.unwrap_or(0);
+        text.push_str(&s_record(
+            end_type,
+            &address_bytes(entry_point, address_len),
+            &[],
+        ));
+        Ok(text)
+    }
+}
+
+/// 按记录长度切分内存段，boundary 不为 None 时记录不跨越该对齐边界
+fn records(
+    segment: &MemorySegment,
+    record_length: usize,
+    boundary: Option<u64>,
+) -> Vec<(u64, &[u8])> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < segment.data.len() {
+        let address = segment.address + offset as u64;
+        let mut len = record_length.min(segment.data.len() - offset);
+        if let Some(boundary) = boundary {
+            len = len.min((boundary - address % boundary) as usize);
+        }
+        records.push((address, &segment.data[offset..offset + len]));
+        offset += len;
+    }
+    records
+}
+
+fn check_address_range(image: &FlashImage, max_address: u64) -> ImageResult<()> {
+    let out_of_range = image
+        .segments
+        .iter()
+        .map(|s| s.end().saturating_sub(1))
+        .chain(image.entry_point)
+        .find(|&address| address > max_address);
+    match out_of_range {
+        Some(address) => Err(ImageError::AddressOutOfRange(address)),
+        None => Ok(()),
+    }
+}
+
+fn address_bytes(address: u64, len: usize) -> Vec<u8> {
+    address.to_be_bytes()[8 - len..].to_vec()
+}
+
+/// Intel HEX 记录：长度、地址、类型、数据，校验和使所有字节之和为 0
+fn hex_record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |acc, &b| acc.wrapping_add(b))
+        .wrapping_neg();
+    format!(":{}{:02X}\n", hex::encode_upper(&bytes), checksum)
+}
+
+/// S-record 记录：计数、地址、数据，校验和为三者之和的反码
+fn s_record(record_type: u8, address: &[u8], data: &[u8]) -> String {
+    let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+    bytes.extend_from_slice(address);
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    format!(
+        "S{}{}{:02X}\n",
+        record_type,
+        hex::encode_upper(&bytes),
+        checksum
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(segments: Vec<MemorySegment>, entry_point: Option<u64>) -> FlashImage {
+        FlashImage {
+            segments,
+            entry_point,
+        }
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 13 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_intel_hex_round_trip() {
+        // 跨越 64 KB 边界的段和高地址段
+        let original = image(
+            vec![
+                MemorySegment::new(0x0000_FFF0, pattern(100)),
+                MemorySegment::new(0x0800_0000, pattern(300)),
+            ],
+            Some(0x0800_0101),
+        );
+
+        let text = ImageWriter::new(ImageFormat::IntelHex)
+            .with_record_length(32)
+            .write(&original)
+            .unwrap();
+        assert!(text.starts_with(":020000040000FA\n"));
+        assert!(text.contains(":020000040001F9\n"));
+        assert!(text.contains(":020000040800F2\n"));
+        assert!(text.ends_with(":00000001FF\n"));
+        assert_eq!(FlashImage::parse(&text).unwrap(), original);
+    }
+
+    #[test]
+    fn test_srecord_round_trip_by_address_width() {
+        for (address, data_type) in [(0x1000, "S1"), (0x12_0000, "S2"), (0x0800_0000, "S3")] {
+            let original = image(
+                vec![
+                    MemorySegment::new(address, pattern(70)),
+                    MemorySegment::new(address + 0x200, pattern(5)),
+                ],
+                Some(address),
+            );
+
+            let text = ImageWriter::new(ImageFormat::SRecord)
+                .write(&original)
+                .unwrap();
+            let types: Vec<&str> = text.lines().map(|l| &l[..2]).collect();
+            assert_eq!(types.first(), Some(&"S0"));
+            assert_eq!(types.iter().filter(|&&t| t == data_type).count(), 6);
+            assert_eq!(types[types.len() - 2], "S5");
+            assert_eq!(FlashImage::parse(&text).unwrap(), original);
+        }
+    }
+
+    #[test]
+    fn test_address_out_of_range() {
+        let original = image(vec![MemorySegment::new(0xFFFF_FFFF, vec![0, 1])], None);
+        for format in [ImageFormat::IntelHex, ImageFormat::SRecord] {
+            assert!(matches!(
+                ImageWriter::new(format).write(&original),
+                Err(ImageError::AddressOutOfRange(0x1_0000_0000))
+            ));
+        }
+    }
+}
@@ -1,21 +1,66 @@
 // 模块声明
+mod checksum;
+mod data_format;
+mod delta;
+mod did_codec;
 mod doip_client;
+#[cfg(test)]
+mod ecu_simulator;
+mod flash_image;
+mod flash_job;
+mod flash_state;
+mod image_writer;
+mod memory_dump;
+mod memory_view;
+mod multi_flash;
+mod periodic;
 mod ping;
 mod security_algorithm;
+mod traffic;
 mod types;
 mod uds_client_manager;
+mod uds_response;
 mod uds_service;
 mod utils;
+mod vbf;
+mod verification;
 
+use crate::delta::{EcuReadMethod, EcuReference, ImageReference};
+use crate::did_codec::{DidDefinition, PhysicalValue};
+use crate::flash_image::{FlashImage, ImageFormat, ImageOptions};
+use crate::flash_job::{FlashJob, FlashJobConfig, FlashProgress, FLASH_PROGRESS_EVENT};
+use crate::flash_state::{FlashJobState, FlashStateStore};
+use crate::image_writer::ImageWriter;
+use crate::memory_dump::{MemoryDumpConfig, MemoryDumpJob};
+use crate::multi_flash::{
+    EcuFlashProgress, EcuFlashTarget, MultiFlashResult, MultiFlashScheduler, SchedulerConfig,
+    MULTI_FLASH_PROGRESS_EVENT,
+};
+use crate::periodic::{PeriodicSample, PERIODIC_DATA_EVENT};
 use crate::ping::PingResult;
-use crate::types::{ConnectionConfig, DiagnosticResult};
+use crate::traffic::{TrafficEvent, TRAFFIC_EVENT};
+use crate::types::{
+    ConnectionConfig, DiagnosticResult, DownloadOptions, DownloadProgress, DynamicDidDefinition,
+    MemoryAccessOptions, MemorySegment, RoutinePollConfig, UploadProgress, DOWNLOAD_PROGRESS_EVENT,
+    UPLOAD_PROGRESS_EVENT,
+};
 use crate::uds_client_manager::UdsClientManager;
+use crate::vbf::VbfFile;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, Emitter, Manager, State};
 use tokio::sync::Mutex;
 
 // 全局状态管理
 type UdsManagerState = Arc<Mutex<UdsClientManager>>;
+// 刷写中止标志，独立于管理器锁，刷写过程中也可置位
+type FlashAbortState = Arc<AtomicBool>;
+// 进行中的多 ECU 刷写的中止标志，每次调度运行使用独立标志
+type MultiFlashAbortState = Arc<std::sync::Mutex<Vec<Arc<AtomicBool>>>>;
+
+// 退出时等待连接空闲并断开的最长时间，超时后直接退出
+const EXIT_DISCONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
 // Tauri 命令
 #[tauri::command]
@@ -49,6 +94,341 @@ async fn send_uds_command(
     Ok(manager.send_uds_command(&service_id, &data).await)
 }
 
+#[tauri::command]
+async fn send_raw_uds_request(
+    data: String,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.send_raw_request(&data).await)
+}
+
+#[tauri::command]
+async fn run_routine(
+    routine_id: u16,
+    option_record: String,
+    poll: Option<RoutinePollConfig>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .run_routine(routine_id, &option_record, poll.unwrap_or_default())
+        .await)
+}
+
+#[tauri::command]
+async fn download_segment(
+    address: u64,
+    data: String,
+    options: Option<DownloadOptions>,
+    app: AppHandle,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .download_segment(
+            address,
+            &data,
+            options.unwrap_or_default(),
+            |progress: DownloadProgress| {
+                if let Err(e) = app.emit(DOWNLOAD_PROGRESS_EVENT, progress) {
+                    log::error!("Failed to emit download progress: {}", e);
+                }
+            },
+        )
+        .await)
+}
+
+// 输入输出控制（0x2F），会话切换或断开连接时自动交还控制
+#[tauri::command]
+async fn io_control(
+    did: u16,
+    parameter: u8,
+    control_state: Option<String>,
+    enable_mask: Option<String>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .io_control(
+            did,
+            parameter,
+            control_state.as_deref().unwrap_or(""),
+            enable_mask.as_deref().unwrap_or(""),
+        )
+        .await)
+}
+
+// 周期数据（0x2A）：启动或停止周期 DID，ECU 推送的样本以事件形式发送到前端
+#[tauri::command]
+async fn read_periodic(
+    mode: u8,
+    periodic_ids: String,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.read_periodic(mode, &periodic_ids).await)
+}
+
+// 一次请求读取多个 DID，按长度表拆分响应，ECU 拒绝组合请求时逐个读取
+#[tauri::command]
+async fn read_data_identifiers(
+    dids: Vec<u16>,
+    lengths: Option<BTreeMap<u16, usize>>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .read_data_identifiers(&dids, &lengths.unwrap_or_default())
+        .await)
+}
+
+// 写入数据标识符（0x2E）并读回校验，数据为十六进制串
+#[tauri::command]
+async fn write_data_identifier(
+    did: u16,
+    data: String,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.write_data_identifier(did, &data).await)
+}
+
+// 按 DID 定义编码物理值后写入，读回校验并返回解码后的值
+#[tauri::command]
+async fn write_did_value(
+    definition: DidDefinition,
+    value: PhysicalValue,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.write_did_value(&definition, &value).await)
+}
+
+// 动态定义数据标识符（0x2C），会话切换后自动重新定义
+#[tauri::command]
+async fn define_dynamic_did(
+    ddid: u16,
+    definition: DynamicDidDefinition,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.define_dynamic_did(ddid, &definition).await)
+}
+
+// 清除动态 DID，不指定 DDID 时清除全部
+#[tauri::command]
+async fn clear_dynamic_did(
+    ddid: Option<u16>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.clear_dynamic_did(ddid).await)
+}
+
+// ECU 复位后重新定义记录的动态 DID
+#[tauri::command]
+async fn restore_dynamic_dids(
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager.restore_dynamic_dids().await)
+}
+
+// 内存查看器：读取一页内存（0x23），前端按返回的前后页地址翻页
+#[tauri::command]
+async fn read_memory_page(
+    address: u64,
+    length: usize,
+    options: Option<MemoryAccessOptions>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .read_memory_page(address, length, options.unwrap_or_default())
+        .await)
+}
+
+// 内存编辑器：写入内存（0x3D）并读回校验
+#[tauri::command]
+async fn write_memory(
+    address: u64,
+    data: String,
+    options: Option<MemoryAccessOptions>,
+    state: State<'_, UdsManagerState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    Ok(manager
+        .write_memory(address, &data, options.unwrap_or_default())
+        .await)
+}
+
+// 读回内存区域并保存为二进制、Intel HEX 或 S-record 文件，可通过 abort_flash 中止
+#[tauri::command]
+async fn dump_memory(
+    config: MemoryDumpConfig,
+    path: String,
+    app: AppHandle,
+    state: State<'_, UdsManagerState>,
+    abort: State<'_, FlashAbortState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    abort.store(false, Ordering::SeqCst);
+    let job = MemoryDumpJob::new(config).with_abort_flag(abort.inner().clone());
+    Ok(manager
+        .dump_memory(job, &path, move |progress: UploadProgress| {
+            if let Err(e) = app.emit(UPLOAD_PROGRESS_EVENT, progress) {
+                log::error!("Failed to emit upload progress: {}", e);
+            }
+        })
+        .await)
+}
+
+// 刷写内存段，配置差分选项时以 reference 为参考数据只刷写变化的块，
+// 未提供 reference 时可按 ecu_reference 从 ECU 读回参考数据
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn start_flash(
+    config: Option<FlashJobConfig>,
+    segments: Vec<MemorySegment>,
+    reference: Option<Vec<MemorySegment>>,
+    ecu_reference: Option<EcuReadMethod>,
+    resume: Option<bool>,
+    app: AppHandle,
+    state: State<'_, UdsManagerState>,
+    abort: State<'_, FlashAbortState>,
+) -> Result<DiagnosticResult, String> {
+    let mut manager = state.lock().await;
+    abort.store(false, Ordering::SeqCst);
+    let mut job =
+        FlashJob::new(config.unwrap_or_default(), segments).with_abort_flag(abort.inner().clone());
+    if let Some(reference) = reference {
+        job = job.with_reference(Box::new(ImageReference::new(reference)));
+    } else if let Some(method) = ecu_reference {
+        job = job.with_reference(Box::new(EcuReference::new(method)));
+    }
+    let job = with_flash_state(job, resume.unwrap_or(false), &app)?;
+    Ok(manager
+        .run_flash_job(job, flash_progress_emitter(app))
+        .await)
+}
+
+// 使用 VBF 文件中的擦除范围和数据块校验刷写
+#[tauri::command]
+async fn start_vbf_flash(
+    path: String,
+    config: Option<FlashJobConfig>,
+    reference_path: Option<String>,
+    resume: Option<bool>,
+    app: AppHandle,
+    state: State<'_, UdsManagerState>,
+    abort: State<'_, FlashAbortState>,
+) -> Result<DiagnosticResult, String> {
+    let vbf = VbfFile::load(&path).map_err(|e| e.to_string())?;
+    let reference = match reference_path {
+        Some(reference_path) => Some(VbfFile::load(&reference_path).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let mut manager = state.lock().await;
+    abort.store(false, Ordering::SeqCst);
+    let mut job = vbf
+        .to_flash_job(config.unwrap_or_default())
+        .map_err(|e| e.to_string())?
+        .with_abort_flag(abort.inner().clone());
+    if let Some(reference) = reference {
+        job = job.with_reference(Box::new(ImageReference::new(reference.segments())));
+    }
+    let job = with_flash_state(job, resume.unwrap_or(false), &app)?;
+    Ok(manager
+        .run_flash_job(job, flash_progress_emitter(app))
+        .await)
+}
+
+// 通过各自的连接并行刷写多个 ECU，返回各 ECU 结果汇总
+#[tauri::command]
+async fn start_multi_flash(
+    targets: Vec<EcuFlashTarget>,
+    scheduler: Option<SchedulerConfig>,
+    app: AppHandle,
+    runs: State<'_, MultiFlashAbortState>,
+) -> Result<MultiFlashResult, String> {
+    if targets.is_empty() {
+        return Err("没有需要刷写的ECU".to_string());
+    }
+    let abort = Arc::new(AtomicBool::new(false));
+    runs.lock().unwrap().push(abort.clone());
+    let scheduler = MultiFlashScheduler::new(scheduler.unwrap_or_default(), targets)
+        .with_abort_flag(abort.clone());
+    let result = scheduler
+        .run(Arc::new(move |progress: EcuFlashProgress| {
+            if let Err(e) = app.emit(MULTI_FLASH_PROGRESS_EVENT, progress) {
+                log::error!("Failed to emit multi-flash progress: {}", e);
+            }
+        }))
+        .await;
+    runs.lock().unwrap().retain(|run| !Arc::ptr_eq(run, &abort));
+    Ok(result)
+}
+
+// 查询未完成刷写的断点
+#[tauri::command]
+fn get_flash_state(app: AppHandle) -> Result<Option<FlashJobState>, String> {
+    flash_state_store(&app)?.load().map_err(|e| e.to_string())
+}
+
+// 放弃断点，下次刷写从头开始
+#[tauri::command]
+fn clear_flash_state(app: AppHandle) -> Result<(), String> {
+    flash_state_store(&app)?.clear().map_err(|e| e.to_string())
+}
+
+fn flash_state_store(app: &AppHandle) -> Result<FlashStateStore, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(FlashStateStore::new(dir.join("flash_state.json")))
+}
+
+/// 刷写过程中保存断点，resume 为 true 时从保存的断点续传
+fn with_flash_state(job: FlashJob, resume: bool, app: &AppHandle) -> Result<FlashJob, String> {
+    let store = flash_state_store(app)?;
+    let state = if resume {
+        Some(
+            store
+                .load()
+                .map_err(|e| e.to_string())?
+                .ok_or("没有可续传的刷写断点")?,
+        )
+    } else {
+        None
+    };
+
+    let job = job.with_state_store(store);
+    Ok(match state {
+        Some(state) => job.with_resume_state(state),
+        None => job,
+    })
+}
+
+fn flash_progress_emitter(app: AppHandle) -> impl FnMut(FlashProgress) + Send {
+    move |progress: FlashProgress| {
+        if let Err(e) = app.emit(FLASH_PROGRESS_EVENT, progress) {
+            log::error!("Failed to emit flash progress: {}", e);
+        }
+    }
+}
+
+#[tauri::command]
+fn abort_flash(abort: State<'_, FlashAbortState>) {
+    abort.store(true, Ordering::SeqCst);
+}
+
+// 中止所有进行中的多 ECU 刷写，不影响单 ECU 刷写
+#[tauri::command]
+fn abort_multi_flash(runs: State<'_, MultiFlashAbortState>) {
+    for abort in runs.lock().unwrap().iter() {
+        abort.store(true, Ordering::SeqCst);
+    }
+}
+
 #[tauri::command]
 async fn get_connection_config(
     state: State<'_, UdsManagerState>,
@@ -57,6 +437,33 @@ async fn get_connection_config(
     Ok(manager.get_connection_config().cloned())
 }
 
+// 读取刷写文件（Intel HEX / S-record）并按选项裁剪、填充
+#[tauri::command]
+fn load_flash_image(path: String, options: Option<ImageOptions>) -> Result<FlashImage, String> {
+    FlashImage::load(&path, &options.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+// 将内存段保存为 Intel HEX / S-record 文件
+#[tauri::command]
+fn save_flash_image(
+    path: String,
+    image: FlashImage,
+    format: ImageFormat,
+    record_length: Option<usize>,
+) -> Result<(), String> {
+    let mut writer = ImageWriter::new(format);
+    if let Some(record_length) = record_length {
+        writer = writer.with_record_length(record_length);
+    }
+    writer.save(&path, &image).map_err(|e| e.to_string())
+}
+
+// 读取 VBF 文件头部和数据块
+#[tauri::command]
+fn load_vbf(path: String) -> Result<VbfFile, String> {
+    VbfFile::load(&path).map_err(|e| e.to_string())
+}
+
 // 测试安全访问算法的命令
 #[tauri::command]
 fn test_security_access() -> String {
@@ -75,21 +482,76 @@ pub fn run() {
     // 初始化日志
     env_logger::init();
 
-    // 创建全局状态
-    let uds_manager = Arc::new(Mutex::new(UdsClientManager::new()));
-
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .manage(uds_manager)
+        .setup(|app| {
+            // 创建全局状态，收发帧和周期数据以事件形式推送到前端
+            let handle = app.handle().clone();
+            let mut uds_manager = UdsClientManager::new();
+            uds_manager.set_traffic_sink(Arc::new(move |event: TrafficEvent| {
+                if let Err(e) = handle.emit(TRAFFIC_EVENT, event) {
+                    log::error!("Failed to emit traffic event: {}", e);
+                }
+            }));
+            let handle = app.handle().clone();
+            uds_manager.set_periodic_sink(Arc::new(move |sample: PeriodicSample| {
+                if let Err(e) = handle.emit(PERIODIC_DATA_EVENT, sample) {
+                    log::error!("Failed to emit periodic data event: {}", e);
+                }
+            }));
+            app.manage::<UdsManagerState>(Arc::new(Mutex::new(uds_manager)));
+            app.manage::<FlashAbortState>(Arc::new(AtomicBool::new(false)));
+            app.manage::<MultiFlashAbortState>(Default::default());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             connect_ecu,
             disconnect_ecu,
             get_connection_status,
             send_uds_command,
+            send_raw_uds_request,
+            run_routine,
+            io_control,
+            read_periodic,
+            read_data_identifiers,
+            write_data_identifier,
+            write_did_value,
+            define_dynamic_did,
+            clear_dynamic_did,
+            restore_dynamic_dids,
+            download_segment,
+            dump_memory,
+            read_memory_page,
+            write_memory,
+            start_flash,
+            start_vbf_flash,
+            start_multi_flash,
+            abort_flash,
+            abort_multi_flash,
+            get_flash_state,
+            clear_flash_state,
+            load_flash_image,
+            save_flash_image,
+            load_vbf,
             get_connection_config,
             test_security_access,
             ping_host
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // 退出前断开连接，交还接管的输入输出控制；刷写等操作长时间占用连接时放弃等待
+            if let tauri::RunEvent::Exit = event {
+                let state = app.state::<UdsManagerState>().inner().clone();
+                tauri::async_runtime::block_on(async move {
+                    let disconnect = async { state.lock().await.disconnect().await };
+                    if tokio::time::timeout(EXIT_DISCONNECT_TIMEOUT, disconnect)
+                        .await
+                        .is_err()
+                    {
+                        log::warn!("UDS manager busy on exit, skipping disconnect");
+                    }
+                });
+            }
+        });
 }
@@ -0,0 +1,178 @@
+/**
+ * 内存转储
+ * 通过 RequestUpload 读回 ECU 内存区域，保存为原始二进制、Intel HEX 或 S-record 文件，
+ * 用于分析或刷写前备份
+ */
+use crate::checksum::crc32;
+use crate::flash_image::{FlashImage, ImageFormat};
+use crate::image_writer::ImageWriter;
+use crate::types::{MemorySegment, UdsError, UdsResult, UploadOptions, UploadProgress};
+use crate::uds_service::UdsService;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+/// 转储文件格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DumpFormat {
+    #[default]
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+/// 内存转储配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryDumpConfig {
+    pub address: u64,
+    pub length: u64,
+    #[serde(default)]
+    pub format: DumpFormat,
+    #[serde(default)]
+    pub upload: UploadOptions,
+}
+
+/// 内存转储结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryDumpReport {
+    pub address: u64,
+    pub length: u64,
+    pub format: DumpFormat,
+    pub path: String,
+    pub crc32: u32, // 读回数据的 CRC32，便于与刷写文件比对
+}
+
+/// 内存转储任务
+pub struct MemoryDumpJob {
+    config: MemoryDumpConfig,
+    abort: Arc<AtomicBool>,
+}
+
+impl MemoryDumpJob {
+    pub fn new(config: MemoryDumpConfig) -> Self {
+        Self {
+            config,
+            abort: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 使用外部中止标志，置位后在下一个数据块前停止
+    pub fn with_abort_flag(mut self, abort: Arc<AtomicBool>) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// 读回内存区域并写入文件
+    pub async fn run<F>(
+        &self,
+        service: &mut UdsService,
+        path: &Path,
+        on_progress: F,
+    ) -> UdsResult<MemoryDumpReport>
+    where
+        F: FnMut(UploadProgress) + Send,
+    {
+        let length = usize::try_from(self.config.length).map_err(|_| {
+            UdsError::InvalidParameter(format!("Dump length {} is too large", self.config.length))
+        })?;
+        let data = service
+            .upload_segment(
+                self.config.address,
+                length,
+                &self.config.upload,
+                &self.abort,
+                on_progress,
+            )
+            .await?;
+
+        let segment = MemorySegment::new(self.config.address, data);
+        std::fs::write(path, render(&segment, self.config.format)?)?;
+        log::info!(
+            "Dumped {} bytes from 0x{:X} to {}",
+            segment.data.len(),
+            segment.address,
+            path.display()
+        );
+
+        Ok(MemoryDumpReport {
+            address: segment.address,
+            length: self.config.length,
+            format: self.config.format,
+            path: path.display().to_string(),
+            crc32: crc32(&segment.data),
+        })
+    }
+}
+
+/// 按格式生成文件内容
+pub fn render(segment: &MemorySegment, format: DumpFormat) -> UdsResult<Vec<u8>> {
+    let image_format = match format {
+        DumpFormat::Binary => return Ok(segment.data.clone()),
+        DumpFormat::IntelHex => ImageFormat::IntelHex,
+        DumpFormat::SRecord => ImageFormat::SRecord,
+    };
+    let image = FlashImage {
+        segments: vec![segment.clone()],
+        entry_point: None,
+    };
+    ImageWriter::new(image_format)
+        .write(&image)
+        .map(String::into_bytes)
+        .map_err(|e| UdsError::DataFormat(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecu_simulator::{fast_timing, memory_handler, sim_memory, EcuSimulator};
+
+    fn memory() -> MemorySegment {
+        MemorySegment::new(0x0800_0000, (0..0x2000u32).map(|i| (i * 7) as u8).collect())
+    }
+
+    #[tokio::test]
+    async fn test_dump_to_files() {
+        let dir = std::env::temp_dir().join(format!("uni-diag-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let expected = memory().data[0x100..0x100 + 1000].to_vec();
+
+        for (format, file) in [
+            (DumpFormat::Binary, "dump.bin"),
+            (DumpFormat::IntelHex, "dump.hex"),
+            (DumpFormat::SRecord, "dump.s19"),
+        ] {
+            let sim = EcuSimulator::start(memory_handler(sim_memory(memory()), usize::MAX)).await;
+            let mut service = sim.connect(fast_timing()).await;
+            let job = MemoryDumpJob::new(MemoryDumpConfig {
+                address: 0x0800_0100,
+                length: 1000,
+                format,
+                upload: UploadOptions::default(),
+            });
+
+            let path = dir.join(file);
+            let mut progress = Vec::new();
+            let report = job
+                .run(&mut service, &path, |p| progress.push(p.transferred_bytes))
+                .await
+                .unwrap();
+            assert_eq!(progress, vec![256, 512, 768, 1000]);
+            assert_eq!(report.crc32, crc32(&expected));
+
+            let content = std::fs::read(&path).unwrap();
+            match format {
+                DumpFormat::Binary => assert_eq!(content, expected),
+                _ => {
+                    let image = FlashImage::parse(&String::from_utf8(content).unwrap()).unwrap();
+                    assert_eq!(
+                        image.segments,
+                        vec![MemorySegment::new(0x0800_0100, expected.clone())]
+                    );
+                }
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+/**
+ * 内存查看/编辑
+ * 通过 ReadMemoryByAddress / WriteMemoryByAddress 分页读取和修改 ECU 内存，
+ * 按行生成十六进制和 ASCII 显示内容供前端翻页浏览
+ */
+use crate::types::hex_bytes;
+use crate::utils::{bytes_to_ascii, bytes_to_hex};
+use serde::{Deserialize, Serialize};
+
+/// 每行显示的字节数
+pub const BYTES_PER_ROW: usize = 16;
+
+/// 内存查看器中的一行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryRow {
+    pub address: u64,
+    pub hex: String,
+    pub ascii: String,
+}
+
+/// 内存查看器的一页数据
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryPage {
+    pub address: u64,
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+    pub rows: Vec<MemoryRow>,
+    pub previous_address: Option<u64>, // 上一页起始地址，已到地址 0 时为 None
+    pub next_address: Option<u64>,     // 下一页起始地址，超出地址空间时为 None
+}
+
+impl MemoryPage {
+    pub fn new(address: u64, data: Vec<u8>) -> Self {
+        let rows = data
+            .chunks(BYTES_PER_ROW)
+            .enumerate()
+            .map(|(i, chunk)| MemoryRow {
+                address: address + (i * BYTES_PER_ROW) as u64,
+                hex: bytes_to_hex(chunk),
+                ascii: bytes_to_ascii(chunk),
+            })
+            .collect();
+        let length = data.len() as u64;
+        Self {
+            address,
+            rows,
+            previous_address: (address > 0).then(|| address.saturating_sub(length)),
+            next_address: address.checked_add(length),
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_page_rows() {
+        let mut data = vec![0x41; 20];
+        data[1] = 0x00;
+        let page = MemoryPage::new(0x20, data);
+
+        assert_eq!(page.rows.len(), 2);
+        assert_eq!(page.rows[0].address, 0x20);
+        assert_eq!(page.rows[0].ascii, "A.AAAAAAAAAAAAAA");
+        assert_eq!(page.rows[1].address, 0x30);
+        assert_eq!(page.rows[1].hex, "41 41 41 41");
+        assert_eq!(page.previous_address, Some(0x0C));
+        assert_eq!(page.next_address, Some(0x34));
+
+        let first = MemoryPage::new(0, vec![0; 16]);
+        assert_eq!(first.previous_address, None);
+    }
+}
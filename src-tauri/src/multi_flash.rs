@@ -0,0 +1,701 @@
+/**
+ * 多 ECU 并行刷写
+ * 每个 ECU 使用独立的 DoIP 连接和刷写任务，调度器按并发数和网关带宽预算
+ * 安排任务启动，并在传输时按数据块限制总带宽；单个 ECU 失败不影响其他 ECU，
+ * 结束后汇总各 ECU 结果
+ */
+use crate::delta::ImageReference;
+use crate::flash_job::{FlashJob, FlashJobConfig, FlashProgress, FlashReport};
+use crate::types::{ConnectionConfig, MemorySegment, NrcInfo, UdsError, UdsResult};
+use crate::uds_service::UdsService;
+use crate::utils::hex_to_address_bytes;
+use crate::verification::VerificationReport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// 多 ECU 刷写进度事件名称
+pub const MULTI_FLASH_PROGRESS_EVENT: &str = "multi-flash-progress";
+
+/// 单个 ECU 的刷写目标
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EcuFlashTarget {
+    pub name: String,
+    pub connection: ConnectionConfig, // 独立连接，同一网关后的多个目标须使用不同的测试仪地址
+    #[serde(default)]
+    pub config: FlashJobConfig,
+    pub segments: Vec<MemorySegment>,
+    #[serde(default)]
+    pub reference: Option<Vec<MemorySegment>>, // 差分刷写参考数据
+    #[serde(default)]
+    pub bandwidth: Option<u64>, // 预估传输速率（字节/秒），未设置时使用调度器默认值
+}
+
+impl EcuFlashTarget {
+    fn total_bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.data.len() as u64).sum()
+    }
+}
+
+/// 调度器配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub max_parallel: usize,           // 同时刷写的 ECU 数量上限
+    pub bandwidth_budget: Option<u64>, // 共享链路总带宽（字节/秒），None 表示不限制
+    pub default_bandwidth: u64,        // 未设置带宽的 ECU 的预估传输速率
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_parallel: 4,
+            bandwidth_budget: None,
+            default_bandwidth: 50_000,
+        }
+    }
+}
+
+/// 单个 ECU 的刷写进度
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EcuFlashProgress {
+    pub ecu_index: usize,
+    pub ecu: String,
+    pub progress: FlashProgress,
+}
+
+/// 单个 ECU 的刷写结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EcuFlashResult {
+    pub ecu: String,
+    pub success: bool,
+    pub message: String,
+    pub report: Option<FlashReport>,
+    pub verification: Option<VerificationReport>, // 刷写数据校验失败时的校验报告
+    pub nrc: Option<NrcInfo>,
+    pub duration_ms: u64,
+}
+
+impl EcuFlashResult {
+    fn with_verification(mut self, report: VerificationReport) -> Self {
+        self.verification = Some(report);
+        self
+    }
+
+    fn failed(ecu: &str, message: String) -> Self {
+        Self {
+            ecu: ecu.to_string(),
+            success: false,
+            message,
+            report: None,
+            verification: None,
+            nrc: None,
+            duration_ms: 0,
+        }
+    }
+}
+
+/// 多 ECU 刷写汇总结果，results 与目标顺序一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiFlashResult {
+    pub success: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<EcuFlashResult>,
+    pub duration_ms: u64,
+}
+
+pub type EcuProgressSink = Arc<dyn Fn(EcuFlashProgress) + Send + Sync>;
+
+/// 共享链路带宽限制（令牌桶），各连接发送每个数据块前按块长度取得配额
+pub struct BandwidthLimiter {
+    rate: f64,                    // 字节/秒
+    burst: f64,                   // 空闲后允许的突发字节数
+    state: Mutex<(f64, Instant)>, // 可用配额（为负时表示已预支）和上次更新时间
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second.max(1) as f64;
+        // 突发量为 100 ms 的传输量
+        let burst = (rate / 10.0).max(1.0);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// 取得 bytes 字节的发送配额，配额不足时等待；并发请求按先后预支配额依次等待
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let (available, last) = *state;
+            let available = (available + now.duration_since(last).as_secs_f64() * self.rate)
+                .min(self.burst)
+                - bytes as f64;
+            *state = (available, now);
+            if available < 0.0 {
+                Duration::from_secs_f64(-available / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// 等待启动的刷写目标
+struct PendingTarget {
+    index: usize,
+    bandwidth: u64,
+    target: EcuFlashTarget,
+}
+
+/// 多 ECU 刷写调度器，设置带宽预算时既限制同时启动的 ECU，也限制传输中的总速率
+pub struct MultiFlashScheduler {
+    config: SchedulerConfig,
+    targets: Vec<EcuFlashTarget>,
+    abort: Arc<AtomicBool>,
+}
+
+impl MultiFlashScheduler {
+    pub fn new(config: SchedulerConfig, targets: Vec<EcuFlashTarget>) -> Self {
+        Self {
+            config,
+            targets,
+            abort: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 使用外部中止标志，置位后所有 ECU 停止刷写，未启动的 ECU 不再启动
+    pub fn with_abort_flag(mut self, abort: Arc<AtomicBool>) -> Self {
+        self.abort = abort;
+        self
+    }
+
+    /// 执行所有刷写目标
+    pub async fn run(self, on_progress: EcuProgressSink) -> MultiFlashResult {
+        let start = Instant::now();
+        let max_parallel = self.config.max_parallel.max(1);
+        let mut results: Vec<Option<EcuFlashResult>> = vec![None; self.targets.len()];
+
+        // 网关对每个测试仪地址只接受一个连接的路由激活，冲突的目标不启动
+        let conflicts = shared_tester_connections(&self.targets);
+        for (&index, message) in &conflicts {
+            log::error!(
+                "Flash of {} rejected: {}",
+                self.targets[index].name,
+                message
+            );
+            results[index] = Some(EcuFlashResult::failed(
+                &self.targets[index].name,
+                message.clone(),
+            ));
+        }
+
+        // 预估耗时最长的先启动，缩短整体完成时间
+        let mut pending: Vec<PendingTarget> = self
+            .targets
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !conflicts.contains_key(index))
+            .map(|(index, target)| PendingTarget {
+                index,
+                bandwidth: target
+                    .bandwidth
+                    .unwrap_or(self.config.default_bandwidth)
+                    .max(1),
+                target,
+            })
+            .collect();
+        pending.sort_by(|a, b| {
+            let a_time = a.target.total_bytes() as f64 / a.bandwidth as f64;
+            let b_time = b.target.total_bytes() as f64 / b.bandwidth as f64;
+            b_time.total_cmp(&a_time)
+        });
+
+        let limiter = self
+            .config
+            .bandwidth_budget
+            .map(|budget| Arc::new(BandwidthLimiter::new(budget)));
+        let mut running = JoinSet::new();
+        let mut used_bandwidth = 0u64;
+        loop {
+            if self.abort.load(Ordering::SeqCst) {
+                for p in pending.drain(..) {
+                    results[p.index] = Some(EcuFlashResult::failed(
+                        &p.target.name,
+                        "刷写已中止".to_string(),
+                    ));
+                }
+            }
+
+            while running.len() < max_parallel {
+                let Some(position) = next_target(
+                    &pending,
+                    running.is_empty(),
+                    used_bandwidth,
+                    self.config.bandwidth_budget,
+                ) else {
+                    break;
+                };
+                let PendingTarget {
+                    index,
+                    bandwidth,
+                    target,
+                } = pending.remove(position);
+                used_bandwidth += bandwidth;
+                log::info!(
+                    "Starting flash of {} ({} bytes, estimated {} B/s)",
+                    target.name,
+                    target.total_bytes(),
+                    bandwidth
+                );
+
+                let abort = self.abort.clone();
+                let limiter = limiter.clone();
+                let on_progress = on_progress.clone();
+                running.spawn(async move {
+                    let name = target.name.clone();
+                    // 在独立任务中刷写，单个 ECU 任务崩溃时只影响该 ECU 的结果
+                    let handle =
+                        tokio::spawn(flash_target(index, target, abort, limiter, on_progress));
+                    let result = handle.await.unwrap_or_else(|e| {
+                        EcuFlashResult::failed(&name, format!("刷写任务异常: {}", e))
+                    });
+                    (index, bandwidth, result)
+                });
+            }
+
+            match running.join_next().await {
+                Some(Ok((index, bandwidth, result))) => {
+                    used_bandwidth -= bandwidth;
+                    log::info!(
+                        "Flash of {} finished: {}",
+                        result.ecu,
+                        if result.success { "success" } else { "failed" }
+                    );
+                    results[index] = Some(result);
+                }
+                Some(Err(e)) => log::error!("Flash scheduler task failed: {}", e),
+                None => break,
+            }
+        }
+
+        let results: Vec<EcuFlashResult> = results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| EcuFlashResult::failed("", "刷写任务异常".to_string())))
+            .collect();
+        let succeeded = results.iter().filter(|r| r.success).count();
+        MultiFlashResult {
+            success: succeeded == results.len(),
+            succeeded,
+            failed: results.len() - succeeded,
+            results,
+            duration_ms: start.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// 找出与其它目标使用同一网关（地址和端口）和同一测试仪地址的目标，返回下标和错误说明
+fn shared_tester_connections(targets: &[EcuFlashTarget]) -> BTreeMap<usize, String> {
+    let mut groups: BTreeMap<(String, u16, String), Vec<usize>> = BTreeMap::new();
+    for (index, target) in targets.iter().enumerate() {
+        let connection = &target.connection;
+        let tester = hex_to_address_bytes(&connection.client_address)
+            .map(hex::encode_upper)
+            .unwrap_or_else(|_| connection.client_address.clone());
+        groups
+            .entry((
+                connection.ip_address.trim().to_string(),
+                connection.port,
+                tester,
+            ))
+            .or_default()
+            .push(index);
+    }
+
+    let mut conflicts = BTreeMap::new();
+    for ((ip_address, port, tester), indices) in groups {
+        if indices.len() < 2 {
+            continue;
+        }
+        let names: Vec<&str> = indices.iter().map(|&i| targets[i].name.as_str()).collect();
+        let message = format!(
+            "{} 通过网关 {}:{} 使用相同的测试仪地址 {}，网关不允许同一地址重复激活路由",
+            names.join("、"),
+            ip_address,
+            port,
+            tester
+        );
+        for index in indices {
+            conflicts.insert(index, message.clone());
+        }
+    }
+    conflicts
+}
+
+/// 选择下一个可以启动的目标：带宽预算不足时跳过，让较小的目标填充剩余带宽；
+/// 没有任务在运行时总是启动第一个目标，避免单个目标超出预算时无法执行
+fn next_target(
+    pending: &[PendingTarget],
+    idle: bool,
+    used_bandwidth: u64,
+    budget: Option<u64>,
+) -> Option<usize> {
+    if idle && !pending.is_empty() {
+        return Some(0);
+    }
+    pending.iter().position(|p| match budget {
+        Some(budget) => used_bandwidth + p.bandwidth <= budget,
+        None => true,
+    })
+}
+
+/// 连接并刷写单个 ECU
+async fn flash_target(
+    index: usize,
+    target: EcuFlashTarget,
+    abort: Arc<AtomicBool>,
+    limiter: Option<Arc<BandwidthLimiter>>,
+    on_progress: EcuProgressSink,
+) -> EcuFlashResult {
+    let start = Instant::now();
+    let name = target.name.clone();
+    let outcome = flash_ecu(index, target, abort, limiter, on_progress).await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    match outcome {
+        Ok(report) => EcuFlashResult {
+            ecu: name,
+            success: true,
+            message: format!(
+                "刷写完成，共 {} 个内存段 {} 字节",
+                report.segment_count, report.total_bytes
+            ),
+            report: Some(report),
+            verification: None,
+            nrc: None,
+            duration_ms,
+        },
+        Err(UdsError::Aborted) => EcuFlashResult {
+            duration_ms,
+            ..EcuFlashResult::failed(&name, "刷写已中止".to_string())
+        },
+        Err(UdsError::ImageVerificationFailed(report)) => EcuFlashResult {
+            duration_ms,
+            ..EcuFlashResult::failed(
+                &name,
+                format!("刷写数据校验失败: {}", report.failures().join("; ")),
+            )
+        }
+        .with_verification(*report),
+        Err(e) => {
+            log::error!("Flash of {} failed: {}", name, e);
+            EcuFlashResult {
+                nrc: e.nrc().map(NrcInfo::from),
+                duration_ms,
+                ..EcuFlashResult::failed(&name, format!("刷写失败: {}", e))
+            }
+        }
+    }
+}
+
+async fn flash_ecu(
+    index: usize,
+    target: EcuFlashTarget,
+    abort: Arc<AtomicBool>,
+    limiter: Option<Arc<BandwidthLimiter>>,
+    on_progress: EcuProgressSink,
+) -> UdsResult<FlashReport> {
+    if target.segments.is_empty() {
+        return Err(UdsError::InvalidParameter(
+            "No segments to flash".to_string(),
+        ));
+    }
+
+    let mut service = UdsService::connect(&target.connection, None).await?;
+    if let Some(limiter) = limiter {
+        service.set_bandwidth_limiter(limiter);
+    }
+    let mut job = FlashJob::new(target.config, target.segments).with_abort_flag(abort);
+    if let Some(reference) = target.reference {
+        job = job.with_reference(Box::new(ImageReference::new(reference)));
+    }
+
+    let ecu = target.name;
+    job.run(&mut service, |progress| {
+        on_progress(EcuFlashProgress {
+            ecu_index: index,
+            ecu: ecu.clone(),
+            progress,
+        })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecu_simulator::{
+        fast_timing, flash_response, recording, request_log, EcuSimulator, SimAction,
+    };
+    use crate::flash_job::FlashStepState;
+    use std::sync::atomic::AtomicUsize;
+
+    /// 正响应所有刷写请求，记录同时处于编程会话的 ECU 数量，fail_erase 时拒绝擦除
+    fn counting_handler(
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+        fail_erase: bool,
+    ) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+        move |request| {
+            let response = match request {
+                [0x10, 0x02, ..] => {
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    vec![0x50, 0x02, 0x00, 0x32, 0x01, 0xF4]
+                }
+                // 复位或回滚返回默认会话时退出编程会话
+                [0x10, 0x01, ..] | [0x11, ..] => {
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    vec![request[0] + 0x40, request[1]]
+                }
+                [0x31, 0x01, 0xFF, 0x00, ..] if fail_erase => vec![0x7F, 0x31, 0x22],
+                [0x36, counter, ..] => {
+                    return vec![
+                        SimAction::Delay(5),
+                        SimAction::Respond(vec![0x76, *counter]),
+                    ]
+                }
+                _ => flash_response(request),
+            };
+            vec![SimAction::Respond(response)]
+        }
+    }
+
+    fn target(name: &str, sim: &EcuSimulator, size: usize) -> EcuFlashTarget {
+        EcuFlashTarget {
+            name: name.to_string(),
+            connection: sim.connection_config(fast_timing()),
+            config: FlashJobConfig::default(),
+            segments: vec![MemorySegment::new(0x0800_0000, vec![0xAA; size])],
+            reference: None,
+            bandwidth: None,
+        }
+    }
+
+    fn pending(bandwidths: &[u64]) -> Vec<PendingTarget> {
+        bandwidths
+            .iter()
+            .enumerate()
+            .map(|(index, &bandwidth)| PendingTarget {
+                index,
+                bandwidth,
+                target: EcuFlashTarget {
+                    name: format!("ECU{}", index),
+                    connection: ConnectionConfig {
+                        ip_address: "127.0.0.1".to_string(),
+                        port: 13400,
+                        server_address: "1001".to_string(),
+                        client_address: "0E80".to_string(),
+                        timeout: None,
+                        timing: None,
+                    },
+                    config: FlashJobConfig::default(),
+                    segments: Vec::new(),
+                    reference: None,
+                    bandwidth: Some(bandwidth),
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bandwidth_budget_selection() {
+        let queue = pending(&[60, 60, 30]);
+        assert_eq!(next_target(&queue, true, 0, Some(100)), Some(0));
+        // 剩余 40 只能容纳带宽 30 的目标
+        assert_eq!(next_target(&queue, false, 60, Some(100)), Some(2));
+        assert_eq!(next_target(&queue, false, 90, Some(100)), None);
+        assert_eq!(next_target(&queue, false, 90, None), Some(0));
+        // 空闲时即使超出预算也启动
+        assert_eq!(next_target(&queue, true, 0, Some(10)), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_limiter() {
+        // 10000 B/s，突发 1000 字节：3000 字节中超出突发的 2000 字节需要约 200 ms
+        let limiter = Arc::new(BandwidthLimiter::new(10_000));
+        let start = Instant::now();
+        let mut tasks = JoinSet::new();
+        for _ in 0..2 {
+            let limiter = limiter.clone();
+            tasks.spawn(async move {
+                for _ in 0..3 {
+                    limiter.acquire(500).await;
+                }
+            });
+        }
+        tasks.join_all().await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_budget_limits_transfer() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut sims = Vec::new();
+        for _ in 0..2 {
+            sims.push(
+                EcuSimulator::start(counting_handler(active.clone(), peak.clone(), false)).await,
+            );
+        }
+        let targets = sims
+            .iter()
+            .enumerate()
+            .map(|(i, sim)| target(&format!("ECU{}", i), sim, 2000))
+            .collect();
+
+        // 两个 ECU 同时启动，共 4000 字节按 10000 B/s 传输
+        let config = SchedulerConfig {
+            max_parallel: 2,
+            bandwidth_budget: Some(10_000),
+            default_bandwidth: 5_000,
+        };
+        let start = Instant::now();
+        let result = MultiFlashScheduler::new(config, targets)
+            .run(Arc::new(|_| {}))
+            .await;
+
+        assert!(result.success);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= Duration::from_millis(290));
+    }
+
+    #[tokio::test]
+    async fn test_shared_tester_address_rejected() {
+        let requests = request_log();
+        let gateway = EcuSimulator::start(recording(requests.clone(), |request| {
+            vec![SimAction::Respond(flash_response(request))]
+        }))
+        .await;
+        let other =
+            EcuSimulator::start(|request| vec![SimAction::Respond(flash_response(request))]).await;
+        let mut targets = vec![
+            target("BCM", &gateway, 100),
+            target("VCU", &gateway, 100),
+            target("ADAS", &other, 100),
+        ];
+        // 同一网关后的不同 ECU，测试仪地址写法不同但数值相同
+        targets[1].connection.server_address = "1002".to_string();
+        targets[1].connection.client_address = "0x0e80".to_string();
+
+        let result = MultiFlashScheduler::new(SchedulerConfig::default(), targets)
+            .run(Arc::new(|_| {}))
+            .await;
+
+        assert_eq!((result.succeeded, result.failed), (1, 2));
+        assert!(result.results[0].message.contains("BCM、VCU"));
+        assert!(result.results[1].message.contains("BCM、VCU"));
+        assert!(result.results[2].success);
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_flash_isolates_failures() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let sims = [
+            EcuSimulator::start(counting_handler(active.clone(), peak.clone(), false)).await,
+            EcuSimulator::start(counting_handler(active.clone(), peak.clone(), true)).await,
+            EcuSimulator::start(counting_handler(active.clone(), peak.clone(), false)).await,
+        ];
+        let targets = vec![
+            target("BCM", &sims[0], 2000),
+            target("VCU", &sims[1], 1000),
+            target("ADAS", &sims[2], 600),
+        ];
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let sink = progress.clone();
+        let result = MultiFlashScheduler::new(SchedulerConfig::default(), targets)
+            .run(Arc::new(move |p: EcuFlashProgress| {
+                sink.lock().unwrap().push(p)
+            }))
+            .await;
+
+        assert!(!result.success);
+        assert_eq!((result.succeeded, result.failed), (2, 1));
+        let names: Vec<&str> = result.results.iter().map(|r| r.ecu.as_str()).collect();
+        assert_eq!(names, vec!["BCM", "VCU", "ADAS"]);
+        assert!(result.results[0].success);
+        assert!(!result.results[1].success);
+        assert_eq!(result.results[1].nrc.as_ref().unwrap().code, 0x22);
+        assert_eq!(result.results[2].report.as_ref().unwrap().total_bytes, 600);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+
+        // 每个成功的 ECU 都上报了完成进度
+        let progress = progress.lock().unwrap();
+        for index in [0, 2] {
+            let last = progress.iter().rfind(|p| p.ecu_index == index).unwrap();
+            assert_eq!(last.progress.state, FlashStepState::Completed);
+            assert_eq!(last.progress.percent, 100.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parallel_limit() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let mut sims = Vec::new();
+        for _ in 0..3 {
+            sims.push(
+                EcuSimulator::start(counting_handler(active.clone(), peak.clone(), false)).await,
+            );
+        }
+        let targets = sims
+            .iter()
+            .enumerate()
+            .map(|(i, sim)| target(&format!("ECU{}", i), sim, 300))
+            .collect();
+
+        let config = SchedulerConfig {
+            max_parallel: 3,
+            bandwidth_budget: Some(80_000),
+            default_bandwidth: 50_000,
+        };
+        let result = MultiFlashScheduler::new(config, targets)
+            .run(Arc::new(|_| {}))
+            .await;
+
+        assert!(result.success);
+        assert_eq!(result.succeeded, 3);
+        // 带宽预算只允许一个 ECU 同时刷写
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_abort_skips_pending_targets() {
+        let sim = EcuSimulator::start(|_| vec![]).await;
+        let abort = Arc::new(AtomicBool::new(true));
+        let mut targets = vec![target("BCM", &sim, 100), target("VCU", &sim, 100)];
+        targets[1].connection.client_address = "0E81".to_string();
+        let result = MultiFlashScheduler::new(SchedulerConfig::default(), targets)
+            .with_abort_flag(abort)
+            .run(Arc::new(|_| {}))
+            .await;
+
+        assert_eq!(result.failed, 2);
+        assert!(result.results.iter().all(|r| r.message == "刷写已中止"));
+    }
+}
@@ -0,0 +1,59 @@
+/**
+ * 周期数据（ReadDataByPeriodicIdentifier 0x2A）
+ * ECU 按设定速率主动推送的周期响应报文不对应任何请求，
+ * 在此解码为带时间戳的样本并推送到前端
+ */
+use crate::utils::{bytes_to_ascii, bytes_to_hex, get_timestamp};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// 推送到前端的周期数据事件名称
+pub const PERIODIC_DATA_EVENT: &str = "periodic-data";
+
+/// 周期 DID 的高字节，请求中只携带低字节
+pub const PERIODIC_DID_HIGH_BYTE: u8 = 0xF2;
+
+/// 单个周期数据样本
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeriodicSample {
+    pub timestamp: String,
+    pub periodic_id: u8,
+    pub did: u16, // 完整 DID（0xF2xx）
+    pub data: String,
+    pub ascii: String,
+}
+
+/// 周期数据接收端，由上层（Tauri）注入
+pub type PeriodicSink = Arc<dyn Fn(PeriodicSample) + Send + Sync>;
+
+/// 解析周期响应报文：6A + periodicDataIdentifier + 数据
+///
+/// 仅有 SID 的 6A 是启动/停止请求的正响应，不属于周期数据，返回 None。
+pub fn parse_periodic_message(uds: &[u8]) -> Option<PeriodicSample> {
+    match uds {
+        [0x6A, periodic_id, data @ ..] => Some(PeriodicSample {
+            timestamp: get_timestamp(),
+            periodic_id: *periodic_id,
+            did: u16::from_be_bytes([PERIODIC_DID_HIGH_BYTE, *periodic_id]),
+            data: bytes_to_hex(data),
+            ascii: bytes_to_ascii(data),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_periodic_message() {
+        let sample = parse_periodic_message(&[0x6A, 0x05, 0x41, 0x00, 0x7F]).unwrap();
+        assert_eq!(sample.periodic_id, 0x05);
+        assert_eq!(sample.did, 0xF205);
+        assert_eq!(sample.data, bytes_to_hex(&[0x41, 0x00, 0x7F]));
+
+        assert!(parse_periodic_message(&[0x6A]).is_none());
+        assert!(parse_periodic_message(&[0x62, 0xF2, 0x05, 0x00]).is_none());
+    }
+}
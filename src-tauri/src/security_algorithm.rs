@@ -106,22 +106,26 @@ impl SecurityAccessAlgorithm {
         temp_key
     }
 
-    /// 根据级别计算密钥
+    /// 根据发送密钥级别计算密钥（忽略抑制正响应位）
+    ///
+    /// 4 种算法按级别序号循环：02/04/06/08 依次对应 Level1–4，0A 起重复。
     pub fn compute_key_by_level(
         &mut self,
         level: u8,
         seed: u32,
         key_k: u32,
     ) -> Result<u32, DoipError> {
-        match level {
-            2 => Ok(self.compute_key_level1(seed, key_k)),
-            4 => Ok(self.compute_key_level2(seed, key_k)),
-            6 => Ok(self.compute_key_level3(seed, key_k)),
-            8 => Ok(self.compute_key_level4(seed, key_k)),
-            _ => Err(DoipError::InvalidData(format!(
+        if !is_send_key_level(level) {
+            return Err(DoipError::InvalidData(format!(
                 "Unsupported security level: {}",
                 level
-            ))),
+            )));
+        }
+        match ((level & 0x7F) / 2 - 1) % 4 {
+            0 => Ok(self.compute_key_level1(seed, key_k)),
+            1 => Ok(self.compute_key_level2(seed, key_k)),
+            2 => Ok(self.compute_key_level3(seed, key_k)),
+            _ => Ok(self.compute_key_level4(seed, key_k)),
         }
     }
 
@@ -141,6 +145,16 @@ impl SecurityAccessAlgorithm {
     }
 }
 
+/// 请求种子（requestSeed）级别：0x01–0x7D 之间的奇数
+pub fn is_request_seed_level(level: u8) -> bool {
+    (0x01..=0x7D).contains(&level) && level & 0x01 == 0x01
+}
+
+/// 发送密钥（sendKey）级别：0x02–0x7E 之间的偶数，可带抑制正响应位
+pub fn is_send_key_level(level: u8) -> bool {
+    (0x02..=0x7E).contains(&(level & 0x7F)) && level & 0x01 == 0
+}
+
 impl Default for SecurityAccessAlgorithm {
     fn default() -> Self {
         Self::new()
@@ -219,6 +233,12 @@ mod tests {
         assert!(algo.compute_key_by_level(4, seed, key).is_ok());
         assert!(algo.compute_key_by_level(6, seed, key).is_ok());
         assert!(algo.compute_key_by_level(8, seed, key).is_ok());
+        assert!(algo.compute_key_by_level(0x42, seed, key).is_ok());
+        assert!(algo.compute_key_by_level(0x7E, seed, key).is_ok());
+        assert_eq!(
+            algo.compute_key_by_level(0x0A, seed, key).unwrap(),
+            algo.compute_key_level1(seed, key)
+        );
 
         // 测试无效级别
         assert!(algo.compute_key_by_level(1, seed, key).is_err());
@@ -226,6 +246,8 @@ mod tests {
         assert!(algo.compute_key_by_level(5, seed, key).is_err());
         assert!(algo.compute_key_by_level(7, seed, key).is_err());
         assert!(algo.compute_key_by_level(9, seed, key).is_err());
+        assert!(algo.compute_key_by_level(0, seed, key).is_err());
+        assert!(algo.compute_key_by_level(0x7F, seed, key).is_err());
     }
 
     #[test]
@@ -0,0 +1,296 @@
+/**
+ * 报文追踪模块
+ * 将 DoIP/UDS 收发的原始帧解码为可推送到前端的实时事件
+ */
+use crate::types::{NegativeResponseCode, UdsServices};
+use crate::utils::{bytes_to_hex, get_timestamp};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// 推送到前端的报文事件名称
+pub const TRAFFIC_EVENT: &str = "doip-traffic";
+
+/// DoIP 头部长度
+const DOIP_HEADER_LEN: usize = 8;
+
+/// 事件中原始数据和摘要最多包含的字节数，批量传输的数据块只推送开头部分
+const MAX_EVENT_BYTES: usize = 64;
+
+/// 报文方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficDirection {
+    Tx,
+    Rx,
+}
+
+/// 单帧报文事件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficEvent {
+    pub timestamp: String,
+    pub direction: TrafficDirection,
+    pub payload_type: Option<u16>,
+    pub payload_type_name: String,
+    pub source_address: Option<u16>,
+    pub target_address: Option<u16>,
+    pub length: usize, // 帧长度，超过 MAX_EVENT_BYTES 时 raw 只包含开头部分
+    pub raw: String,
+    pub summary: String,
+}
+
+/// 报文事件接收端，由上层（Tauri）注入
+pub type TrafficSink = Arc<dyn Fn(TrafficEvent) + Send + Sync>;
+
+/// 将一次收发的数据拆分为 DoIP 帧并逐帧解码
+pub fn decode_frames(direction: TrafficDirection, data: &[u8]) -> Vec<TrafficEvent> {
+    let timestamp = get_timestamp();
+    let mut events = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let rest = &data[pos..];
+        let frame_len = match doip_frame_len(rest) {
+            Some(len) => len.min(rest.len()),
+            None => rest.len(),
+        };
+        events.push(decode_frame(
+            direction,
+            &rest[..frame_len],
+            frame_len,
+            &timestamp,
+        ));
+        pos += frame_len;
+    }
+
+    events
+}
+
+/// 解码以帧头和数据两部分发送的单个帧，只复制解码所需的开头部分
+pub fn decode_frame_parts(direction: TrafficDirection, head: &[u8], body: &[u8]) -> TrafficEvent {
+    let prefix: Vec<u8> = head
+        .iter()
+        .chain(body)
+        .take(DOIP_HEADER_LEN + 4 + MAX_EVENT_BYTES)
+        .copied()
+        .collect();
+    decode_frame(
+        direction,
+        &prefix,
+        head.len() + body.len(),
+        &get_timestamp(),
+    )
+}
+
+/// 根据 DoIP 头部计算整帧长度，头部无效时返回 None
+fn doip_frame_len(data: &[u8]) -> Option<usize> {
+    if data.len() < DOIP_HEADER_LEN || data[0] != !data[1] {
+        return None;
+    }
+    let payload_len = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+    Some(DOIP_HEADER_LEN + payload_len)
+}
+
+/// 解码单个 DoIP 帧，frame 可以只是长度为 length 的帧的开头部分
+fn decode_frame(
+    direction: TrafficDirection,
+    frame: &[u8],
+    length: usize,
+    timestamp: &str,
+) -> TrafficEvent {
+    let mut event = TrafficEvent {
+        timestamp: timestamp.to_string(),
+        direction,
+        payload_type: None,
+        payload_type_name: "Unknown".to_string(),
+        source_address: None,
+        target_address: None,
+        length,
+        raw: hex_prefix(frame, length),
+        summary: String::new(),
+    };
+
+    if doip_frame_len(frame).is_none() {
+        event.summary = format!("Non-DoIP data ({} bytes)", length);
+        return event;
+    }
+
+    let payload_type = u16::from_be_bytes([frame[2], frame[3]]);
+    let payload = &frame[DOIP_HEADER_LEN..];
+    event.payload_type = Some(payload_type);
+    event.payload_type_name = payload_type_name(payload_type).to_string();
+
+    match payload_type {
+        // 路由激活请求：源地址 + 激活类型
+        0x0005 if payload.len() >= 3 => {
+            event.source_address = Some(read_u16(payload, 0));
+            event.summary = format!("Activation type 0x{:02X}", payload[2]);
+        }
+        // 路由激活响应：测试仪地址 + 实体地址 + 响应码
+        0x0006 if payload.len() >= 5 => {
+            event.target_address = Some(read_u16(payload, 0));
+            event.source_address = Some(read_u16(payload, 2));
+            event.summary = format!("Response code 0x{:02X}", payload[4]);
+        }
+        // 诊断报文
+        0x8001 if payload.len() >= 4 => {
+            event.source_address = Some(read_u16(payload, 0));
+            event.target_address = Some(read_u16(payload, 2));
+            event.summary = summarize_uds(&payload[4..], length - DOIP_HEADER_LEN - 4);
+        }
+        // 诊断报文确认/否定确认
+        0x8002 | 0x8003 if payload.len() >= 5 => {
+            event.source_address = Some(read_u16(payload, 0));
+            event.target_address = Some(read_u16(payload, 2));
+            event.summary = format!("Ack code 0x{:02X}", payload[4]);
+        }
+        _ => {
+            event.summary = format!("{} payload bytes", payload.len());
+        }
+    }
+
+    event
+}
+
+/// 十六进制表示，超过 MAX_EVENT_BYTES 或 data 只是长度为 length 的数据的开头部分时截断
+fn hex_prefix(data: &[u8], length: usize) -> String {
+    let shown = &data[..data.len().min(MAX_EVENT_BYTES)];
+    if shown.len() < length {
+        format!("{} ... ({} bytes)", bytes_to_hex(shown), length)
+    } else {
+        bytes_to_hex(shown)
+    }
+}
+
+/// 生成 UDS 负载摘要，uds 可以只是长度为 length 的负载的开头部分
+fn summarize_uds(uds: &[u8], length: usize) -> String {
+    let Some(&sid) = uds.first() else {
+        return "Empty UDS payload".to_string();
+    };
+
+    if sid == 0x7F && uds.len() >= 3 {
+        return format!(
+            "Negative response to {}: {}",
+            service_label(uds[1]),
+            NegativeResponseCode::from(uds[2])
+        );
+    }
+
+    // 数据块只显示块序号和长度
+    if sid == 0x36 && uds.len() >= 2 {
+        return format!(
+            "TransferData request: block 0x{:02X}, {} bytes",
+            uds[1],
+            length - 2
+        );
+    }
+
+    let hex = hex_prefix(uds, length);
+    match UdsServices::name(sid) {
+        Some(name) => format!("{} request: {}", name, hex),
+        None => match sid.checked_sub(0x40).and_then(UdsServices::name) {
+            Some(name) => format!("{} positive response: {}", name, hex),
+            None => format!("UDS: {}", hex),
+        },
+    }
+}
+
+/// 服务名称（含 SID）
+fn service_label(sid: u8) -> String {
+    match UdsServices::name(sid) {
+        Some(name) => format!("{} (0x{:02X})", name, sid),
+        None => format!("0x{:02X}", sid),
+    }
+}
+
+/// DoIP 负载类型名称
+fn payload_type_name(payload_type: u16) -> &'static str {
+    match payload_type {
+        0x0000 => "GenericHeaderNack",
+        0x0001..=0x0003 => "VehicleIdentificationRequest",
+        0x0004 => "VehicleAnnouncement",
+        0x0005 => "RoutingActivationRequest",
+        0x0006 => "RoutingActivationResponse",
+        0x0007 => "AliveCheckRequest",
+        0x0008 => "AliveCheckResponse",
+        0x4001 => "EntityStatusRequest",
+        0x4002 => "EntityStatusResponse",
+        0x4003 => "PowerModeRequest",
+        0x4004 => "PowerModeResponse",
+        0x8001 => "DiagnosticMessage",
+        0x8002 => "DiagnosticMessageAck",
+        0x8003 => "DiagnosticMessageNack",
+        _ => "Unknown",
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_diagnostic_request() {
+        let frame = [
+            0x02, 0xFD, 0x80, 0x01, 0x00, 0x00, 0x00, 0x07, 0x0E, 0x80, 0x10, 0x01, 0x22, 0xF1,
+            0x90,
+        ];
+        let events = decode_frames(TrafficDirection::Tx, &frame);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].payload_type, Some(0x8001));
+        assert_eq!(events[0].source_address, Some(0x0E80));
+        assert_eq!(events[0].target_address, Some(0x1001));
+        assert!(events[0].summary.starts_with("ReadDataByIdentifier request"));
+    }
+
+    #[test]
+    fn test_decode_concatenated_frames() {
+        let data = [
+            // 诊断确认
+            0x02, 0xFD, 0x80, 0x02, 0x00, 0x00, 0x00, 0x05, 0x10, 0x01, 0x0E, 0x80, 0x00,
+            // 否定响应
+            0x02, 0xFD, 0x80, 0x01, 0x00, 0x00, 0x00, 0x07, 0x10, 0x01, 0x0E, 0x80, 0x7F, 0x22,
+            0x31,
+        ];
+        let events = decode_frames(TrafficDirection::Rx, &data);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].payload_type_name, "DiagnosticMessageAck");
+        assert_eq!(
+            events[1].summary,
+            "Negative response to ReadDataByIdentifier (0x22): requestOutOfRange (0x31)"
+        );
+    }
+
+    #[test]
+    fn test_decode_transfer_data_parts() {
+        let head = [
+            0x02, 0xFD, 0x80, 0x01, 0x00, 0x00, 0x10, 0x06, 0x0E, 0x80, 0x10, 0x01, 0x36, 0x05,
+        ];
+        let body = vec![0xAA; 0x1000];
+        let event = decode_frame_parts(TrafficDirection::Tx, &head, &body);
+        assert_eq!(event.length, head.len() + body.len());
+        assert_eq!(event.target_address, Some(0x1001));
+        assert_eq!(
+            event.summary,
+            "TransferData request: block 0x05, 4096 bytes"
+        );
+        assert!(event.raw.len() < 4 * MAX_EVENT_BYTES);
+        assert!(event.raw.ends_with(&format!("({} bytes)", event.length)));
+
+        // 短帧不截断
+        let event = decode_frame_parts(TrafficDirection::Tx, &head[..12], &[0x3E, 0x00]);
+        assert_eq!(
+            event.raw,
+            bytes_to_hex(&[&head[..12], &[0x3E, 0x00][..]].concat())
+        );
+    }
+
+    #[test]
+    fn test_decode_non_doip_data() {
+        let events = decode_frames(TrafficDirection::Rx, &[0x01, 0x02, 0x03]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].payload_type, None);
+    }
+}
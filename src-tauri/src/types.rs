@@ -2,6 +2,8 @@
  * 共享类型定义
  * 定义 DoIP 和 UDS 相关的数据结构
  */
+use crate::data_format::TransferEncoding;
+use crate::verification::VerificationReport;
 use serde::{Deserialize, Serialize};
 
 /// DoIP 客户端配置
@@ -19,10 +21,39 @@ pub struct VehicleConfig {
     pub client_address: String,
 }
 
+/// UDS 应用层时间参数（ISO 14229-2）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UdsTimingConfig {
+    pub p2_client_ms: u64,         // 请求后等待首个响应的时间
+    pub p2_star_client_ms: u64,    // 每次收到 0x78 后等待的时间
+    pub max_pending_count: u32,    // 允许的最大 0x78 次数
+    pub busy_repeat_count: u32,    // 收到 0x21 后的最大重发次数
+    pub busy_repeat_delay_ms: u64, // 重发前的等待时间
+    pub use_server_timing: bool,   // 是否采用 0x10 正响应中的 P2/P2* 值
+    pub network_delay_ms: u64,     // 采用 ECU 时间参数时额外增加的网络延迟
+}
+
+impl Default for UdsTimingConfig {
+    fn default() -> Self {
+        Self {
+            p2_client_ms: 1000,
+            p2_star_client_ms: 5000,
+            max_pending_count: 100,
+            busy_repeat_count: 3,
+            busy_repeat_delay_ms: 100,
+            use_server_timing: true,
+            network_delay_ms: 500,
+        }
+    }
+}
+
 /// UDS 配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UdsConfig {
     pub vehicle_info: VehicleConfig,
+    #[serde(default)]
+    pub timing: UdsTimingConfig,
 }
 
 /// UDS 响应结果
@@ -33,6 +64,343 @@ pub struct UdsResponse {
     pub error: Option<String>,
 }
 
+/// 原始请求期间收到的一帧诊断报文
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RawUdsFrame {
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+    pub elapsed_ms: f64, // 自请求发出起的耗时
+}
+
+/// 原始 UDS 请求的响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawUdsResponse {
+    pub request: Vec<u8>,
+    pub frames: Vec<RawUdsFrame>, // 目标 ECU 发来的所有诊断报文，含响应挂起和周期数据
+    pub response: Option<Vec<u8>>, // 最终响应，超时或抑制正响应且无响应时为 None
+    pub nrc: Option<u8>,
+    pub timed_out: bool, // 需要响应但在 P2/P2* 内未收到最终响应
+    pub elapsed_ms: f64,
+}
+
+/// 例程控制响应（routineStatusRecord 含义由主机厂定义）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutineStatus {
+    pub control_type: u8,
+    pub routine_id: u16,
+    pub status_record: Vec<u8>,
+}
+
+impl RoutineStatus {
+    /// 状态记录首字节（routineInfo）
+    pub fn routine_info(&self) -> Option<u8> {
+        self.status_record.first().copied()
+    }
+}
+
+/// 例程结果轮询配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutinePollConfig {
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    pub in_progress_info: Vec<u8>, // 表示例程仍在运行的 routineInfo 取值
+}
+
+impl Default for RoutinePollConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 200,
+            timeout_ms: 60000,
+            in_progress_info: vec![0x02],
+        }
+    }
+}
+
+impl RoutinePollConfig {
+    /// 根据 routineInfo 判断例程是否已结束，无状态记录视为结束
+    pub fn is_complete(&self, status: &RoutineStatus) -> bool {
+        match status.routine_info() {
+            Some(info) => !self.in_progress_info.contains(&info),
+            None => true,
+        }
+    }
+}
+
+/// 内存段：起始地址和连续数据
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemorySegment {
+    pub address: u64,
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl MemorySegment {
+    pub fn new(address: u64, data: Vec<u8>) -> Self {
+        Self { address, data }
+    }
+
+    /// 结束地址（不含）
+    pub fn end(&self) -> u64 {
+        self.address + self.data.len() as u64
+    }
+}
+
+/// 内存区域：起始地址和长度（例如擦除范围）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryRange {
+    pub address: u64,
+    pub length: u64,
+}
+
+/// 字节数组以十六进制字符串形式序列化
+pub(crate) mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        crate::utils::hex_to_bytes(&hex).map_err(serde::de::Error::custom)
+    }
+}
+
+/// 下载进度推送到前端的事件名称
+pub const DOWNLOAD_PROGRESS_EVENT: &str = "download-progress";
+
+/// 按地址读写内存（0x23/0x3D）选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemoryAccessOptions {
+    pub address_and_length_format: u8,
+    pub max_response_length: usize, // ECU 单个 0x23 响应的最大长度（含 SID）
+    pub max_request_length: usize,  // ECU 单个 0x3D 请求的最大长度（含 SID）
+}
+
+impl Default for MemoryAccessOptions {
+    fn default() -> Self {
+        Self {
+            address_and_length_format: 0x44,
+            max_response_length: 0x0FFF,
+            max_request_length: 0x0FFF,
+        }
+    }
+}
+
+/// 上传进度推送到前端的事件名称
+pub const UPLOAD_PROGRESS_EVENT: &str = "upload-progress";
+
+/// 地址与长度格式标识符（addressAndLengthFormatIdentifier）
+///
+/// 高 4 位为 memorySize 字节数，低 4 位为 memoryAddress 字节数。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressAndLengthFormat {
+    pub address_bytes: u8,
+    pub length_bytes: u8,
+}
+
+impl AddressAndLengthFormat {
+    /// 从标识符字节解析
+    pub fn from_identifier(identifier: u8) -> UdsResult<Self> {
+        let format = Self {
+            address_bytes: identifier & 0x0F,
+            length_bytes: identifier >> 4,
+        };
+        if !(1..=8).contains(&format.address_bytes) || !(1..=8).contains(&format.length_bytes) {
+            return Err(UdsError::InvalidParameter(format!(
+                "Invalid addressAndLengthFormatIdentifier 0x{:02X}",
+                identifier
+            )));
+        }
+        Ok(format)
+    }
+
+    /// 标识符字节
+    pub fn identifier(&self) -> u8 {
+        (self.length_bytes << 4) | self.address_bytes
+    }
+
+    /// 编码为 ALFID + memoryAddress + memorySize
+    pub fn encode(&self, address: u64, size: u64) -> UdsResult<Vec<u8>> {
+        let mut encoded = vec![self.identifier()];
+        encoded.extend(fixed_width_be(address, self.address_bytes).ok_or_else(|| {
+            UdsError::InvalidParameter(format!(
+                "Address 0x{:X} does not fit in {} bytes",
+                address, self.address_bytes
+            ))
+        })?);
+        encoded.extend(fixed_width_be(size, self.length_bytes).ok_or_else(|| {
+            UdsError::InvalidParameter(format!(
+                "Size {} does not fit in {} bytes",
+                size, self.length_bytes
+            ))
+        })?);
+        Ok(encoded)
+    }
+}
+
+impl Default for AddressAndLengthFormat {
+    fn default() -> Self {
+        Self {
+            address_bytes: 4,
+            length_bytes: 4,
+        }
+    }
+}
+
+/// 按指定字节数大端编码，超出范围返回 None
+fn fixed_width_be(value: u64, width: u8) -> Option<Vec<u8>> {
+    let width = width as usize;
+    if width < 8 && value >> (width * 8) != 0 {
+        return None;
+    }
+    Some(value.to_be_bytes()[8 - width..].to_vec())
+}
+
+/// 动态 DID 的数据来源：源 DID 中从 position（1 起始）开始的 size 字节
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidSource {
+    pub source_did: u16,
+    pub position: u8,
+    pub size: u8,
+}
+
+/// 单个 DID 的数据记录
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DidRecord {
+    pub did: u16,
+    #[serde(with = "hex_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// 写入 DID 后的读回校验结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteVerification {
+    /// 读回数据与写入一致
+    Verified(Vec<u8>),
+    /// 写入成功但读回失败（如 DID 不可读），未能校验，附失败原因
+    Unverified(String),
+}
+
+/// 一次动态定义请求（0x2C 01/02），同一 DDID 的多次定义按顺序追加
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DynamicDidDefinition {
+    ByIdentifier {
+        sources: Vec<DidSource>,
+    },
+    ByMemoryAddress {
+        address_and_length_format: u8,
+        sources: Vec<MemoryRange>,
+    },
+}
+
+impl DynamicDidDefinition {
+    /// 该次定义追加的数据字节数
+    pub fn data_length(&self) -> usize {
+        match self {
+            Self::ByIdentifier { sources } => sources.iter().map(|s| s.size as usize).sum(),
+            Self::ByMemoryAddress { sources, .. } => {
+                sources.iter().map(|r| r.length as usize).sum()
+            }
+        }
+    }
+
+    /// 编码为完整的 0x2C 请求
+    pub fn encode(&self, ddid: u16) -> UdsResult<Vec<u8>> {
+        let mut request = vec![0x2C];
+        match self {
+            Self::ByIdentifier { sources } => {
+                request.push(DynamicDefinitionType::DEFINE_BY_IDENTIFIER);
+                request.extend_from_slice(&ddid.to_be_bytes());
+                for source in sources {
+                    request.extend_from_slice(&source.source_did.to_be_bytes());
+                    request.push(source.position);
+                    request.push(source.size);
+                }
+            }
+            Self::ByMemoryAddress {
+                address_and_length_format,
+                sources,
+            } => {
+                request.push(DynamicDefinitionType::DEFINE_BY_MEMORY_ADDRESS);
+                request.extend_from_slice(&ddid.to_be_bytes());
+                // ALFID 只出现一次，之后是各段的地址和长度
+                let format = AddressAndLengthFormat::from_identifier(*address_and_length_format)?;
+                request.push(format.identifier());
+                for range in sources {
+                    request.extend_from_slice(&format.encode(range.address, range.length)?[1..]);
+                }
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// 内存段下载选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DownloadOptions {
+    pub data_format_identifier: u8,
+    pub address_and_length_format: u8,
+    pub max_block_length: Option<usize>, // 限制每个 TransferData 请求的长度（含 SID 和序号）
+    pub encoding: TransferEncoding,      // 压缩/加密配置，设置后覆盖 data_format_identifier
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            data_format_identifier: 0x00,
+            address_and_length_format: 0x44,
+            max_block_length: None,
+            encoding: TransferEncoding::default(),
+        }
+    }
+}
+
+/// 下载进度
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub address: u64,
+    pub total_bytes: usize,
+    pub transferred_bytes: usize,
+    pub block_sequence_counter: u8,
+}
+
+/// 内存段上传选项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadOptions {
+    pub address_and_length_format: u8,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            address_and_length_format: 0x44,
+        }
+    }
+}
+
+/// 上传进度
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub address: u64,
+    pub total_bytes: usize,
+    pub transferred_bytes: usize,
+    pub block_sequence_counter: u8,
+}
+
+/// 传输断点：ECU 已确认的最后一个数据块
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferCheckpoint {
+    pub block_sequence_counter: u8, // 尚未确认任何数据块时为 0x00
+    pub confirmed_bytes: usize,     // 已确认的传输字节数（压缩/加密后）
+    pub max_block_length: usize,
+}
+
 /// 诊断结果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiagnosticResult {
@@ -40,6 +408,8 @@ pub struct DiagnosticResult {
     pub message: String,
     pub data: Option<serde_json::Value>,
     pub timestamp: String,
+    #[serde(default)]
+    pub nrc: Option<NrcInfo>,
 }
 
 /// 连接配置
@@ -50,6 +420,8 @@ pub struct ConnectionConfig {
     pub server_address: String,
     pub client_address: String,
     pub timeout: Option<u64>,
+    #[serde(default)]
+    pub timing: Option<UdsTimingConfig>,
 }
 
 /// UDS 服务 ID 常量
@@ -67,6 +439,41 @@ impl UdsServices {
     pub const ROUTINE_CONTROL: u8 = 0x31;
     pub const TESTER_PRESENT: u8 = 0x3E;
     pub const CONTROL_DTC_SETTING: u8 = 0x85;
+
+    /// 根据服务 ID 获取 ISO 14229-1 服务名称
+    pub fn name(service_id: u8) -> Option<&'static str> {
+        let name = match service_id {
+            0x10 => "DiagnosticSessionControl",
+            0x11 => "ECUReset",
+            0x14 => "ClearDiagnosticInformation",
+            0x19 => "ReadDTCInformation",
+            0x22 => "ReadDataByIdentifier",
+            0x23 => "ReadMemoryByAddress",
+            0x24 => "ReadScalingDataByIdentifier",
+            0x27 => "SecurityAccess",
+            0x28 => "CommunicationControl",
+            0x29 => "Authentication",
+            0x2A => "ReadDataByPeriodicIdentifier",
+            0x2C => "DynamicallyDefineDataIdentifier",
+            0x2E => "WriteDataByIdentifier",
+            0x2F => "InputOutputControlByIdentifier",
+            0x31 => "RoutineControl",
+            0x34 => "RequestDownload",
+            0x35 => "RequestUpload",
+            0x36 => "TransferData",
+            0x37 => "RequestTransferExit",
+            0x38 => "RequestFileTransfer",
+            0x3D => "WriteMemoryByAddress",
+            0x3E => "TesterPresent",
+            0x83 => "AccessTimingParameter",
+            0x84 => "SecuredDataTransmission",
+            0x85 => "ControlDTCSetting",
+            0x86 => "ResponseOnEvent",
+            0x87 => "LinkControl",
+            _ => return None,
+        };
+        Some(name)
+    }
 }
 
 /// 常用 DID（数据标识符）常量
@@ -82,6 +489,56 @@ impl CommonDids {
     pub const ECU_INSTALLATION_DATE: u16 = 0xF18B;
 }
 
+/// 例程控制类型常量
+pub struct RoutineControlType;
+
+impl RoutineControlType {
+    pub const START_ROUTINE: u8 = 0x01;
+    pub const STOP_ROUTINE: u8 = 0x02;
+    pub const REQUEST_ROUTINE_RESULTS: u8 = 0x03;
+}
+
+/// 输入输出控制参数（inputOutputControlParameter）常量
+pub struct IoControlParameter;
+
+impl IoControlParameter {
+    pub const RETURN_CONTROL_TO_ECU: u8 = 0x00;
+    pub const RESET_TO_DEFAULT: u8 = 0x01;
+    pub const FREEZE_CURRENT_STATE: u8 = 0x02;
+    pub const SHORT_TERM_ADJUSTMENT: u8 = 0x03;
+}
+
+/// 周期数据传输模式（transmissionMode）常量
+pub struct PeriodicTransmissionMode;
+
+impl PeriodicTransmissionMode {
+    pub const SEND_AT_SLOW_RATE: u8 = 0x01;
+    pub const SEND_AT_MEDIUM_RATE: u8 = 0x02;
+    pub const SEND_AT_FAST_RATE: u8 = 0x03;
+    pub const STOP_SENDING: u8 = 0x04;
+}
+
+/// 动态定义数据标识符子功能（definitionType）常量
+pub struct DynamicDefinitionType;
+
+impl DynamicDefinitionType {
+    pub const DEFINE_BY_IDENTIFIER: u8 = 0x01;
+    pub const DEFINE_BY_MEMORY_ADDRESS: u8 = 0x02;
+    pub const CLEAR_DYNAMICALLY_DEFINED_DATA_IDENTIFIER: u8 = 0x03;
+}
+
+/// 常用例程标识符常量
+pub struct CommonRoutines;
+
+impl CommonRoutines {
+    pub const ERASE_MEMORY: u16 = 0xFF00;
+    pub const CHECK_PROGRAMMING_DEPENDENCIES: u16 = 0xFF01;
+    pub const ERASE_MIRROR_MEMORY_DTCS: u16 = 0xFF02;
+    // 以下为常见的主机厂自定义例程
+    pub const CHECK_MEMORY: u16 = 0x0202;
+    pub const CHECK_PROGRAMMING_PRECONDITIONS: u16 = 0x0203;
+}
+
 /// 会话类型常量
 pub struct SessionTypes;
 
@@ -128,6 +585,134 @@ impl DtcStatusMask {
     pub const WARNING_INDICATOR_REQUESTED: u8 = 0x80;
 }
 
+/// 定义否定响应码枚举及其码值、名称映射
+macro_rules! negative_response_codes {
+    ($($variant:ident = $code:literal => $name:literal,)*) => {
+        /// UDS 否定响应码（ISO 14229-1 表 A.1）
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum NegativeResponseCode {
+            $($variant,)*
+            /// 0xF0-0xFE 整车厂自定义条件
+            VehicleManufacturerSpecific(u8),
+            /// 保留或未知的码值
+            Reserved(u8),
+        }
+
+        impl NegativeResponseCode {
+            /// 获取 NRC 码值
+            pub fn code(&self) -> u8 {
+                match self {
+                    $(Self::$variant => $code,)*
+                    Self::VehicleManufacturerSpecific(code) | Self::Reserved(code) => *code,
+                }
+            }
+
+            /// 获取 ISO 14229-1 中的名称
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                    Self::VehicleManufacturerSpecific(_) => "vehicleManufacturerSpecificConditionsNotCorrect",
+                    Self::Reserved(_) => "isoSAEReserved",
+                }
+            }
+        }
+
+        impl From<u8> for NegativeResponseCode {
+            fn from(code: u8) -> Self {
+                match code {
+                    $($code => Self::$variant,)*
+                    0xF0..=0xFE => Self::VehicleManufacturerSpecific(code),
+                    _ => Self::Reserved(code),
+                }
+            }
+        }
+    };
+}
+
+negative_response_codes! {
+    GeneralReject = 0x10 => "generalReject",
+    ServiceNotSupported = 0x11 => "serviceNotSupported",
+    SubFunctionNotSupported = 0x12 => "subFunctionNotSupported",
+    IncorrectMessageLengthOrInvalidFormat = 0x13 => "incorrectMessageLengthOrInvalidFormat",
+    ResponseTooLong = 0x14 => "responseTooLong",
+    BusyRepeatRequest = 0x21 => "busyRepeatRequest",
+    ConditionsNotCorrect = 0x22 => "conditionsNotCorrect",
+    RequestSequenceError = 0x24 => "requestSequenceError",
+    NoResponseFromSubnetComponent = 0x25 => "noResponseFromSubnetComponent",
+    FailurePreventsExecutionOfRequestedAction = 0x26 => "failurePreventsExecutionOfRequestedAction",
+    RequestOutOfRange = 0x31 => "requestOutOfRange",
+    SecurityAccessDenied = 0x33 => "securityAccessDenied",
+    AuthenticationRequired = 0x34 => "authenticationRequired",
+    InvalidKey = 0x35 => "invalidKey",
+    ExceedNumberOfAttempts = 0x36 => "exceedNumberOfAttempts",
+    RequiredTimeDelayNotExpired = 0x37 => "requiredTimeDelayNotExpired",
+    SecureDataTransmissionRequired = 0x38 => "secureDataTransmissionRequired",
+    SecureDataTransmissionNotAllowed = 0x39 => "secureDataTransmissionNotAllowed",
+    SecureDataVerificationFailed = 0x3A => "secureDataVerificationFailed",
+    CertificateVerificationFailedInvalidTimePeriod = 0x50 => "certificateVerificationFailedInvalidTimePeriod",
+    CertificateVerificationFailedInvalidSignature = 0x51 => "certificateVerificationFailedInvalidSignature",
+    CertificateVerificationFailedInvalidChainOfTrust = 0x52 => "certificateVerificationFailedInvalidChainOfTrust",
+    CertificateVerificationFailedInvalidType = 0x53 => "certificateVerificationFailedInvalidType",
+    CertificateVerificationFailedInvalidFormat = 0x54 => "certificateVerificationFailedInvalidFormat",
+    CertificateVerificationFailedInvalidContent = 0x55 => "certificateVerificationFailedInvalidContent",
+    CertificateVerificationFailedInvalidScope = 0x56 => "certificateVerificationFailedInvalidScope",
+    CertificateVerificationFailedInvalidCertificate = 0x57 => "certificateVerificationFailedInvalidCertificate",
+    OwnershipVerificationFailed = 0x58 => "ownershipVerificationFailed",
+    ChallengeCalculationFailed = 0x59 => "challengeCalculationFailed",
+    SettingAccessRightsFailed = 0x5A => "settingAccessRightsFailed",
+    SessionKeyCreationDerivationFailed = 0x5B => "sessionKeyCreationDerivationFailed",
+    ConfigurationDataUsageFailed = 0x5C => "configurationDataUsageFailed",
+    DeAuthenticationFailed = 0x5D => "deAuthenticationFailed",
+    UploadDownloadNotAccepted = 0x70 => "uploadDownloadNotAccepted",
+    TransferDataSuspended = 0x71 => "transferDataSuspended",
+    GeneralProgrammingFailure = 0x72 => "generalProgrammingFailure",
+    WrongBlockSequenceCounter = 0x73 => "wrongBlockSequenceCounter",
+    RequestCorrectlyReceivedResponsePending = 0x78 => "requestCorrectlyReceived-ResponsePending",
+    SubFunctionNotSupportedInActiveSession = 0x7E => "subFunctionNotSupportedInActiveSession",
+    ServiceNotSupportedInActiveSession = 0x7F => "serviceNotSupportedInActiveSession",
+    RpmTooHigh = 0x81 => "rpmTooHigh",
+    RpmTooLow = 0x82 => "rpmTooLow",
+    EngineIsRunning = 0x83 => "engineIsRunning",
+    EngineIsNotRunning = 0x84 => "engineIsNotRunning",
+    EngineRunTimeTooLow = 0x85 => "engineRunTimeTooLow",
+    TemperatureTooHigh = 0x86 => "temperatureTooHigh",
+    TemperatureTooLow = 0x87 => "temperatureTooLow",
+    VehicleSpeedTooHigh = 0x88 => "vehicleSpeedTooHigh",
+    VehicleSpeedTooLow = 0x89 => "vehicleSpeedTooLow",
+    ThrottlePedalTooHigh = 0x8A => "throttle/PedalTooHigh",
+    ThrottlePedalTooLow = 0x8B => "throttle/PedalTooLow",
+    TransmissionRangeNotInNeutral = 0x8C => "transmissionRangeNotInNeutral",
+    TransmissionRangeNotInGear = 0x8D => "transmissionRangeNotInGear",
+    BrakeSwitchNotClosed = 0x8F => "brakeSwitch(es)NotClosed",
+    ShifterLeverNotInPark = 0x90 => "shifterLeverNotInPark",
+    TorqueConverterClutchLocked = 0x91 => "torqueConverterClutchLocked",
+    VoltageTooHigh = 0x92 => "voltageTooHigh",
+    VoltageTooLow = 0x93 => "voltageTooLow",
+    ResourceTemporarilyNotAvailable = 0x94 => "resourceTemporarilyNotAvailable",
+}
+
+impl std::fmt::Display for NegativeResponseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (0x{:02X})", self.name(), self.code())
+    }
+}
+
+/// 返回给前端的 NRC 信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NrcInfo {
+    pub code: u8,
+    pub name: String,
+}
+
+impl From<NegativeResponseCode> for NrcInfo {
+    fn from(nrc: NegativeResponseCode) -> Self {
+        Self {
+            code: nrc.code(),
+            name: nrc.name().to_string(),
+        }
+    }
+}
+
 /// 错误类型定义
 #[derive(Debug, thiserror::Error)]
 pub enum DoipError {
@@ -177,9 +762,138 @@ pub enum UdsError {
     #[error("Response timeout")]
     ResponseTimeout,
 
+    #[error("Timing violation: {0}")]
+    TimingViolation(String),
+
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
+
+    #[error("Routine 0x{0:04X} did not complete in time")]
+    RoutineTimeout(u16),
+
+    #[error("Step '{0}' timed out")]
+    StepTimeout(String),
+
+    #[error("Operation aborted")]
+    Aborted,
+
+    #[error("Data format error: {0}")]
+    DataFormat(String),
+
+    #[error("Verification failed: {0}")]
+    VerificationFailed(String),
+
+    #[error("Flash data verification failed: {}", .0.failures().join("; "))]
+    ImageVerificationFailed(Box<VerificationReport>),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+
+    #[error("Negative response to service 0x{service:02X}: {nrc}")]
+    NegativeResponse {
+        service: u8,
+        nrc: NegativeResponseCode,
+    },
+}
+
+impl UdsError {
+    /// 获取否定响应码（若错误来自否定响应）
+    pub fn nrc(&self) -> Option<NegativeResponseCode> {
+        match self {
+            UdsError::NegativeResponse { nrc, .. } => Some(*nrc),
+            _ => None,
+        }
+    }
+}
+
+/// 刷写文件解析错误类型
+#[derive(Debug, thiserror::Error)]
+pub enum ImageError {
+    #[error("Line {line}: {reason}")]
+    InvalidRecord { line: usize, reason: String },
+
+    #[error("Line {line}: checksum mismatch (expected 0x{expected:02X}, found 0x{found:02X})")]
+    ChecksumMismatch {
+        line: usize,
+        expected: u8,
+        found: u8,
+    },
+
+    #[error("Overlapping data at 0x{0:X}")]
+    Overlap(u64),
+
+    #[error("Unsupported image format: {0}")]
+    UnsupportedFormat(String),
+
+    #[error("Address 0x{0:X} exceeds the range of the output format")]
+    AddressOutOfRange(u64),
+
+    #[error("Invalid VBF: {0}")]
+    InvalidVbf(String),
+
+    #[error(
+        "Block at 0x{address:X}: checksum mismatch (expected 0x{expected:X}, found 0x{found:X})"
+    )]
+    BlockChecksumMismatch {
+        address: u64,
+        expected: u32,
+        found: u32,
+    },
+
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, DoipError>;
 pub type UdsResult<T> = std::result::Result<T, UdsError>;
+pub type ImageResult<T> = std::result::Result<T, ImageError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_and_length_format_encode() {
+        let format = AddressAndLengthFormat::default();
+        assert_eq!(format.identifier(), 0x44);
+        assert_eq!(
+            format.encode(0x0800_0000, 0x400).unwrap(),
+            vec![0x44, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00]
+        );
+
+        let format = AddressAndLengthFormat::from_identifier(0x23).unwrap();
+        assert_eq!(format.address_bytes, 3);
+        assert_eq!(format.length_bytes, 2);
+        assert_eq!(
+            format.encode(0x01_2345, 0x10).unwrap(),
+            vec![0x23, 0x01, 0x23, 0x45, 0x00, 0x10]
+        );
+        assert!(format.encode(0x0100_0000, 0x10).is_err());
+        assert!(format.encode(0, 0x1_0000).is_err());
+
+        assert!(AddressAndLengthFormat::from_identifier(0x40).is_err());
+        assert!(AddressAndLengthFormat::from_identifier(0x94).is_err());
+    }
+
+    #[test]
+    fn test_negative_response_code_mapping() {
+        let nrc = NegativeResponseCode::from(0x22);
+        assert_eq!(nrc, NegativeResponseCode::ConditionsNotCorrect);
+        assert_eq!(nrc.code(), 0x22);
+        assert_eq!(nrc.name(), "conditionsNotCorrect");
+        assert_eq!(nrc.to_string(), "conditionsNotCorrect (0x22)");
+    }
+
+    #[test]
+    fn test_negative_response_code_unknown() {
+        assert_eq!(
+            NegativeResponseCode::from(0xF3),
+            NegativeResponseCode::VehicleManufacturerSpecific(0xF3)
+        );
+        assert_eq!(
+            NegativeResponseCode::from(0x15),
+            NegativeResponseCode::Reserved(0x15)
+        );
+        assert_eq!(NegativeResponseCode::from(0x15).code(), 0x15);
+    }
+}
@@ -2,17 +2,34 @@
  * UDS 客户端管理器 - Rust 实现
  * 提供高级的 UDS 诊断服务接口，用于 Tauri 应用
  */
-use crate::doip_client::DoipClient;
-use crate::types::{ConnectionConfig, DiagnosticResult, DoipClientConfig, UdsConfig, UdsServices};
+use crate::did_codec::{DidDefinition, PhysicalValue};
+use crate::flash_job::{FlashJob, FlashProgress};
+use crate::memory_dump::MemoryDumpJob;
+use crate::memory_view::MemoryPage;
+use crate::periodic::PeriodicSink;
+use crate::traffic::TrafficSink;
+use crate::types::{
+    ConnectionConfig, DiagnosticResult, DownloadOptions, DownloadProgress, DynamicDidDefinition,
+    MemoryAccessOptions, NegativeResponseCode, NrcInfo, PeriodicTransmissionMode,
+    RoutinePollConfig, UdsError, UdsServices, UploadProgress, WriteVerification,
+};
+use crate::uds_response::suppresses_positive_response;
 use crate::uds_service::UdsService;
 use crate::utils::{get_timestamp, hex_to_bytes};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 pub struct UdsClientManager {
     uds_service: Option<UdsService>,
     is_connected: bool,
     connection_config: Option<ConnectionConfig>,
+    traffic_sink: Option<TrafficSink>,
+    periodic_sink: Option<PeriodicSink>,
 }
 
+/// 断开前交还输入输出控制和停止周期传输的总时长上限
+const DISCONNECT_CLEANUP_TIMEOUT: Duration = Duration::from_secs(2);
+
 impl UdsClientManager {
     /// 创建新的 UDS 客户端管理器
     pub fn new() -> Self {
@@ -20,107 +37,127 @@ impl UdsClientManager {
             uds_service: None,
             is_connected: false,
             connection_config: None,
+            traffic_sink: None,
+            periodic_sink: None,
         }
     }
 
+    /// 设置报文事件接收端，之后建立的连接都会推送收发帧
+    pub fn set_traffic_sink(&mut self, sink: TrafficSink) {
+        self.traffic_sink = Some(sink);
+    }
+
+    /// 设置周期数据接收端，连接后由转发任务把周期数据通道中的样本交给它
+    pub fn set_periodic_sink(&mut self, sink: PeriodicSink) {
+        self.periodic_sink = Some(sink);
+    }
+
     /// 连接到 ECU
     pub async fn connect(&mut self, config: ConnectionConfig) -> DiagnosticResult {
         self.connection_config = Some(config.clone());
 
-        let doip_config = DoipClientConfig {
-            ip_address: config.ip_address.clone(),
-            port: config.port,
-            timeout: config.timeout,
-        };
-
-        let mut doip_client = DoipClient::new(doip_config);
-
-        match doip_client.connect().await {
-            Ok(true) => {
-                println!("TCP connection established successfully");
+        match UdsService::connect(&config, self.traffic_sink.clone()).await {
+            Ok(mut uds_service) => {
+                // 转发任务不持有管理器锁，通道随连接关闭而结束
+                if let (Some(sink), Some(mut samples)) = (
+                    self.periodic_sink.clone(),
+                    uds_service.take_periodic_receiver(),
+                ) {
+                    tokio::spawn(async move {
+                        while let Some(sample) = samples.recv().await {
+                            sink(sample);
+                        }
+                    });
+                }
+                self.uds_service = Some(uds_service);
+                self.is_connected = true;
 
-                // 创建 UDS 服务
-                let uds_config = UdsConfig {
-                    vehicle_info: crate::types::VehicleConfig {
-                        server_address: config.server_address.clone(),
-                        client_address: config.client_address.clone(),
-                    },
+                DiagnosticResult {
+                    success: true,
+                    message: format!(
+                        "成功连接到ECU {}:{} 并完成路由激活",
+                        config.ip_address, config.port
+                    ),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                }
+            }
+            Err(e) => {
+                let message = match &e {
+                    UdsError::DoipError(_) => format!("连接ECU失败: {}", e),
+                    _ => format!("路由激活失败: {}", e),
                 };
-
-                println!(
-                    "Creating UDS service with server_address: {}, client_address: {}",
-                    config.server_address, config.client_address
-                );
-
-                match UdsService::new(doip_client, uds_config) {
-                    Ok(mut uds_service) => {
-                        // 执行路由激活
-                        match uds_service.routine_active().await {
-                            Ok(true) => {
-                                self.uds_service = Some(uds_service);
-                                self.is_connected = true;
-
-                                DiagnosticResult {
-                                    success: true,
-                                    message: format!(
-                                        "成功连接到ECU {}:{} 并完成路由激活",
-                                        config.ip_address, config.port
-                                    ),
-                                    data: None,
-                                    timestamp: get_timestamp(),
-                                }
-                            }
-                            Ok(false) => DiagnosticResult {
-                                success: false,
-                                message: "路由激活被拒绝".to_string(),
-                                data: None,
-                                timestamp: get_timestamp(),
-                            },
-                            Err(e) => {
-                                // 记录详细的路由激活失败信息
-                                eprintln!("Routing activation failed: {:?}", e);
-                                DiagnosticResult {
-                                    success: false,
-                                    message: format!("路由激活失败: {:?}", e),
-                                    data: None,
-                                    timestamp: get_timestamp(),
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => DiagnosticResult {
-                        success: false,
-                        message: format!("创建UDS服务失败: {}", e),
-                        data: None,
-                        timestamp: get_timestamp(),
-                    },
+                DiagnosticResult {
+                    success: false,
+                    message,
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
                 }
             }
-            Ok(false) => DiagnosticResult {
-                success: false,
-                message: "连接ECU失败".to_string(),
-                data: None,
-                timestamp: get_timestamp(),
-            },
-            Err(e) => DiagnosticResult {
-                success: false,
-                message: format!("连接ECU失败: {:?}", e),
-                data: None,
-                timestamp: get_timestamp(),
-            },
         }
     }
 
     /// 断开连接
     pub async fn disconnect(&mut self) -> DiagnosticResult {
+        // 断开前交还测试仪接管的输入输出控制，连接已断开时跳过，总耗时有上限
+        let mut failed_controls = Vec::new();
+        if let Some(uds_service) = self.uds_service.as_mut() {
+            let controls = uds_service.active_io_controls();
+            if uds_service.is_connected() {
+                let cleanup = async {
+                    let failed = uds_service.return_io_controls().await;
+                    if !uds_service.active_periodic_ids().is_empty() {
+                        let _ = uds_service
+                            .read_data_by_periodic_identifier(
+                                PeriodicTransmissionMode::STOP_SENDING,
+                                &[],
+                            )
+                            .await;
+                    }
+                    failed
+                };
+                failed_controls =
+                    match tokio::time::timeout(DISCONNECT_CLEANUP_TIMEOUT, cleanup).await {
+                        Ok(failed) => failed,
+                        Err(_) => {
+                            log::warn!("Returning IO controls timed out before disconnect");
+                            uds_service.active_io_controls()
+                        }
+                    };
+            } else {
+                failed_controls = controls.clone();
+            }
+            if !controls.is_empty() {
+                log::info!(
+                    "Returned {} of {} IO controls to ECU before disconnect",
+                    controls.len() - failed_controls.len(),
+                    controls.len()
+                );
+            }
+        }
         self.uds_service = None;
         self.is_connected = false;
 
+        let message = if failed_controls.is_empty() {
+            "已断开ECU连接".to_string()
+        } else {
+            let dids: Vec<String> = failed_controls
+                .iter()
+                .map(|did| format!("0x{:04X}", did))
+                .collect();
+            format!(
+                "已断开ECU连接，以下输入输出控制交还失败: {}",
+                dids.join(", ")
+            )
+        };
         DiagnosticResult {
             success: true,
-            message: "已断开ECU连接".to_string(),
+            message,
             data: None,
             timestamp: get_timestamp(),
+            nrc: None,
         }
     }
 
@@ -137,6 +174,7 @@ impl UdsClientManager {
                 message: "未连接到ECU".to_string(),
                 data: None,
                 timestamp: get_timestamp(),
+                nrc: None,
             };
         }
 
@@ -148,6 +186,7 @@ impl UdsClientManager {
                     message: format!("无效的服务ID: {}", service_id),
                     data: None,
                     timestamp: get_timestamp(),
+                    nrc: None,
                 };
             }
         };
@@ -160,6 +199,7 @@ impl UdsClientManager {
                     message: format!("无效的数据格式: {}", e),
                     data: None,
                     timestamp: get_timestamp(),
+                    nrc: None,
                 };
             }
         };
@@ -174,8 +214,8 @@ impl UdsClientManager {
                     0x01
                 };
                 match uds_service.start_session(session).await {
-                    Ok(success) => (success, "会话控制".to_string(), None),
-                    Err(e) => (false, format!("会话控制失败: {}", e), None),
+                    Ok(success) => (success, "会话控制".to_string(), None, None),
+                    Err(e) => (false, format!("会话控制失败: {}", e), None, e.nrc()),
                 }
             }
 
@@ -186,15 +226,15 @@ impl UdsClientManager {
                     0x01
                 };
                 match uds_service.ecu_reset(reset_type).await {
-                    Ok(success) => (success, "ECU重启".to_string(), None),
-                    Err(e) => (false, format!("ECU重启失败: {}", e), None),
+                    Ok(success) => (success, "ECU重启".to_string(), None, None),
+                    Err(e) => (false, format!("ECU重启失败: {}", e), None, e.nrc()),
                 }
             }
 
             UdsServices::CLEAR_DIAGNOSTIC_INFORMATION => {
                 match uds_service.clear_diagnostic_information().await {
-                    Ok(success) => (success, "清除DTC".to_string(), None),
-                    Err(e) => (false, format!("清除DTC失败: {}", e), None),
+                    Ok(success) => (success, "清除DTC".to_string(), None, None),
+                    Err(e) => (false, format!("清除DTC失败: {}", e), None, e.nrc()),
                 }
             }
 
@@ -205,8 +245,13 @@ impl UdsClientManager {
                     0x02
                 };
                 match uds_service.read_dtc_information(sub_func).await {
-                    Ok(response) => (response.success, "读取DTC信息".to_string(), response.data),
-                    Err(e) => (false, format!("读取DTC信息失败: {}", e), None),
+                    Ok(response) => (
+                        response.success,
+                        "读取DTC信息".to_string(),
+                        response.data,
+                        None,
+                    ),
+                    Err(e) => (false, format!("读取DTC信息失败: {}", e), None, e.nrc()),
                 }
             }
 
@@ -218,33 +263,36 @@ impl UdsClientManager {
                             response.success,
                             format!("读取DID 0x{:04x}", did),
                             response.data,
+                            None,
                         ),
-                        Err(e) => (false, format!("读取DID失败: {}", e), None),
+                        Err(e) => (false, format!("读取DID失败: {}", e), None, e.nrc()),
                     }
                 } else {
-                    (false, "DID参数不足".to_string(), None)
+                    (false, "DID参数不足".to_string(), None, None)
                 }
             }
 
             UdsServices::SECURITY_ACCESS => {
                 if data_bytes.len() >= 2 {
                     let level = data_bytes[1];
-                    if level % 2 == 1 {
+                    if (level & 0x7F) % 2 == 1 {
                         // 奇数级别：获取种子
                         match uds_service.security_access_get_seed(level).await {
-                            Ok(success) => (success, "获取安全访问种子".to_string(), None),
-                            Err(e) => (false, format!("获取安全访问种子失败: {}", e), None),
+                            Ok(success) => (success, "获取安全访问种子".to_string(), None, None),
+                            Err(e) => {
+                                (false, format!("获取安全访问种子失败: {}", e), None, e.nrc())
+                            }
                         }
                     } else {
                         // 偶数级别：发送密钥（这里需要实际的密钥计算）
                         let key = 0x1234u32; // 示例密钥，实际应用中需要正确计算
                         match uds_service.security_access_compare_key(level, key).await {
-                            Ok(success) => (success, "安全访问验证".to_string(), None),
-                            Err(e) => (false, format!("安全访问验证失败: {}", e), None),
+                            Ok(success) => (success, "安全访问验证".to_string(), None, None),
+                            Err(e) => (false, format!("安全访问验证失败: {}", e), None, e.nrc()),
                         }
                     }
                 } else {
-                    (false, "安全访问级别参数不足".to_string(), None)
+                    (false, "安全访问级别参数不足".to_string(), None, None)
                 }
             }
 
@@ -255,33 +303,57 @@ impl UdsClientManager {
                     0x00
                 };
                 match uds_service.communication_control(comm_type).await {
-                    Ok(success) => (success, "通信控制".to_string(), None),
-                    Err(e) => (false, format!("通信控制失败: {}", e), None),
+                    Ok(success) => (success, "通信控制".to_string(), None, None),
+                    Err(e) => (false, format!("通信控制失败: {}", e), None, e.nrc()),
                 }
             }
 
             UdsServices::WRITE_DATA_BY_IDENTIFIER => {
                 if data_bytes.len() >= 3 {
                     let did = ((data_bytes[1] as u16) << 8) | (data_bytes[2] as u16);
-                    let write_data = String::from_utf8_lossy(&data_bytes[3..]);
-                    match uds_service.write_data_by_identifier(did, &write_data).await {
+                    match uds_service
+                        .write_data_by_identifier(did, &data_bytes[3..])
+                        .await
+                    {
                         Ok(response) => (
                             response.success,
                             format!("写入DID 0x{:04x}", did),
                             response.data,
+                            None,
+                        ),
+                        Err(e) => (false, format!("写入DID失败: {}", e), None, e.nrc()),
+                    }
+                } else {
+                    (false, "写入DID参数不足".to_string(), None, None)
+                }
+            }
+
+            UdsServices::ROUTINE_CONTROL => {
+                if data_bytes.len() >= 4 {
+                    let control_type = data_bytes[1];
+                    let routine_id = u16::from_be_bytes([data_bytes[2], data_bytes[3]]);
+                    match uds_service
+                        .routine_control(control_type, routine_id, &data_bytes[4..])
+                        .await
+                    {
+                        Ok(status) => (
+                            true,
+                            format!("例程控制 0x{:04X}", routine_id),
+                            status.map(|s| s.status_record),
+                            None,
                         ),
-                        Err(e) => (false, format!("写入DID失败: {}", e), None),
+                        Err(e) => (false, format!("例程控制失败: {}", e), None, e.nrc()),
                     }
                 } else {
-                    (false, "写入DID参数不足".to_string(), None)
+                    (false, "例程控制参数不足".to_string(), None, None)
                 }
             }
 
             UdsServices::TESTER_PRESENT => {
-                let suppress_resp = data_bytes.len() > 1 && data_bytes[1] == 0x80;
+                let suppress_resp = data_bytes.len() > 1 && data_bytes[1] & 0x80 != 0;
                 match uds_service.tester_present(suppress_resp).await {
-                    Ok(success) => (success, "测试器在线".to_string(), None),
-                    Err(e) => (false, format!("测试器在线失败: {}", e), None),
+                    Ok(success) => (success, "测试器在线".to_string(), None, None),
+                    Err(e) => (false, format!("测试器在线失败: {}", e), None, e.nrc()),
                 }
             }
 
@@ -292,16 +364,23 @@ impl UdsClientManager {
                     0x02
                 };
                 match uds_service.control_dtc_setting(dtc_type).await {
-                    Ok(success) => (success, "DTC控制".to_string(), None),
-                    Err(e) => (false, format!("DTC控制失败: {}", e), None),
+                    Ok(success) => (success, "DTC控制".to_string(), None, None),
+                    Err(e) => (false, format!("DTC控制失败: {}", e), None, e.nrc()),
                 }
             }
 
-            _ => (false, format!("不支持的UDS服务: 0x{:02x}", service), None),
+            _ => (
+                false,
+                format!("不支持的UDS服务: 0x{:02x}", service),
+                None,
+                None,
+            ),
         };
 
-        let (success, message, response_data) = result;
-        let final_message = if success {
+        let (success, message, response_data, nrc) = result;
+        let final_message = if success && suppresses_positive_response(&data_bytes) {
+            format!("{}成功（已抑制正响应）", message)
+        } else if success {
             format!("{}成功", message)
         } else {
             message
@@ -312,6 +391,806 @@ impl UdsClientManager {
             message: final_message,
             data: response_data.map(|d| serde_json::Value::String(hex::encode(d))),
             timestamp: get_timestamp(),
+            nrc: nrc.map(NrcInfo::from),
+        }
+    }
+
+    /// 启动例程并轮询结果直到例程结束
+    pub async fn run_routine(
+        &mut self,
+        routine_id: u16,
+        option_record: &str,
+        poll: RoutinePollConfig,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let option_record = match self.hex_string_to_bytes(option_record) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service
+            .run_routine(routine_id, &option_record, &poll)
+            .await
+        {
+            Ok(status) => DiagnosticResult {
+                success: true,
+                message: format!("例程 0x{:04X} 执行完成", routine_id),
+                data: Some(serde_json::json!({
+                    "routine_id": status.routine_id,
+                    "routine_info": status.routine_info(),
+                    "status_record": hex::encode(&status.status_record),
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("例程 0x{:04X} 执行失败: {}", routine_id, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 下载内存段，每个数据块完成后回调进度
+    pub async fn download_segment<F>(
+        &mut self,
+        address: u64,
+        data: &str,
+        options: DownloadOptions,
+        on_progress: F,
+    ) -> DiagnosticResult
+    where
+        F: FnMut(DownloadProgress),
+    {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let data = match self.hex_string_to_bytes(data) {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            Ok(_) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: "下载数据为空".to_string(),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service
+            .download_segment(address, &data, &options, on_progress)
+            .await
+        {
+            Ok(block_count) => DiagnosticResult {
+                success: true,
+                message: format!("下载 {} 字节到 0x{:X} 成功", data.len(), address),
+                data: Some(serde_json::json!({
+                    "address": address,
+                    "size": data.len(),
+                    "block_count": block_count,
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("下载到 0x{:X} 失败: {}", address, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 执行刷写任务，任务的中止标志置位时在下一个步骤或数据块前中止
+    pub async fn run_flash_job<F>(&mut self, job: FlashJob, on_progress: F) -> DiagnosticResult
+    where
+        F: FnMut(FlashProgress) + Send,
+    {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        if job.segment_count() == 0 {
+            return DiagnosticResult {
+                success: false,
+                message: "没有需要刷写的数据".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match job.run(uds_service, on_progress).await {
+            Ok(report) => DiagnosticResult {
+                success: true,
+                message: format!(
+                    "刷写完成，共 {} 个内存段 {} 字节",
+                    report.segment_count, report.total_bytes
+                ),
+                data: serde_json::to_value(&report).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(UdsError::Aborted) => DiagnosticResult {
+                success: false,
+                message: "刷写已中止".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(UdsError::ImageVerificationFailed(report)) => DiagnosticResult {
+                success: false,
+                message: format!("刷写数据校验失败: {}", report.failures().join("; ")),
+                data: serde_json::to_value(&report).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("刷写失败: {}", e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 输入输出控制（0x2F），返回 controlStatusRecord 和当前接管的 DID
+    pub async fn io_control(
+        &mut self,
+        did: u16,
+        parameter: u8,
+        control_state: &str,
+        enable_mask: &str,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let (control_state, enable_mask) = match (
+            self.hex_string_to_bytes(control_state),
+            self.hex_string_to_bytes(enable_mask),
+        ) {
+            (Ok(state), Ok(mask)) => (state, mask),
+            (Err(e), _) | (_, Err(e)) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service
+            .input_output_control(did, parameter, &control_state, &enable_mask)
+            .await
+        {
+            Ok(status) => DiagnosticResult {
+                success: true,
+                message: format!("输入输出控制 0x{:04X} 成功", did),
+                data: Some(serde_json::json!({
+                    "did": did,
+                    "parameter": parameter,
+                    "control_status": hex::encode(&status),
+                    "active_controls": uds_service.active_io_controls(),
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("输入输出控制 0x{:04X} 失败: {}", did, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 按周期标识符读取数据（0x2A），periodic_ids 为周期 DID 低字节的十六进制串
+    pub async fn read_periodic(&mut self, mode: u8, periodic_ids: &str) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let periodic_ids = match self.hex_string_to_bytes(periodic_ids) {
+            Ok(ids) => ids,
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service
+            .read_data_by_periodic_identifier(mode, &periodic_ids)
+            .await
+        {
+            Ok(()) => DiagnosticResult {
+                success: true,
+                message: format!("周期数据传输模式 0x{:02X} 设置成功", mode),
+                data: Some(serde_json::json!({
+                    "mode": mode,
+                    "active_ids": uds_service.active_periodic_ids(),
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("周期数据传输模式 0x{:02X} 设置失败: {}", mode, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 一次请求读取多个 DID（0x22），lengths 为各 DID 的数据长度表
+    pub async fn read_data_identifiers(
+        &mut self,
+        dids: &[u16],
+        lengths: &BTreeMap<u16, usize>,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.read_data_by_identifiers(dids, lengths).await {
+            Ok(records) => DiagnosticResult {
+                success: true,
+                message: format!("读取 {}/{} 个DID", records.len(), dids.len()),
+                data: Some(serde_json::json!(records)),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("读取DID失败: {}", e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 写入数据标识符（0x2E）并读回校验，data 为数据记录的十六进制串
+    pub async fn write_data_identifier(&mut self, did: u16, data: &str) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let data = match self.hex_string_to_bytes(data) {
+            Ok(data) => data,
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.write_data_verified(did, &data).await {
+            Ok(WriteVerification::Verified(read_back)) => DiagnosticResult {
+                success: true,
+                message: format!("写入DID 0x{:04X} 并校验成功", did),
+                data: Some(serde_json::json!({
+                    "did": did,
+                    "data": hex::encode(&read_back),
+                    "verified": true,
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Ok(WriteVerification::Unverified(reason)) => DiagnosticResult {
+                success: true,
+                message: format!("写入DID 0x{:04X} 成功，读回校验未完成: {}", did, reason),
+                data: Some(serde_json::json!({
+                    "did": did,
+                    "data": hex::encode(&data),
+                    "verified": false,
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("写入DID 0x{:04X} 失败: {}", did, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 按 DID 定义将物理值编码后写入并读回校验，返回读回数据解码后的物理值
+    pub async fn write_did_value(
+        &mut self,
+        definition: &DidDefinition,
+        value: &PhysicalValue,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let data = match definition.encode(value) {
+            Ok(data) => data,
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("物理值编码失败: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+        let did = definition.did;
+
+        match uds_service.write_data_verified(did, &data).await {
+            Ok(WriteVerification::Verified(read_back)) => DiagnosticResult {
+                success: true,
+                message: format!("写入DID 0x{:04X} 并校验成功", did),
+                data: Some(serde_json::json!({
+                    "did": did,
+                    "data": hex::encode(&read_back),
+                    "value": definition.decode(&read_back).ok(),
+                    "verified": true,
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Ok(WriteVerification::Unverified(reason)) => DiagnosticResult {
+                success: true,
+                message: format!("写入DID 0x{:04X} 成功，读回校验未完成: {}", did, reason),
+                data: Some(serde_json::json!({
+                    "did": did,
+                    "data": hex::encode(&data),
+                    "value": value,
+                    "verified": false,
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("写入DID 0x{:04X} 失败: {}", did, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 动态定义数据标识符（0x2C），返回当前已定义的 DDID
+    pub async fn define_dynamic_did(
+        &mut self,
+        ddid: u16,
+        definition: &DynamicDidDefinition,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.define_dynamic_did(ddid, definition).await {
+            Ok(()) => DiagnosticResult {
+                success: true,
+                message: format!("动态定义 DID 0x{:04X} 成功", ddid),
+                data: Some(serde_json::json!({
+                    "ddid": ddid,
+                    "dynamic_dids": uds_service.dynamic_dids(),
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("动态定义 DID 0x{:04X} 失败: {}", ddid, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 清除动态 DID（0x2C 03），ddid 为 None 时清除全部
+    pub async fn clear_dynamic_did(&mut self, ddid: Option<u16>) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.clear_dynamic_did(ddid).await {
+            Ok(()) => DiagnosticResult {
+                success: true,
+                message: "清除动态 DID 成功".to_string(),
+                data: Some(serde_json::json!({
+                    "dynamic_dids": uds_service.dynamic_dids(),
+                })),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("清除动态 DID 失败: {}", e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 按记录重新定义所有动态 DID，用于 ECU 复位之后
+    pub async fn restore_dynamic_dids(&mut self) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+        let total = uds_service.dynamic_dids().len();
+        let restored = uds_service.restore_dynamic_dids().await;
+
+        DiagnosticResult {
+            success: restored == total,
+            message: format!("重新定义动态 DID {}/{}", restored, total),
+            data: Some(serde_json::json!({
+                "restored": restored,
+                "dynamic_dids": uds_service.dynamic_dids(),
+            })),
+            timestamp: get_timestamp(),
+            nrc: None,
+        }
+    }
+
+    /// 读取一页内存（0x23），返回按行格式化的内存页
+    pub async fn read_memory_page(
+        &mut self,
+        address: u64,
+        length: usize,
+        options: MemoryAccessOptions,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        if length == 0 {
+            return DiagnosticResult {
+                success: false,
+                message: "读取长度不能为 0".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.read_memory(address, length, &options).await {
+            Ok(data) => DiagnosticResult {
+                success: true,
+                message: format!("读取 0x{:X} 起 {} 字节成功", address, data.len()),
+                data: serde_json::to_value(MemoryPage::new(address, data)).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("读取 0x{:X} 失败: {}", address, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 写入内存（0x3D）后读回校验，返回写入区域的内存页
+    pub async fn write_memory(
+        &mut self,
+        address: u64,
+        data: &str,
+        options: MemoryAccessOptions,
+    ) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let data = match self.hex_string_to_bytes(data) {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            Ok(_) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: "写入数据为空".to_string(),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        let result = match uds_service.write_memory(address, &data, &options).await {
+            Ok(()) => uds_service.read_memory(address, data.len(), &options).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(read_back) if read_back == data => DiagnosticResult {
+                success: true,
+                message: format!("写入 0x{:X} 起 {} 字节成功", address, data.len()),
+                data: serde_json::to_value(MemoryPage::new(address, read_back)).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Ok(read_back) => DiagnosticResult {
+                success: false,
+                message: format!("写入 0x{:X} 后读回数据不一致", address),
+                data: serde_json::to_value(MemoryPage::new(address, read_back)).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("写入 0x{:X} 失败: {}", address, e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 执行内存转储任务，读回的数据写入 path
+    pub async fn dump_memory<F>(
+        &mut self,
+        job: MemoryDumpJob,
+        path: &str,
+        on_progress: F,
+    ) -> DiagnosticResult
+    where
+        F: FnMut(UploadProgress) + Send,
+    {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match job
+            .run(uds_service, std::path::Path::new(path), on_progress)
+            .await
+        {
+            Ok(report) => DiagnosticResult {
+                success: true,
+                message: format!(
+                    "已读取 0x{:X} 起 {} 字节并保存到 {}",
+                    report.address, report.length, report.path
+                ),
+                data: serde_json::to_value(&report).ok(),
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(UdsError::Aborted) => DiagnosticResult {
+                success: false,
+                message: "内存读取已中止".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            },
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("内存读取失败: {}", e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
+        }
+    }
+
+    /// 发送原始 UDS 请求（专家模式）
+    pub async fn send_raw_request(&mut self, data: &str) -> DiagnosticResult {
+        if !self.is_connected || self.uds_service.is_none() {
+            return DiagnosticResult {
+                success: false,
+                message: "未连接到ECU".to_string(),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: None,
+            };
+        }
+
+        let payload = match self.hex_string_to_bytes(data) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                return DiagnosticResult {
+                    success: false,
+                    message: format!("无效的数据格式: {}", e),
+                    data: None,
+                    timestamp: get_timestamp(),
+                    nrc: None,
+                };
+            }
+        };
+
+        let uds_service = self.uds_service.as_mut().unwrap();
+
+        match uds_service.send_raw_request(&payload).await {
+            Ok(response) => {
+                let nrc = response.nrc.map(NegativeResponseCode::from);
+                let message = match (nrc, &response.response) {
+                    (Some(nrc), _) => format!("原始请求收到否定响应: {}", nrc),
+                    (None, None) if response.timed_out => {
+                        format!("原始请求在 {:.1} ms 内未收到最终响应", response.elapsed_ms)
+                    }
+                    (None, None) => "原始请求成功（已抑制正响应，无响应）".to_string(),
+                    (None, Some(_)) => "原始请求成功".to_string(),
+                };
+                DiagnosticResult {
+                    success: response.nrc.is_none() && !response.timed_out,
+                    message,
+                    data: Some(serde_json::json!({
+                        "request": hex::encode(&response.request),
+                        "response": response.response.as_ref().map(hex::encode),
+                        "frames": response.frames,
+                        "nrc": response.nrc,
+                        "timed_out": response.timed_out,
+                        "elapsed_ms": response.elapsed_ms,
+                    })),
+                    timestamp: get_timestamp(),
+                    nrc: nrc.map(NrcInfo::from),
+                }
+            }
+            Err(e) => DiagnosticResult {
+                success: false,
+                message: format!("原始请求失败: {}", e),
+                data: None,
+                timestamp: get_timestamp(),
+                nrc: e.nrc().map(NrcInfo::from),
+            },
         }
     }
 
@@ -331,3 +1210,118 @@ impl Default for UdsClientManager {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecu_simulator::{fast_timing, recording, request_log, EcuSimulator, SimAction};
+    use crate::periodic::PeriodicSample;
+    use crate::types::{IoControlParameter, UdsTimingConfig};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_disconnect_bounds_io_control_return() {
+        // 交还控制的请求一直挂起
+        let sim = EcuSimulator::start(|request| match request {
+            [0x2F, _, _, 0x00, ..] => vec![SimAction::Respond(vec![0x7F, 0x2F, 0x78])],
+            [0x2F, hi, lo, parameter, ..] => {
+                vec![SimAction::Respond(vec![0x6F, *hi, *lo, *parameter])]
+            }
+            _ => vec![SimAction::Respond(vec![0x7F, request[0], 0x11])],
+        })
+        .await;
+        let mut manager = UdsClientManager::new();
+        let timing = UdsTimingConfig {
+            p2_star_client_ms: 10_000,
+            ..fast_timing()
+        };
+        assert!(manager.connect(sim.connection_config(timing)).await.success);
+        let result = manager
+            .io_control(0x4101, IoControlParameter::FREEZE_CURRENT_STATE, "", "")
+            .await;
+        assert!(result.success, "{}", result.message);
+
+        let start = Instant::now();
+        let result = manager.disconnect().await;
+        assert!(start.elapsed() < DISCONNECT_CLEANUP_TIMEOUT + Duration::from_secs(1));
+        assert!(result.message.contains("0x4101"));
+        assert!(!manager.get_connection_status());
+    }
+
+    #[tokio::test]
+    async fn test_periodic_samples_forwarded_without_polling() {
+        let sim = EcuSimulator::start(|request| match request {
+            [0x2A, ..] => vec![
+                SimAction::Respond(vec![0x6A]),
+                SimAction::Delay(50),
+                SimAction::Respond(vec![0x6A, 0x01, 0xAA]),
+            ],
+            // 例程执行期间 ECU 推送周期数据
+            [0x31, 0x01, hi, lo] => vec![
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Respond(vec![0x6A, 0x01, 0xBB]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x71, 0x01, *hi, *lo]),
+            ],
+            _ => vec![SimAction::Respond(vec![0x7F, request[0], 0x11])],
+        })
+        .await;
+        let samples = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = samples.clone();
+        let mut manager = UdsClientManager::new();
+        manager.set_periodic_sink(Arc::new(move |sample: PeriodicSample| {
+            sink.lock().unwrap().push((Instant::now(), sample.data));
+        }));
+        assert!(
+            manager
+                .connect(sim.connection_config(fast_timing()))
+                .await
+                .success
+        );
+        assert!(manager.read_periodic(0x03, "01").await.success);
+
+        // 空闲时没有请求读取连接，样本仍被转发
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(samples.lock().unwrap().len(), 1);
+
+        let result = manager.send_uds_command("31", "31 01 FF 00").await;
+        let finished = Instant::now();
+        assert!(result.success, "{}", result.message);
+
+        // 样本在收到时转发，不等请求结束
+        let samples = samples.lock().unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].1, "aa");
+        assert_eq!(samples[1].1, "bb");
+        assert!(finished - samples[1].0 >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_send_write_command_is_binary_safe() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(recording(requests.clone(), |request| {
+            let response = match request {
+                [0x2E, hi, lo, ..] => vec![0x6E, *hi, *lo],
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        }))
+        .await;
+        let mut manager = UdsClientManager::new();
+        assert!(
+            manager
+                .connect(sim.connection_config(fast_timing()))
+                .await
+                .success
+        );
+
+        // 非 UTF-8 数据原样写入
+        let result = manager.send_uds_command("2E", "2E F1 8C FF 80 00 C3").await;
+        assert!(result.success, "{}", result.message);
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![vec![0x2E, 0xF1, 0x8C, 0xFF, 0x80, 0x00, 0xC3]]
+        );
+    }
+}
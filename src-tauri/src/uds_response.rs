@@ -0,0 +1,497 @@
+/**
+ * UDS 响应校验
+ * 按 DoIP 帧结构与 ISO 14229-1 响应格式严格校验 ECU 响应
+ */
+use crate::types::{NegativeResponseCode, RoutineStatus, UdsError, UdsResult};
+
+/// DoIP 头部长度
+pub const DOIP_HEADER_LEN: usize = 8;
+
+/// DoIP 负载类型常量
+pub struct DoipPayloadTypes;
+
+impl DoipPayloadTypes {
+    pub const ROUTING_ACTIVATION_RESPONSE: u16 = 0x0006;
+    pub const DIAGNOSTIC_MESSAGE: u16 = 0x8001;
+    pub const DIAGNOSTIC_MESSAGE_ACK: u16 = 0x8002;
+    pub const DIAGNOSTIC_MESSAGE_NACK: u16 = 0x8003;
+}
+
+/// 否定响应服务 ID
+pub const NEGATIVE_RESPONSE_SID: u8 = 0x7F;
+
+/// 正响应 SID 偏移
+pub const POSITIVE_RESPONSE_OFFSET: u8 = 0x40;
+
+/// 已解析的 DoIP 帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoipFrame {
+    pub payload_type: u16,
+    pub payload: Vec<u8>,
+}
+
+impl DoipFrame {
+    /// 解析单个完整的 DoIP 帧
+    pub fn parse(frame: &[u8]) -> UdsResult<Self> {
+        if frame.len() < DOIP_HEADER_LEN {
+            return Err(UdsError::InvalidResponse(format!(
+                "DoIP frame too short: {} bytes",
+                frame.len()
+            )));
+        }
+        if frame[0] != !frame[1] {
+            return Err(UdsError::InvalidResponse(format!(
+                "Invalid DoIP protocol version: {:02X} {:02X}",
+                frame[0], frame[1]
+            )));
+        }
+
+        let payload_len = u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]) as usize;
+        if frame.len() != DOIP_HEADER_LEN + payload_len {
+            return Err(UdsError::InvalidResponse(format!(
+                "DoIP length mismatch: header says {} bytes, got {}",
+                payload_len,
+                frame.len() - DOIP_HEADER_LEN
+            )));
+        }
+
+        Ok(Self {
+            payload_type: u16::from_be_bytes([frame[2], frame[3]]),
+            payload: frame[DOIP_HEADER_LEN..].to_vec(),
+        })
+    }
+
+    /// 诊断报文的源地址和目标地址
+    pub fn diagnostic_addresses(&self) -> Option<(u16, u16)> {
+        if self.payload.len() < 4 {
+            return None;
+        }
+        Some((
+            u16::from_be_bytes([self.payload[0], self.payload[1]]),
+            u16::from_be_bytes([self.payload[2], self.payload[3]]),
+        ))
+    }
+
+    /// 诊断报文中的 UDS 数据（诊断确认中为确认码及之后的数据）
+    pub fn user_data(&self) -> &[u8] {
+        self.payload.get(4..).unwrap_or(&[])
+    }
+}
+
+/// 接收帧的分类结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReceivedFrame {
+    /// 来自目标 ECU 的 UDS 响应
+    Response(Vec<u8>),
+    /// 诊断报文正确认
+    Ack,
+    /// 与当前请求无关的帧（原因）
+    Ignored(String),
+}
+
+/// 按地址与负载类型对接收帧分类
+pub fn classify_frame(
+    frame: &DoipFrame,
+    ecu_address: u16,
+    tester_address: u16,
+) -> UdsResult<ReceivedFrame> {
+    match frame.payload_type {
+        DoipPayloadTypes::DIAGNOSTIC_MESSAGE => {
+            let Some((source, target)) = frame.diagnostic_addresses() else {
+                return Err(UdsError::InvalidResponse(
+                    "Diagnostic message without addresses".to_string(),
+                ));
+            };
+            if source != ecu_address || target != tester_address {
+                return Ok(ReceivedFrame::Ignored(format!(
+                    "Diagnostic message 0x{:04X} -> 0x{:04X} not addressed from target 0x{:04X}",
+                    source, target, ecu_address
+                )));
+            }
+            if frame.user_data().is_empty() {
+                return Err(UdsError::InvalidResponse(
+                    "Diagnostic message without UDS data".to_string(),
+                ));
+            }
+            Ok(ReceivedFrame::Response(frame.user_data().to_vec()))
+        }
+        DoipPayloadTypes::DIAGNOSTIC_MESSAGE_ACK => Ok(ReceivedFrame::Ack),
+        DoipPayloadTypes::DIAGNOSTIC_MESSAGE_NACK => {
+            let code = frame.user_data().first().copied().unwrap_or(0xFF);
+            Err(UdsError::RequestDenied(format!(
+                "Diagnostic message NACK code 0x{:02X}",
+                code
+            )))
+        }
+        other => Ok(ReceivedFrame::Ignored(format!(
+            "Unexpected DoIP payload type 0x{:04X}",
+            other
+        ))),
+    }
+}
+
+/// 正响应中需要回显的请求字节数（不含 SID）
+fn echo_length(request: &[u8]) -> usize {
+    let echo = match request[0] {
+        // 带子功能的服务回显子功能
+        0x10 | 0x11 | 0x19 | 0x27 | 0x28 | 0x29 | 0x3E | 0x83 | 0x85 | 0x86 | 0x87 => 1,
+        // 多 DID 读取时 ECU 可省略不支持的 DID，首个 DID 不一定回显，由调用方按顺序拆分
+        0x22 if request.len() > 3 => 0,
+        // 回显 DID
+        0x22 | 0x24 | 0x2E | 0x2F => 2,
+        // 子功能 + 例程标识符
+        0x31 => 3,
+        // 子功能 + 动态 DID
+        0x2C => 3,
+        // 块序号
+        0x36 => 1,
+        // 地址长度格式 + 地址 + 长度（不含写入的数据）
+        0x3D => request.get(1).map_or(0, |alfid| {
+            1 + (alfid & 0x0F) as usize + (alfid >> 4) as usize
+        }),
+        _ => 0,
+    };
+    echo.min(request.len() - 1)
+}
+
+/// 是否为带子功能参数的服务
+pub fn has_sub_function(service: u8) -> bool {
+    matches!(
+        service,
+        0x10 | 0x11 | 0x19 | 0x27 | 0x28 | 0x29 | 0x2C | 0x31 | 0x3E | 0x83 | 0x85 | 0x86 | 0x87
+    )
+}
+
+/// 请求是否设置了抑制正响应位（suppressPosRspMsgIndicationBit）
+///
+/// ReadDTCInformation (0x19) 的子功能不支持该位，始终需要响应。
+pub fn suppresses_positive_response(request: &[u8]) -> bool {
+    request.len() >= 2
+        && has_sub_function(request[0])
+        && request[0] != 0x19
+        && request[1] & 0x80 != 0
+}
+
+/// 解析例程控制正响应（71 type RID_H RID_L statusRecord...）
+pub fn parse_routine_status(response: &[u8]) -> UdsResult<RoutineStatus> {
+    if response.len() < 4 || response[0] != 0x71 {
+        return Err(UdsError::InvalidResponse(format!(
+            "Malformed RoutineControl response: {:02X?}",
+            response
+        )));
+    }
+    Ok(RoutineStatus {
+        control_type: response[1] & 0x7F,
+        routine_id: u16::from_be_bytes([response[2], response[3]]),
+        status_record: response[4..].to_vec(),
+    })
+}
+
+/// 解析 RequestDownload/RequestUpload 正响应中的 maxNumberOfBlockLength
+pub fn parse_max_block_length(response: &[u8]) -> UdsResult<usize> {
+    let field_len = response.get(1).map(|lfi| (lfi >> 4) as usize).unwrap_or(0);
+    if !(1..=8).contains(&field_len) || response.len() < 2 + field_len {
+        return Err(UdsError::InvalidResponse(format!(
+            "Malformed lengthFormatIdentifier in {:02X?}",
+            response
+        )));
+    }
+
+    let max_block_length = response[2..2 + field_len]
+        .iter()
+        .fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    // 至少需容纳 SID、块序号和一个数据字节
+    if max_block_length < 3 {
+        return Err(UdsError::InvalidResponse(format!(
+            "maxNumberOfBlockLength {} is too small",
+            max_block_length
+        )));
+    }
+    Ok(max_block_length.min(usize::MAX as u64) as usize)
+}
+
+/// 是否为指定服务的忙响应（7F SID 21）
+pub fn is_busy_response(service: u8, response: &[u8]) -> bool {
+    response == [NEGATIVE_RESPONSE_SID, service, 0x21]
+}
+
+/// 是否为指定服务的响应挂起（7F SID 78）
+pub fn is_response_pending(service: u8, response: &[u8]) -> bool {
+    response == [NEGATIVE_RESPONSE_SID, service, 0x78]
+}
+
+/// 从 0x10 正响应中解析 P2server_max 和 P2*server_max（毫秒）
+///
+/// 响应格式：50 会话类型 P2(2 字节, 1 ms) P2*(2 字节, 10 ms)
+pub fn parse_session_timing(response: &[u8]) -> Option<(u64, u64)> {
+    if response.len() < 6 || response[0] != 0x50 {
+        return None;
+    }
+    let p2 = u16::from_be_bytes([response[2], response[3]]) as u64;
+    let p2_star = u16::from_be_bytes([response[4], response[5]]) as u64 * 10;
+    Some((p2, p2_star))
+}
+
+/// 校验 UDS 响应并返回正响应数据
+///
+/// 正响应首字节必须为 SID + 0x40，且回显的子功能/DID 与请求一致；
+/// 否定响应必须为 7F + SID + NRC，此时返回携带 NRC 的错误。
+pub fn validate_response(request: &[u8], response: &[u8]) -> UdsResult<Vec<u8>> {
+    let service = *request
+        .first()
+        .ok_or_else(|| UdsError::InvalidParameter("Empty UDS request".to_string()))?;
+    let first = *response
+        .first()
+        .ok_or_else(|| UdsError::InvalidResponse("Empty UDS response".to_string()))?;
+
+    if first == NEGATIVE_RESPONSE_SID {
+        if response.len() != 3 {
+            return Err(UdsError::InvalidResponse(format!(
+                "Malformed negative response: {:02X?}",
+                response
+            )));
+        }
+        if response[1] != service {
+            return Err(UdsError::InvalidResponse(format!(
+                "Negative response for service 0x{:02X}, expected 0x{:02X}",
+                response[1], service
+            )));
+        }
+        return Err(UdsError::NegativeResponse {
+            service,
+            nrc: NegativeResponseCode::from(response[2]),
+        });
+    }
+
+    if first != service.wrapping_add(POSITIVE_RESPONSE_OFFSET) {
+        return Err(UdsError::InvalidResponse(format!(
+            "Unexpected response SID 0x{:02X} for service 0x{:02X}",
+            first, service
+        )));
+    }
+
+    let echo_len = echo_length(request);
+    if response.len() < 1 + echo_len {
+        return Err(UdsError::InvalidResponse(format!(
+            "Positive response for service 0x{:02X} too short: {:02X?}",
+            service, response
+        )));
+    }
+
+    for i in 1..=echo_len {
+        let (mut expected, mut actual) = (request[i], response[i]);
+        // 子功能字节比较时忽略抑制正响应位
+        if i == 1 && has_sub_function(service) {
+            expected &= 0x7F;
+            actual &= 0x7F;
+        }
+        if expected != actual {
+            return Err(UdsError::InvalidResponse(format!(
+                "Echo mismatch for service 0x{:02X}: expected {:02X?}, got {:02X?}",
+                service,
+                &request[1..=echo_len],
+                &response[1..=echo_len]
+            )));
+        }
+    }
+
+    Ok(response.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic_frame(source: u16, target: u16, uds: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x02, 0xFD, 0x80, 0x01];
+        frame.extend_from_slice(&((uds.len() + 4) as u32).to_be_bytes());
+        frame.extend_from_slice(&source.to_be_bytes());
+        frame.extend_from_slice(&target.to_be_bytes());
+        frame.extend_from_slice(uds);
+        frame
+    }
+
+    #[test]
+    fn test_parse_diagnostic_frame() {
+        let frame = DoipFrame::parse(&diagnostic_frame(0x1001, 0x0E80, &[0x50, 0x03])).unwrap();
+        assert_eq!(frame.payload_type, DoipPayloadTypes::DIAGNOSTIC_MESSAGE);
+        assert_eq!(frame.diagnostic_addresses(), Some((0x1001, 0x0E80)));
+        assert_eq!(frame.user_data(), &[0x50, 0x03]);
+    }
+
+    #[test]
+    fn test_parse_rejects_length_mismatch() {
+        let mut frame = diagnostic_frame(0x1001, 0x0E80, &[0x50, 0x03]);
+        frame.push(0x00);
+        assert!(DoipFrame::parse(&frame).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_version() {
+        let mut frame = diagnostic_frame(0x1001, 0x0E80, &[0x50, 0x03]);
+        frame[1] = 0xFE;
+        assert!(DoipFrame::parse(&frame).is_err());
+    }
+
+    #[test]
+    fn test_classify_response_from_target() {
+        let frame = DoipFrame::parse(&diagnostic_frame(0x1001, 0x0E80, &[0x50, 0x03])).unwrap();
+        assert_eq!(
+            classify_frame(&frame, 0x1001, 0x0E80).unwrap(),
+            ReceivedFrame::Response(vec![0x50, 0x03])
+        );
+    }
+
+    #[test]
+    fn test_classify_ignores_other_source() {
+        // 其它 ECU 的正响应不能被当作目标 ECU 的响应
+        let frame = DoipFrame::parse(&diagnostic_frame(0x1002, 0x0E80, &[0x50, 0x03])).unwrap();
+        assert!(matches!(
+            classify_frame(&frame, 0x1001, 0x0E80).unwrap(),
+            ReceivedFrame::Ignored(_)
+        ));
+    }
+
+    #[test]
+    fn test_classify_ack_and_nack() {
+        let ack = DoipFrame {
+            payload_type: DoipPayloadTypes::DIAGNOSTIC_MESSAGE_ACK,
+            payload: vec![0x10, 0x01, 0x0E, 0x80, 0x00],
+        };
+        assert_eq!(
+            classify_frame(&ack, 0x1001, 0x0E80).unwrap(),
+            ReceivedFrame::Ack
+        );
+
+        let nack = DoipFrame {
+            payload_type: DoipPayloadTypes::DIAGNOSTIC_MESSAGE_NACK,
+            payload: vec![0x10, 0x01, 0x0E, 0x80, 0x03],
+        };
+        assert!(classify_frame(&nack, 0x1001, 0x0E80).is_err());
+    }
+
+    #[test]
+    fn test_parse_session_timing() {
+        assert_eq!(
+            parse_session_timing(&[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]),
+            Some((50, 5000))
+        );
+        assert_eq!(parse_session_timing(&[0x50, 0x03]), None);
+    }
+
+    #[test]
+    fn test_parse_max_block_length() {
+        assert_eq!(
+            parse_max_block_length(&[0x74, 0x20, 0x04, 0x02]).unwrap(),
+            0x0402
+        );
+        assert_eq!(
+            parse_max_block_length(&[0x75, 0x40, 0x00, 0x00, 0x10, 0x00]).unwrap(),
+            0x1000
+        );
+        assert!(parse_max_block_length(&[0x74, 0x20, 0x04]).is_err());
+        assert!(parse_max_block_length(&[0x74, 0x00]).is_err());
+        assert!(parse_max_block_length(&[0x74, 0x10, 0x02]).is_err());
+    }
+
+    #[test]
+    fn test_parse_routine_status() {
+        let status = parse_routine_status(&[0x71, 0x01, 0xFF, 0x00, 0x02, 0x10]).unwrap();
+        assert_eq!(status.control_type, 0x01);
+        assert_eq!(status.routine_id, 0xFF00);
+        assert_eq!(status.routine_info(), Some(0x02));
+        assert_eq!(status.status_record, vec![0x02, 0x10]);
+
+        let status = parse_routine_status(&[0x71, 0x02, 0x02, 0x03]).unwrap();
+        assert_eq!(status.routine_info(), None);
+
+        assert!(parse_routine_status(&[0x71, 0x01, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn test_suppress_positive_response_bit() {
+        assert!(suppresses_positive_response(&[0x3E, 0x80]));
+        assert!(suppresses_positive_response(&[0x10, 0x83]));
+        assert!(suppresses_positive_response(&[0x31, 0x81, 0xFF, 0x00]));
+        assert!(!suppresses_positive_response(&[0x10, 0x03]));
+        // 无子功能的服务中 0x80 是数据
+        assert!(!suppresses_positive_response(&[0x22, 0xF1, 0x90]));
+        // 0x19 不支持抑制正响应
+        assert!(!suppresses_positive_response(&[0x19, 0x82, 0xFF]));
+    }
+
+    #[test]
+    fn test_pending_and_busy_detection() {
+        assert!(is_response_pending(0x31, &[0x7F, 0x31, 0x78]));
+        assert!(!is_response_pending(0x22, &[0x7F, 0x31, 0x78]));
+        assert!(is_busy_response(0x22, &[0x7F, 0x22, 0x21]));
+        assert!(!is_busy_response(0x22, &[0x62, 0x7F, 0x22, 0x21]));
+    }
+
+    #[test]
+    fn test_positive_response() {
+        let response = validate_response(&[0x22, 0xF1, 0x90], &[0x62, 0xF1, 0x90, 0x41]).unwrap();
+        assert_eq!(response, vec![0x62, 0xF1, 0x90, 0x41]);
+    }
+
+    #[test]
+    fn test_negative_response_is_not_success() {
+        // 旧实现会在数据中找到 0x62 而误判为正响应
+        let err = validate_response(&[0x22, 0xF1, 0x62], &[0x7F, 0x22, 0x31]).unwrap_err();
+        assert_eq!(err.nrc(), Some(NegativeResponseCode::RequestOutOfRange));
+    }
+
+    #[test]
+    fn test_data_byte_matching_sid_is_not_success() {
+        // 0x50 出现在数据中，但首字节不是 0x50
+        let err = validate_response(&[0x10, 0x03], &[0x7E, 0x50]).unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+
+    #[test]
+    fn test_did_echo_mismatch() {
+        let err = validate_response(&[0x22, 0xF1, 0x90], &[0x62, 0xF1, 0x91, 0x41]).unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+
+    #[test]
+    fn test_multi_did_response_skips_echo() {
+        // 首个 DID 不支持时响应从第二个 DID 开始
+        let request = [0x22, 0x01, 0x00, 0xF1, 0x87];
+        assert!(validate_response(&request, &[0x62, 0xF1, 0x87, 0x01]).is_ok());
+        assert!(validate_response(&request, &[0x63, 0xF1, 0x87, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_sub_function_echo() {
+        assert!(validate_response(&[0x10, 0x03], &[0x50, 0x03, 0x00, 0x32, 0x01, 0xF4]).is_ok());
+        assert!(validate_response(&[0x10, 0x03], &[0x50, 0x02, 0x00, 0x32, 0x01, 0xF4]).is_err());
+        // 抑制正响应位不参与比较
+        assert!(validate_response(&[0x3E, 0x80], &[0x7E, 0x00]).is_ok());
+    }
+
+    #[test]
+    fn test_negative_response_for_other_service() {
+        let err = validate_response(&[0x27, 0x01], &[0x7F, 0x22, 0x33]).unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+
+    #[test]
+    fn test_malformed_negative_response() {
+        let err = validate_response(&[0x27, 0x01], &[0x7F, 0x27]).unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+
+    #[test]
+    fn test_write_memory_echo_excludes_data() {
+        let request = [0x3D, 0x12, 0x20, 0x00, 0x02, 0xAA, 0xBB];
+        assert!(validate_response(&request, &[0x7D, 0x12, 0x20, 0x00, 0x02]).is_ok());
+        assert!(validate_response(&request, &[0x7D, 0x12, 0x20, 0x01, 0x02]).is_err());
+    }
+
+    #[test]
+    fn test_truncated_positive_response() {
+        let err = validate_response(&[0x31, 0x01, 0xFF, 0x00], &[0x71, 0x01]).unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+}
@@ -2,14 +2,41 @@
  * UDS 服务 - Rust 实现
  * 提供完整的 UDS 诊断服务功能
  */
-use crate::doip_client::DoipClient;
-use crate::security_algorithm::SecurityAccessAlgorithm;
-use crate::types::{UdsConfig, UdsError, UdsResponse, UdsResult};
+use crate::data_format::{DataCodec, EncodeStream};
+use crate::doip_client::{DoipClient, FrameRouter};
+use crate::multi_flash::BandwidthLimiter;
+use crate::periodic::{parse_periodic_message, PeriodicSample};
+use crate::security_algorithm::{is_request_seed_level, SecurityAccessAlgorithm};
+use crate::traffic::TrafficSink;
+use crate::types::{
+    AddressAndLengthFormat, ConnectionConfig, DidRecord, DoipClientConfig, DoipError,
+    DownloadOptions, DownloadProgress, DynamicDefinitionType, DynamicDidDefinition,
+    IoControlParameter, MemoryAccessOptions, NegativeResponseCode, PeriodicTransmissionMode,
+    RawUdsFrame, RawUdsResponse, RoutineControlType, RoutinePollConfig, RoutineStatus,
+    SessionTypes, TransferCheckpoint, UdsConfig, UdsError, UdsResponse, UdsResult, UdsTimingConfig,
+    UploadOptions, UploadProgress, VehicleConfig, WriteVerification,
+};
+use crate::uds_response::{
+    classify_frame, is_busy_response, is_response_pending, parse_max_block_length,
+    parse_routine_status, parse_session_timing, suppresses_positive_response, validate_response,
+    DoipFrame, DoipPayloadTypes, ReceivedFrame,
+};
 use crate::utils::{
-    bytes_to_ascii_with_escape, bytes_to_int, ends_with, extract_last_frame, find_bytes,
-    get_timestamp, hex_to_address_bytes, hex_to_bytes, int_to_bytes, is_continuous_frames,
-    starts_with,
+    bytes_to_ascii_with_escape, bytes_to_int, find_bytes, get_timestamp, hex_to_address_bytes,
+    int_to_bytes,
 };
+use bytes::{BufMut, BytesMut};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// DoIP 诊断报文头：协议版本 02、反向版本 FD、负载类型 0x8001
+const DIAGNOSTIC_MESSAGE_HEADER: [u8; 4] = [0x02, 0xFD, 0x80, 0x01];
+
+/// 批量传输时每隔多少帧记录一次收发日志
+const TRANSFER_LOG_SAMPLE_INTERVAL: u64 = 64;
 
 pub struct UdsService {
     client: DoipClient,
@@ -17,14 +44,22 @@ pub struct UdsService {
     security_access_seed: Vec<u8>,
     server_address: Vec<u8>,
     client_address: Vec<u8>,
-    doip_head_bytes: Vec<u8>,
     doip_address_bytes: Vec<u8>,
-    reverse_doip_address_bytes: Vec<u8>,
+    tx_buffer: BytesMut, // 复用的帧头缓冲区：DoIP 头部 + 地址 + 请求头
+    io_controls: BTreeMap<u16, Vec<u8>>, // 测试仪接管中的输入输出 DID 及其控制使能掩码
+    periodic_ids: BTreeMap<u8, u8>, // 正在推送的周期 DID（低字节）及其传输模式
+    periodic_rx: Option<mpsc::UnboundedReceiver<PeriodicSample>>, // 接收任务转发的周期数据
+    capture_periodic: Arc<AtomicBool>, // 原始请求期间周期数据同时交给等待响应的调用方
+    dynamic_dids: BTreeMap<u16, Vec<DynamicDidDefinition>>, // 已定义的动态 DID，会话切换后重新定义
+    bandwidth_limiter: Option<Arc<BandwidthLimiter>>, // 与其他连接共享的下载带宽配额
+    timing: UdsTimingConfig,
+    p2_client: Duration,
+    p2_star_client: Duration,
 }
 
 impl UdsService {
     /// 创建新的 UDS 服务实例
-    pub fn new(client: DoipClient, config: UdsConfig) -> UdsResult<Self> {
+    pub fn new(mut client: DoipClient, config: UdsConfig) -> UdsResult<Self> {
         let security_algorithm = SecurityAccessAlgorithm::new();
 
         // 解析地址配置
@@ -38,18 +73,20 @@ impl UdsService {
             server_address, client_address
         );
 
-        // DoIP 协议头
-        let doip_head_bytes = hex_to_bytes("02 fd 80 01")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid DoIP header: {}", e)))?;
-
         // 组合地址字节
         let mut doip_address_bytes = Vec::new();
         doip_address_bytes.extend_from_slice(&client_address);
         doip_address_bytes.extend_from_slice(&server_address);
 
-        let mut reverse_doip_address_bytes = Vec::new();
-        reverse_doip_address_bytes.extend_from_slice(&server_address);
-        reverse_doip_address_bytes.extend_from_slice(&client_address);
+        // 周期数据由接收任务在收到时解码并送入通道，不经过等待响应的调用方
+        let (periodic_tx, periodic_rx) = mpsc::unbounded_channel();
+        let capture_periodic = Arc::new(AtomicBool::new(false));
+        client.set_frame_router(periodic_router(
+            u16::from_be_bytes([server_address[0], server_address[1]]),
+            u16::from_be_bytes([client_address[0], client_address[1]]),
+            periodic_tx,
+            capture_periodic.clone(),
+        ));
 
         Ok(Self {
             client,
@@ -57,50 +94,240 @@ impl UdsService {
             security_access_seed: Vec::new(),
             server_address,
             client_address,
-            doip_head_bytes,
             doip_address_bytes,
-            reverse_doip_address_bytes,
+            tx_buffer: BytesMut::with_capacity(64),
+            io_controls: BTreeMap::new(),
+            periodic_ids: BTreeMap::new(),
+            periodic_rx: Some(periodic_rx),
+            capture_periodic,
+            bandwidth_limiter: None,
+            dynamic_dids: BTreeMap::new(),
+            p2_client: Duration::from_millis(config.timing.p2_client_ms),
+            p2_star_client: Duration::from_millis(config.timing.p2_star_client_ms),
+            timing: config.timing,
         })
     }
 
-    /// 处理 DoIP 接收数据
-    async fn doip_receive_handle(&mut self, service: &[u8]) -> UdsResult<Vec<u8>> {
-        loop {
-            let data = self.client.receive().await.map_err(UdsError::DoipError)?;
+    /// 建立 DoIP 连接并完成路由激活，每次调用使用独立的 TCP 连接
+    pub async fn connect(
+        config: &ConnectionConfig,
+        traffic_sink: Option<TrafficSink>,
+    ) -> UdsResult<Self> {
+        let mut client = DoipClient::new(DoipClientConfig {
+            ip_address: config.ip_address.clone(),
+            port: config.port,
+            timeout: config.timeout,
+        });
+        if let Some(sink) = traffic_sink {
+            client.set_traffic_sink(sink);
+        }
+        if !client.connect().await? {
+            return Err(UdsError::DoipError(DoipError::ConnectionFailed(format!(
+                "{}:{}",
+                config.ip_address, config.port
+            ))));
+        }
 
-            // 检查连续帧
-            if is_continuous_frames(&data, &self.doip_head_bytes) {
-                if let Some(last_frame) = extract_last_frame(&data, &self.doip_head_bytes) {
-                    return Ok(last_frame);
+        let mut service = Self::new(
+            client,
+            UdsConfig {
+                vehicle_info: VehicleConfig {
+                    server_address: config.server_address.clone(),
+                    client_address: config.client_address.clone(),
+                },
+                timing: config.timing.clone().unwrap_or_default(),
+            },
+        )?;
+        if !service.routine_active().await? {
+            return Err(UdsError::RequestDenied(
+                "Routing activation rejected".to_string(),
+            ));
+        }
+        Ok(service)
+    }
+
+    /// 接收目标 ECU 对指定服务的 UDS 响应
+    ///
+    /// 首个响应须在 P2client 内到达，每次收到 7F xx 78 后改为等待 P2*client，
+    /// 超时或挂起次数超限时返回时间违例错误。诊断确认帧和其它地址的报文被跳过。
+    /// `response_required` 为 false（抑制正响应）时，P2client 内无响应返回 None。
+    async fn doip_receive_handle(
+        &mut self,
+        service: u8,
+        response_required: bool,
+    ) -> UdsResult<Option<Vec<u8>>> {
+        let ecu_address = u16::from_be_bytes([self.server_address[0], self.server_address[1]]);
+        let tester_address = u16::from_be_bytes([self.client_address[0], self.client_address[1]]);
+
+        let mut window = self.p2_client;
+        let mut deadline = Instant::now() + window;
+        let mut pending_count = 0;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let data = match self.client.receive_frame_timeout(remaining).await {
+                Ok(data) => data,
+                Err(DoipError::Timeout) if !response_required && pending_count == 0 => {
+                    return Ok(None);
+                }
+                Err(DoipError::Timeout) => {
+                    let phase = if pending_count == 0 {
+                        "P2client"
+                    } else {
+                        "P2*client"
+                    };
+                    let message = format!(
+                        "No response to service 0x{:02X} within {} ({} ms)",
+                        service,
+                        phase,
+                        window.as_millis()
+                    );
+                    self.log("error", &message);
+                    return Err(UdsError::TimingViolation(message));
+                }
+                Err(e) => return Err(UdsError::DoipError(e)),
+            };
+            let frame = DoipFrame::parse(&data)?;
+
+            match classify_frame(&frame, ecu_address, tester_address)? {
+                ReceivedFrame::Response(uds) => {
+                    // 原始请求期间留下的周期数据已由接收任务转发，不属于当前请求
+                    if parse_periodic_message(&uds).is_some() {
+                        continue;
+                    }
+                    // 7F xx 78（请求正确接收-响应挂起）：以 P2*client 重新计时
+                    if is_response_pending(service, &uds) {
+                        pending_count += 1;
+                        if pending_count > self.timing.max_pending_count {
+                            let message = format!(
+                                "Service 0x{:02X} exceeded {} response pending messages",
+                                service, self.timing.max_pending_count
+                            );
+                            self.log("error", &message);
+                            return Err(UdsError::TimingViolation(message));
+                        }
+                        window = self.p2_star_client;
+                        deadline = Instant::now() + window;
+                        continue;
+                    }
+                    return Ok(Some(uds));
+                }
+                ReceivedFrame::Ack => continue,
+                ReceivedFrame::Ignored(reason) => {
+                    self.log("debug", &format!("Ignored frame: {}", reason));
+                    continue;
                 }
             }
+        }
+    }
 
-            // 检查 7F 78 响应（请求正确接收-响应挂起）
-            let mut target78 = vec![0x7f];
-            target78.extend_from_slice(service);
-            target78.push(0x78);
-            if find_bytes(&data, &target78).is_some() {
-                continue;
-            }
+    /// 发送 UDS 请求
+    async fn send_request(&mut self, payload: &[u8]) -> UdsResult<()> {
+        self.send_request_parts(payload, &[]).await
+    }
 
-            // 检查 7F 21 响应（忙-请求序列错误）
-            let mut target21 = vec![0x7f];
-            target21.extend_from_slice(service);
-            target21.push(0x21);
-            if find_bytes(&data, &target21).is_some() {
-                continue;
-            }
+    /// 发送由请求头和数据组成的 UDS 请求
+    ///
+    /// 帧头和请求头写入复用的缓冲区，数据不复制，与帧头一起以向量写发出。
+    async fn send_request_parts(&mut self, head: &[u8], body: &[u8]) -> UdsResult<()> {
+        self.tx_buffer.clear();
+        self.tx_buffer.put_slice(&DIAGNOSTIC_MESSAGE_HEADER);
+        self.tx_buffer
+            .put_u32((self.doip_address_bytes.len() + head.len() + body.len()) as u32);
+        self.tx_buffer.put_slice(&self.doip_address_bytes);
+        self.tx_buffer.put_slice(head);
+        self.client
+            .send_parts(&self.tx_buffer, body)
+            .await
+            .map_err(UdsError::DoipError)?;
+        Ok(())
+    }
 
-            // 检查地址响应
-            let mut address_target = self.reverse_doip_address_bytes.clone();
-            address_target.push(0x00);
-            if ends_with(&data, &address_target) {
+    /// 发送 UDS 请求并返回校验后的正响应
+    async fn request(&mut self, payload: &[u8]) -> UdsResult<Vec<u8>> {
+        self.request_parts(payload, &[]).await
+    }
+
+    /// 发送由请求头和数据组成的 UDS 请求并返回校验后的正响应
+    async fn request_parts(&mut self, payload: &[u8], body: &[u8]) -> UdsResult<Vec<u8>> {
+        self.request_parts_optional(payload, body)
+            .await?
+            .ok_or_else(|| {
+                UdsError::InvalidParameter(format!(
+                    "Request {:02X?} suppresses the positive response this service needs",
+                    payload
+                ))
+            })
+    }
+
+    /// 发送 UDS 请求，设置了抑制正响应位且 P2client 内无否定响应时返回 None
+    ///
+    /// 收到 7F xx 21（忙-重复请求）时按配置延时后重发请求。
+    async fn request_optional(&mut self, payload: &[u8]) -> UdsResult<Option<Vec<u8>>> {
+        self.request_parts_optional(payload, &[]).await
+    }
+
+    /// 发送由请求头和数据组成的 UDS 请求，响应按请求头校验
+    async fn request_parts_optional(
+        &mut self,
+        payload: &[u8],
+        body: &[u8],
+    ) -> UdsResult<Option<Vec<u8>>> {
+        let response_required = !suppresses_positive_response(payload);
+        let mut busy_repeats = 0;
+
+        loop {
+            self.send_request_parts(payload, body).await?;
+            let Some(response) = self
+                .doip_receive_handle(payload[0], response_required)
+                .await?
+            else {
+                self.log(
+                    "info",
+                    &format!(
+                        "Service 0x{:02X}: no response expected (positive response suppressed)",
+                        payload[0]
+                    ),
+                );
+                return Ok(None);
+            };
+
+            if is_busy_response(payload[0], &response)
+                && busy_repeats < self.timing.busy_repeat_count
+            {
+                busy_repeats += 1;
+                self.log(
+                    "info",
+                    &format!(
+                        "Service 0x{:02X} busy, repeating request ({}/{})",
+                        payload[0], busy_repeats, self.timing.busy_repeat_count
+                    ),
+                );
+                tokio::time::sleep(Duration::from_millis(self.timing.busy_repeat_delay_ms)).await;
                 continue;
             }
 
-            if !data.is_empty() {
-                return Ok(data);
-            }
+            return validate_response(payload, &response).map(Some);
+        }
+    }
+
+    /// 采用 0x10 正响应中 ECU 报告的 P2/P2* 值
+    fn apply_session_timing(&mut self, response: &[u8]) {
+        if !self.timing.use_server_timing {
+            return;
+        }
+        if let Some((p2_server, p2_star_server)) = parse_session_timing(response) {
+            self.p2_client = Duration::from_millis(p2_server + self.timing.network_delay_ms);
+            self.p2_star_client =
+                Duration::from_millis(p2_star_server + self.timing.network_delay_ms);
+            self.log(
+                "info",
+                &format!(
+                    "Session timing updated: P2client={} ms, P2*client={} ms",
+                    self.p2_client.as_millis(),
+                    self.p2_star_client.as_millis()
+                ),
+            );
         }
     }
 
@@ -148,8 +375,25 @@ impl UdsService {
             .await
             .map_err(UdsError::DoipError)?;
 
-        // 等待响应
-        let response = self.doip_receive_handle(&[0x00]).await?;
+        // 等待路由激活响应，跳过其它类型的帧
+        let response = loop {
+            let data = self
+                .client
+                .receive_frame()
+                .await
+                .map_err(UdsError::DoipError)?;
+            let frame = DoipFrame::parse(&data)?;
+            if frame.payload_type == DoipPayloadTypes::ROUTING_ACTIVATION_RESPONSE {
+                break data;
+            }
+            self.log(
+                "debug",
+                &format!(
+                    "Ignoring payload type 0x{:04X} during routing activation",
+                    frame.payload_type
+                ),
+            );
+        };
 
         self.log(
             "debug",
@@ -171,8 +415,9 @@ impl UdsService {
             && response[2] == 0x00
             && response[3] == 0x06
         {
+            // 负载：测试仪地址(2) + 实体地址(2) + 响应码(1) + 保留(4) [+ OEM特定(4)]
             if response.len() >= 13 {
-                let response_code = response[response.len() - 5];
+                let response_code = response[12];
                 match response_code {
                     0x10 => {
                         self.log("info", "Routing activation successful");
@@ -234,241 +479,256 @@ impl UdsService {
         }
     }
 
-    /// 启动诊断会话
+    /// 启动诊断会话，切换前先交还测试仪接管的输入输出控制
+    ///
+    /// 切换到默认或扩展会话后重新定义动态 DID，编程会话不恢复。
     pub async fn start_session(&mut self, session: u8) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 06")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x10);
-        request.push(session);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
-
-        if session > 0x03 {
-            return Ok(true);
-        }
-
-        let response = self.doip_receive_handle(&[0x10]).await?;
-
-        // 检查正响应
-        if find_bytes(&response, &[0x50]).is_some() {
-            self.log("info", "Start session granted");
-            Ok(true)
-        } else {
-            self.log("error", "Start session denied or error occurred");
-            Err(UdsError::RequestDenied("Start session denied".to_string()))
+        self.return_io_controls().await;
+        match self.request_optional(&[0x10, session]).await {
+            Ok(response) => {
+                self.log("info", "Start session granted");
+                // 会话切换后 ECU 停止所有周期数据传输
+                self.periodic_ids.clear();
+                if let Some(response) = response {
+                    self.apply_session_timing(&response);
+                }
+                if matches!(
+                    session & 0x7F,
+                    SessionTypes::DEFAULT | SessionTypes::EXTENDED
+                ) {
+                    self.restore_dynamic_dids().await;
+                }
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("Start session denied: {}", e));
+                Err(e)
+            }
         }
     }
 
     /// 控制 DTC 设置
     pub async fn control_dtc_setting(&mut self, dtc_type: u8) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 06")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x85);
-        request.push(dtc_type);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
-
-        let response = self.doip_receive_handle(&[0x85]).await?;
-
-        // 检查正响应
-        if find_bytes(&response, &[0xc5]).is_some() {
-            self.log("info", "Control DTC setting granted");
-            Ok(true)
-        } else {
-            self.log("error", "Control DTC setting denied or error occurred");
-            Err(UdsError::RequestDenied(
-                "Control DTC setting denied".to_string(),
-            ))
+        match self.request_optional(&[0x85, dtc_type]).await {
+            Ok(_) => {
+                self.log("info", "Control DTC setting granted");
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("Control DTC setting denied: {}", e));
+                Err(e)
+            }
         }
     }
 
     /// 通信控制
     pub async fn communication_control(&mut self, comm_type: u8) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 07")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x28);
-        request.push(comm_type);
-        request.push(0x03);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
-
-        if comm_type > 0x80 {
-            return Ok(true);
-        }
-
-        let response = self.doip_receive_handle(&[0x28]).await?;
-
-        // 检查正响应
-        if find_bytes(&response, &[0x68]).is_some() {
-            self.log("info", "Communication control granted");
-            Ok(true)
-        } else {
-            self.log("error", "Communication control denied or error occurred");
-            Err(UdsError::RequestDenied(
-                "Communication control denied".to_string(),
-            ))
+        match self.request_optional(&[0x28, comm_type, 0x03]).await {
+            Ok(_) => {
+                self.log("info", "Communication control granted");
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("Communication control denied: {}", e));
+                Err(e)
+            }
         }
     }
 
     /// 读取数据标识符
     pub async fn read_data_by_identifier(&mut self, did: u16) -> UdsResult<UdsResponse> {
-        let request_length_bytes = hex_to_bytes("00 00 00 07")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let did_bytes = vec![(did >> 8) as u8, did as u8];
-
-        let mut request = doip_request_bytes;
-        request.push(0x22);
-        request.extend_from_slice(&did_bytes);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
+        let did_bytes = did.to_be_bytes();
+
+        match self.request(&[0x22, did_bytes[0], did_bytes[1]]).await {
+            Ok(response) => {
+                self.log(
+                    "info",
+                    &format!("Read data identifier 0x{:04x} granted", did),
+                );
+                self.bytes_to_ascii(&response, &[0x62, did_bytes[0], did_bytes[1]]);
+                Ok(UdsResponse {
+                    success: true,
+                    data: Some(response),
+                    error: None,
+                })
+            }
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!("Read data identifier 0x{:04x} denied: {}", did, e),
+                );
+                Err(e)
+            }
+        }
+    }
 
-        let response = self.doip_receive_handle(&[0x22]).await?;
+    /// 一次请求读取多个 DID，按长度拆分为各 DID 的数据记录
+    ///
+    /// 长度依次取自 lengths 和已定义动态 DID 的记录。任一 DID 长度未知、ECU 以否定响应
+    /// 拒绝组合请求或响应无法按长度拆分时，改为逐个读取。ECU 不支持的 DID
+    /// （组合响应中省略或单独读取时返回 requestOutOfRange）不出现在结果中。
+    pub async fn read_data_by_identifiers(
+        &mut self,
+        dids: &[u16],
+        lengths: &BTreeMap<u16, usize>,
+    ) -> UdsResult<Vec<DidRecord>> {
+        let known: Option<BTreeMap<u16, usize>> = dids
+            .iter()
+            .map(|&did| self.did_length(did, lengths).map(|len| (did, len)))
+            .collect();
+
+        match known {
+            Some(known) if dids.len() > 1 => {
+                let mut request = vec![0x22];
+                for did in dids {
+                    request.extend_from_slice(&did.to_be_bytes());
+                }
+                match self.request(&request).await {
+                    Ok(response) => match split_did_records(&response[1..], dids, &known) {
+                        Ok(records) => {
+                            self.log(
+                                "info",
+                                &format!("Read {} data identifiers in one request", dids.len()),
+                            );
+                            return Ok(records);
+                        }
+                        Err(e) => self.log(
+                            "error",
+                            &format!("Cannot split combined DID response: {}", e),
+                        ),
+                    },
+                    Err(e) if e.nrc().is_some() || matches!(e, UdsError::InvalidResponse(_)) => {
+                        self.log(
+                            "info",
+                            &format!("Combined DID read rejected ({}), reading one by one", e),
+                        )
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Some(_) => {}
+            None => self.log(
+                "debug",
+                "DID length unknown, reading data identifiers one by one",
+            ),
+        }
 
-        // 检查正响应
-        if find_bytes(&response, &[0x62]).is_some() {
-            self.log(
-                "info",
-                &format!("Read data identifier 0x{:04x} granted", did),
-            );
-            self.bytes_to_ascii(&response, &[vec![0x62], did_bytes].concat());
-            Ok(UdsResponse {
-                success: true,
-                data: Some(response),
-                error: None,
-            })
-        } else {
-            self.log(
-                "error",
-                &format!(
-                    "Read data identifier 0x{:04x} denied or error occurred",
-                    did
-                ),
-            );
-            Err(UdsError::RequestDenied(
-                "Read data identifier denied".to_string(),
-            ))
+        let mut records = Vec::new();
+        for &did in dids {
+            let did_bytes = did.to_be_bytes();
+            match self.request(&[0x22, did_bytes[0], did_bytes[1]]).await {
+                Ok(response) => records.push(DidRecord {
+                    did,
+                    data: response[3..].to_vec(),
+                }),
+                Err(e) if e.nrc() == Some(NegativeResponseCode::RequestOutOfRange) => {
+                    self.log(
+                        "info",
+                        &format!("Data identifier 0x{:04x} not supported", did),
+                    );
+                }
+                Err(e) => return Err(e),
+            }
         }
+        Ok(records)
+    }
+
+    /// DID 数据长度：先查长度表，再按动态 DID 定义计算
+    fn did_length(&self, did: u16, lengths: &BTreeMap<u16, usize>) -> Option<usize> {
+        lengths.get(&did).copied().or_else(|| {
+            self.dynamic_dids
+                .get(&did)
+                .map(|definitions| definitions.iter().map(|d| d.data_length()).sum())
+        })
     }
 
-    /// 写入数据标识符
+    /// 写入数据标识符，数据记录按原始字节发送
     pub async fn write_data_by_identifier(
         &mut self,
         did: u16,
-        data: &str,
+        data: &[u8],
     ) -> UdsResult<UdsResponse> {
-        let data_bytes = data.as_bytes();
-        let data_len = data_bytes.len() + 7;
-        let request_length_bytes = int_to_bytes(data_len as u32);
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let did_bytes = vec![(did >> 8) as u8, did as u8];
-
-        let mut request = doip_request_bytes;
-        request.push(0x2E);
-        request.extend_from_slice(&did_bytes);
-        request.extend_from_slice(data_bytes);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
-
-        let response = self.doip_receive_handle(&[0x2E]).await?;
+        let mut request = vec![0x2E];
+        request.extend_from_slice(&did.to_be_bytes());
+        request.extend_from_slice(data);
+
+        match self.request(&request).await {
+            Ok(response) => {
+                self.log(
+                    "info",
+                    &format!("Write data identifier 0x{:04x} granted", did),
+                );
+                Ok(UdsResponse {
+                    success: true,
+                    data: Some(response),
+                    error: None,
+                })
+            }
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!("Write data identifier 0x{:04x} denied: {}", did, e),
+                );
+                Err(e)
+            }
+        }
+    }
 
-        // 检查正响应
-        if find_bytes(&response, &[0x6E]).is_some() {
-            self.log(
-                "info",
-                &format!("Write data identifier 0x{:04x} granted", did),
-            );
-            Ok(UdsResponse {
-                success: true,
-                data: Some(response),
-                error: None,
-            })
-        } else {
-            self.log(
-                "error",
-                &format!(
-                    "Write data identifier 0x{:04x} denied or error occurred",
-                    did
-                ),
+    /// 写入数据标识符后读回校验
+    ///
+    /// 读回数据不一致时返回 VerificationFailed；写入成功但读回请求失败时不视为错误，
+    /// 返回未校验结果。
+    pub async fn write_data_verified(
+        &mut self,
+        did: u16,
+        data: &[u8],
+    ) -> UdsResult<WriteVerification> {
+        self.write_data_by_identifier(did, data).await?;
+
+        let did_bytes = did.to_be_bytes();
+        let response = match self.request(&[0x22, did_bytes[0], did_bytes[1]]).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.log(
+                    "warn",
+                    &format!(
+                        "Data identifier 0x{:04x} written but not read back: {}",
+                        did, e
+                    ),
+                );
+                return Ok(WriteVerification::Unverified(e.to_string()));
+            }
+        };
+        let read_back = response[3..].to_vec();
+        if read_back != data {
+            let message = format!(
+                "DID 0x{:04X} read back {:02X?}, wrote {:02X?}",
+                did, read_back, data
             );
-            Err(UdsError::RequestDenied(
-                "Write data identifier denied".to_string(),
-            ))
+            self.log("error", &message);
+            return Err(UdsError::VerificationFailed(message));
         }
+        self.log(
+            "info",
+            &format!("Write data identifier 0x{:04x} verified", did),
+        );
+        Ok(WriteVerification::Verified(read_back))
     }
 
     /// 安全访问 - 获取种子
     pub async fn security_access_get_seed(&mut self, level: u8) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 06")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x27);
-        request.push(level);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
-
-        let response = self.doip_receive_handle(&[0x27]).await?;
-
-        if find_bytes(&response, &[0x67]).is_some() {
-            self.log("info", "Security access get seed granted");
-            // 提取最后4个字节作为种子
-            if response.len() >= 4 {
-                self.security_access_seed = response[response.len() - 4..].to_vec();
+        match self.request(&[0x27, level]).await {
+            Ok(response) => {
+                self.log("info", "Security access get seed granted");
+                // 67 + 级别之后的字节为种子
+                self.security_access_seed = response[2..].to_vec();
                 self.print_hex(&self.security_access_seed);
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("Security access get seed denied: {}", e));
+                Err(e)
             }
-            Ok(true)
-        } else {
-            self.log("error", "Security access get seed denied or error occurred");
-            Err(UdsError::SecurityAccessDenied)
         }
     }
 
@@ -483,195 +743,1113 @@ impl UdsService {
         let seed_value = bytes_to_int(&self.security_access_seed)
             .map_err(|e| UdsError::InvalidParameter(format!("Invalid seed: {}", e)))?;
 
-        // 根据级别计算token
-        let token = match level {
-            2 => {
-                self.log("info", "Security access compute level1 compare key");
-                self.security_algorithm.compute_key_level1(seed_value, key)
-            }
-            4 => {
-                self.log("info", "Security access compute level2 compare key");
-                self.security_algorithm.compute_key_level2(seed_value, key)
-            }
-            6 => {
-                self.log("info", "Security access compute level3 compare key");
-                self.security_algorithm.compute_key_level3(seed_value, key)
-            }
-            8 => {
-                self.log("info", "Security access compute level4 compare key");
-                self.security_algorithm.compute_key_level4(seed_value, key)
+        // 根据级别计算token（忽略抑制正响应位）
+        let token = match self
+            .security_algorithm
+            .compute_key_by_level(level, seed_value, key)
+        {
+            Ok(token) => {
+                self.log(
+                    "info",
+                    &format!(
+                        "Security access compute compare key for level 0x{:02X}",
+                        level
+                    ),
+                );
+                token
             }
-            _ => {
+            Err(e) => {
                 self.log("error", &format!("Unsupported security level: {}", level));
-                return Err(UdsError::InvalidParameter(format!(
-                    "Unsupported security level: {}",
-                    level
-                )));
+                return Err(UdsError::InvalidParameter(e.to_string()));
             }
         };
 
-        let request_length_bytes = hex_to_bytes("00 00 00 0a")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
+        let mut request = vec![0x27, level];
+        request.extend_from_slice(&int_to_bytes(token));
 
-        let token_bytes = int_to_bytes(token);
-        let mut request = doip_request_bytes;
-        request.push(0x27);
-        request.push(level);
-        request.extend_from_slice(&token_bytes);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
+        match self.request_optional(&request).await {
+            Ok(_) => {
+                self.log("info", "Security access compare key granted");
+                Ok(true)
+            }
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!("Security access compare key denied: {}", e),
+                );
+                Err(e)
+            }
+        }
+    }
 
-        let response = self.doip_receive_handle(&[0x27]).await?;
+    /// 安全解锁：请求种子并发送密钥（level 为请求种子的奇数级别）
+    ///
+    /// 种子全为 0 表示该级别已解锁，此时不再发送密钥。
+    pub async fn security_unlock(&mut self, level: u8, key: u32) -> UdsResult<bool> {
+        let send_key_level = level
+            .checked_add(1)
+            .filter(|_| is_request_seed_level(level))
+            .ok_or_else(|| {
+                UdsError::InvalidParameter(format!(
+                    "Security level 0x{:02X} is not a requestSeed level",
+                    level
+                ))
+            })?;
+        self.security_access_get_seed(level).await?;
 
-        if find_bytes(&response, &[0x67]).is_some() {
-            self.log("info", "Security access compare key granted");
-            Ok(true)
-        } else {
+        if self.security_access_seed.iter().all(|&b| b == 0) {
             self.log(
-                "error",
-                "Security access compare key denied or error occurred",
+                "info",
+                &format!("Security level 0x{:02X} already unlocked", level),
             );
-            Err(UdsError::SecurityAccessDenied)
+            return Ok(true);
         }
+
+        self.security_access_compare_key(send_key_level, key).await
     }
 
     /// ECU 复位
     pub async fn ecu_reset(&mut self, reset_type: u8) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 06")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x11);
-        request.push(reset_type);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
+        match self.request_optional(&[0x11, reset_type]).await {
+            Ok(_) => {
+                self.log("info", "ECU Reset granted");
+                // 复位后 ECU 自行恢复所有输入输出控制
+                self.io_controls.clear();
+                self.periodic_ids.clear();
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("ECU Reset denied: {}", e));
+                Err(e)
+            }
+        }
+    }
 
-        let response = self.doip_receive_handle(&[0x11]).await?;
+    /// 输入输出控制（0x2F），返回 controlStatusRecord
+    ///
+    /// control_state 仅在短期调整时携带，enable_mask 为控制使能掩码（无掩码时为空）。
+    /// 非交还控制的请求成功后记录该 DID，会话切换或断开连接前自动交还。
+    pub async fn input_output_control(
+        &mut self,
+        did: u16,
+        parameter: u8,
+        control_state: &[u8],
+        enable_mask: &[u8],
+    ) -> UdsResult<Vec<u8>> {
+        let mut request = vec![0x2F];
+        request.extend_from_slice(&did.to_be_bytes());
+        request.push(parameter);
+        request.extend_from_slice(control_state);
+        request.extend_from_slice(enable_mask);
+
+        let response = match self.request(&request).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.log("error", &format!("IO control 0x{:04X} denied: {}", did, e));
+                return Err(e);
+            }
+        };
+        if response.get(3) != Some(&parameter) {
+            return Err(UdsError::InvalidResponse(format!(
+                "IO control response {:02X?} does not echo parameter 0x{:02X}",
+                response, parameter
+            )));
+        }
 
-        if find_bytes(&response, &[0x51]).is_some() {
-            self.log("info", "ECU Reset granted");
-            Ok(true)
+        if parameter == IoControlParameter::RETURN_CONTROL_TO_ECU {
+            self.io_controls.remove(&did);
         } else {
-            self.log("error", "ECU Reset denied or error occurred");
-            Err(UdsError::RequestDenied("ECU Reset denied".to_string()))
+            self.io_controls.insert(did, enable_mask.to_vec());
         }
+        self.log(
+            "info",
+            &format!(
+                "IO control 0x{:04X} parameter 0x{:02X} granted",
+                did, parameter
+            ),
+        );
+        Ok(response[4..].to_vec())
     }
 
-    /// 读取 DTC 信息
-    pub async fn read_dtc_information(&mut self, sub_function: u8) -> UdsResult<UdsResponse> {
-        let request_length_bytes = hex_to_bytes("00 00 00 07")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x19);
-        request.push(sub_function);
-        request.push(0xAF);
-
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
+    /// TCP 连接是否仍可用，对端关闭或读写失败后为 false
+    pub fn is_connected(&self) -> bool {
+        self.client.is_socket_connected()
+    }
 
-        let response = self.doip_receive_handle(&[0x19]).await?;
+    /// 测试仪当前接管的输入输出 DID
+    pub fn active_io_controls(&self) -> Vec<u16> {
+        self.io_controls.keys().copied().collect()
+    }
 
-        if find_bytes(&response, &[0x59]).is_some() {
-            self.log("info", "Read DTC information granted");
-            Ok(UdsResponse {
-                success: true,
-                data: Some(response),
-                error: None,
-            })
-        } else {
-            self.log("error", "Read DTC information denied or error occurred");
-            Err(UdsError::RequestDenied(
-                "Read DTC information denied".to_string(),
-            ))
+    /// 交还所有接管的输入输出控制，单个失败不影响其余，返回交还失败的 DID
+    ///
+    /// 交还失败的 DID 仍保留在接管列表中，可稍后重试。
+    pub async fn return_io_controls(&mut self) -> Vec<u16> {
+        let controls: Vec<(u16, Vec<u8>)> = self
+            .io_controls
+            .iter()
+            .map(|(did, mask)| (*did, mask.clone()))
+            .collect();
+        let mut failed = Vec::new();
+        for (did, enable_mask) in controls {
+            if let Err(e) = self
+                .input_output_control(
+                    did,
+                    IoControlParameter::RETURN_CONTROL_TO_ECU,
+                    &[],
+                    &enable_mask,
+                )
+                .await
+            {
+                self.log(
+                    "warn",
+                    &format!("Failed to return IO control 0x{:04X} to ECU: {}", did, e),
+                );
+                failed.push(did);
+            }
         }
+        failed
     }
 
-    /// 清除诊断信息
-    pub async fn clear_diagnostic_information(&mut self) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 08")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
-
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
-
-        let mut request = doip_request_bytes;
-        request.push(0x14);
-        request.extend_from_slice(&[0xFF, 0xFF, 0xFF]); // 清除所有DTC
+    /// 取出周期数据通道的接收端，只能取出一次。样本的时间戳为接收任务收到报文的时间
+    pub fn take_periodic_receiver(&mut self) -> Option<mpsc::UnboundedReceiver<PeriodicSample>> {
+        self.periodic_rx.take()
+    }
 
-        self.client
-            .send(&request)
-            .await
-            .map_err(UdsError::DoipError)?;
+    /// 设置共享带宽配额，下载时每个数据块发送前按块长度取得配额
+    pub fn set_bandwidth_limiter(&mut self, limiter: Arc<BandwidthLimiter>) {
+        self.bandwidth_limiter = Some(limiter);
+    }
 
-        let response = self.doip_receive_handle(&[0x14]).await?;
+    /// 按周期标识符读取数据（0x2A），periodic_ids 为周期 DID 低字节
+    ///
+    /// 以停止模式且不带标识符请求时停止全部周期传输。ECU 随后主动推送的报文
+    /// 由接收任务送入周期数据通道。
+    pub async fn read_data_by_periodic_identifier(
+        &mut self,
+        mode: u8,
+        periodic_ids: &[u8],
+    ) -> UdsResult<()> {
+        let mut request = vec![0x2A, mode];
+        request.extend_from_slice(periodic_ids);
 
-        if find_bytes(&response, &[0x54]).is_some() {
-            self.log("info", "Clear diagnostic information granted");
-            Ok(true)
+        if let Err(e) = self.request(&request).await {
+            self.log(
+                "error",
+                &format!(
+                    "Periodic mode 0x{:02X} {:02X?} denied: {}",
+                    mode, periodic_ids, e
+                ),
+            );
+            return Err(e);
+        }
+
+        if mode == PeriodicTransmissionMode::STOP_SENDING {
+            if periodic_ids.is_empty() {
+                self.periodic_ids.clear();
+            }
+            for id in periodic_ids {
+                self.periodic_ids.remove(id);
+            }
         } else {
+            for &id in periodic_ids {
+                self.periodic_ids.insert(id, mode);
+            }
+        }
+        self.log(
+            "info",
+            &format!("Periodic mode 0x{:02X} {:02X?} granted", mode, periodic_ids),
+        );
+        Ok(())
+    }
+
+    /// 正在推送的周期 DID（低字节）及其传输模式
+    pub fn active_periodic_ids(&self) -> Vec<(u8, u8)> {
+        self.periodic_ids
+            .iter()
+            .map(|(&id, &mode)| (id, mode))
+            .collect()
+    }
+
+    /// 动态定义数据标识符（0x2C 01/02），成功后记录定义以便会话切换后重新定义
+    pub async fn define_dynamic_did(
+        &mut self,
+        ddid: u16,
+        definition: &DynamicDidDefinition,
+    ) -> UdsResult<()> {
+        let request = definition.encode(ddid)?;
+        if let Err(e) = self.request(&request).await {
             self.log(
                 "error",
-                "Clear diagnostic information denied or error occurred",
+                &format!("Define dynamic DID 0x{:04X} denied: {}", ddid, e),
             );
-            Err(UdsError::RequestDenied(
-                "Clear diagnostic information denied".to_string(),
-            ))
+            return Err(e);
         }
+
+        self.dynamic_dids
+            .entry(ddid)
+            .or_default()
+            .push(definition.clone());
+        self.log(
+            "info",
+            &format!("Define dynamic DID 0x{:04X} granted", ddid),
+        );
+        Ok(())
     }
 
-    /// 测试器在线
-    pub async fn tester_present(&mut self, suppress_response: bool) -> UdsResult<bool> {
-        let request_length_bytes = hex_to_bytes("00 00 00 06")
-            .map_err(|e| UdsError::InvalidParameter(format!("Invalid request length: {}", e)))?;
+    /// 清除动态 DID（0x2C 03），ddid 为 None 时清除全部
+    pub async fn clear_dynamic_did(&mut self, ddid: Option<u16>) -> UdsResult<()> {
+        let mut request = vec![
+            0x2C,
+            DynamicDefinitionType::CLEAR_DYNAMICALLY_DEFINED_DATA_IDENTIFIER,
+        ];
+        if let Some(ddid) = ddid {
+            request.extend_from_slice(&ddid.to_be_bytes());
+        }
+        if let Err(e) = self.request(&request).await {
+            self.log("error", &format!("Clear dynamic DID denied: {}", e));
+            return Err(e);
+        }
 
-        let mut doip_request_bytes = self.doip_head_bytes.clone();
-        doip_request_bytes.extend_from_slice(&request_length_bytes);
-        doip_request_bytes.extend_from_slice(&self.doip_address_bytes);
+        match ddid {
+            Some(ddid) => {
+                self.dynamic_dids.remove(&ddid);
+            }
+            None => self.dynamic_dids.clear(),
+        }
+        self.log("info", "Clear dynamic DID granted");
+        Ok(())
+    }
 
-        let sub_function = if suppress_response { 0x80 } else { 0x00 };
-        let mut request = doip_request_bytes;
-        request.push(0x3E);
-        request.push(sub_function);
+    /// 已定义的动态 DID 及其定义
+    pub fn dynamic_dids(&self) -> &BTreeMap<u16, Vec<DynamicDidDefinition>> {
+        &self.dynamic_dids
+    }
 
-        self.client
-            .send(&request)
+    /// 按记录重新定义所有动态 DID，返回成功重建的数量
+    ///
+    /// 每个 DDID 先清除再按原顺序定义，避免 ECU 保留旧定义时重复追加。
+    /// 失败的 DDID 仍保留记录，下次会话切换时重试。
+    pub async fn restore_dynamic_dids(&mut self) -> usize {
+        let dynamic_dids = self.dynamic_dids.clone();
+        let mut restored = 0;
+        let mut failed = Vec::new();
+        for (ddid, definitions) in dynamic_dids {
+            let mut clear = vec![
+                0x2C,
+                DynamicDefinitionType::CLEAR_DYNAMICALLY_DEFINED_DATA_IDENTIFIER,
+            ];
+            clear.extend_from_slice(&ddid.to_be_bytes());
+            // ECU 已丢弃该定义时清除会被拒绝，忽略即可
+            let _ = self.request(&clear).await;
+
+            let mut success = true;
+            for definition in &definitions {
+                let result = match definition.encode(ddid) {
+                    Ok(request) => self.request(&request).await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    self.log(
+                        "error",
+                        &format!("Restore dynamic DID 0x{:04X} failed: {}", ddid, e),
+                    );
+                    success = false;
+                    break;
+                }
+            }
+            if success {
+                restored += 1;
+            } else {
+                failed.push(format!("0x{:04X}", ddid));
+            }
+        }
+        if restored > 0 {
+            self.log("info", &format!("Restored {} dynamic DIDs", restored));
+        }
+        if !failed.is_empty() {
+            self.log(
+                "warn",
+                &format!("Dynamic DIDs not restored: {}", failed.join(", ")),
+            );
+        }
+        restored
+    }
+
+    /// 读取 DTC 信息
+    pub async fn read_dtc_information(&mut self, sub_function: u8) -> UdsResult<UdsResponse> {
+        match self.request(&[0x19, sub_function, 0xAF]).await {
+            Ok(response) => {
+                self.log("info", "Read DTC information granted");
+                Ok(UdsResponse {
+                    success: true,
+                    data: Some(response),
+                    error: None,
+                })
+            }
+            Err(e) => {
+                self.log("error", &format!("Read DTC information denied: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    /// 清除诊断信息
+    pub async fn clear_diagnostic_information(&mut self) -> UdsResult<bool> {
+        // 清除所有DTC
+        match self.request(&[0x14, 0xFF, 0xFF, 0xFF]).await {
+            Ok(_) => {
+                self.log("info", "Clear diagnostic information granted");
+                Ok(true)
+            }
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!("Clear diagnostic information denied: {}", e),
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// 例程控制（0x31），设置抑制正响应位且无响应时返回 None
+    pub async fn routine_control(
+        &mut self,
+        control_type: u8,
+        routine_id: u16,
+        option_record: &[u8],
+    ) -> UdsResult<Option<RoutineStatus>> {
+        let mut request = vec![0x31, control_type];
+        request.extend_from_slice(&routine_id.to_be_bytes());
+        request.extend_from_slice(option_record);
+
+        match self.request_optional(&request).await {
+            Ok(response) => {
+                let status = response.as_deref().map(parse_routine_status).transpose()?;
+                self.log(
+                    "info",
+                    &format!(
+                        "Routine control 0x{:02X} for 0x{:04X} granted",
+                        control_type, routine_id
+                    ),
+                );
+                Ok(status)
+            }
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!(
+                        "Routine control 0x{:02X} for 0x{:04X} denied: {}",
+                        control_type, routine_id, e
+                    ),
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// 轮询例程结果，直到 routineInfo 不再表示运行中或超时
+    pub async fn poll_routine_results(
+        &mut self,
+        routine_id: u16,
+        poll: &RoutinePollConfig,
+    ) -> UdsResult<RoutineStatus> {
+        let deadline = Instant::now() + Duration::from_millis(poll.timeout_ms);
+
+        loop {
+            let status = self
+                .routine_control(RoutineControlType::REQUEST_ROUTINE_RESULTS, routine_id, &[])
+                .await?
+                .ok_or_else(|| {
+                    UdsError::InvalidResponse("Missing routine results response".to_string())
+                })?;
+
+            if poll.is_complete(&status) {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                self.log(
+                    "error",
+                    &format!(
+                        "Routine 0x{:04X} still running after {} ms",
+                        routine_id, poll.timeout_ms
+                    ),
+                );
+                return Err(UdsError::RoutineTimeout(routine_id));
+            }
+
+            self.log(
+                "debug",
+                &format!(
+                    "Routine 0x{:04X} in progress (info {:02X?})",
+                    routine_id,
+                    status.routine_info()
+                ),
+            );
+            tokio::time::sleep(Duration::from_millis(poll.interval_ms)).await;
+        }
+    }
+
+    /// 启动例程并等待其结束
+    ///
+    /// 启动响应已表明例程结束（同步例程）时直接返回，否则轮询请求结果。
+    pub async fn run_routine(
+        &mut self,
+        routine_id: u16,
+        option_record: &[u8],
+        poll: &RoutinePollConfig,
+    ) -> UdsResult<RoutineStatus> {
+        let started = self
+            .routine_control(RoutineControlType::START_ROUTINE, routine_id, option_record)
+            .await?;
+
+        match started {
+            Some(status) if status.routine_info().is_some() && poll.is_complete(&status) => {
+                Ok(status)
+            }
+            _ => self.poll_routine_results(routine_id, poll).await,
+        }
+    }
+
+    /// 请求下载（0x34），返回 ECU 允许的 maxNumberOfBlockLength
+    pub async fn request_download(
+        &mut self,
+        data_format_identifier: u8,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> UdsResult<usize> {
+        let mut request = vec![0x34, data_format_identifier];
+        request.extend(format.encode(address, size)?);
+
+        match self.request(&request).await {
+            Ok(response) => {
+                let max_block_length = parse_max_block_length(&response)?;
+                self.log(
+                    "info",
+                    &format!(
+                        "Request download 0x{:X} ({} bytes) granted, max block length {}",
+                        address, size, max_block_length
+                    ),
+                );
+                Ok(max_block_length)
+            }
+            Err(e) => {
+                self.log("error", &format!("Request download denied: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    /// 请求上传（0x35），返回 ECU 允许的 maxNumberOfBlockLength
+    pub async fn request_upload(
+        &mut self,
+        data_format_identifier: u8,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> UdsResult<usize> {
+        let mut request = vec![0x35, data_format_identifier];
+        request.extend(format.encode(address, size)?);
+
+        match self.request(&request).await {
+            Ok(response) => {
+                let max_block_length = parse_max_block_length(&response)?;
+                self.log(
+                    "info",
+                    &format!(
+                        "Request upload 0x{:X} ({} bytes) granted, max block length {}",
+                        address, size, max_block_length
+                    ),
+                );
+                Ok(max_block_length)
+            }
+            Err(e) => {
+                self.log("error", &format!("Request upload denied: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    /// 传输数据（0x36），返回 transferResponseParameterRecord
+    pub async fn transfer_data(
+        &mut self,
+        block_sequence_counter: u8,
+        data: &[u8],
+    ) -> UdsResult<Vec<u8>> {
+        match self
+            .request_parts(&[0x36, block_sequence_counter], data)
             .await
-            .map_err(UdsError::DoipError)?;
+        {
+            Ok(response) => Ok(response[2..].to_vec()),
+            Err(e) => {
+                self.log(
+                    "error",
+                    &format!(
+                        "Transfer data block 0x{:02X} denied: {}",
+                        block_sequence_counter, e
+                    ),
+                );
+                Err(e)
+            }
+        }
+    }
+
+    /// 请求退出传输（0x37），返回 transferResponseParameterRecord
+    pub async fn request_transfer_exit(&mut self, parameter_record: &[u8]) -> UdsResult<Vec<u8>> {
+        let mut request = vec![0x37];
+        request.extend_from_slice(parameter_record);
 
-        if suppress_response {
-            return Ok(true); // 抑制响应模式，不等待响应
+        match self.request(&request).await {
+            Ok(response) => {
+                self.log("info", "Request transfer exit granted");
+                Ok(response[1..].to_vec())
+            }
+            Err(e) => {
+                self.log("error", &format!("Request transfer exit denied: {}", e));
+                Err(e)
+            }
         }
+    }
 
-        let response = self.doip_receive_handle(&[0x3E]).await?;
+    /// 下载一个内存段：RequestDownload + 多次 TransferData + RequestTransferExit
+    ///
+    /// 块序号从 0x01 开始，0xFF 之后回绕为 0x00。每个数据块发送成功后回调进度。
+    pub async fn download_segment<F>(
+        &mut self,
+        address: u64,
+        data: &[u8],
+        options: &DownloadOptions,
+        on_progress: F,
+    ) -> UdsResult<usize>
+    where
+        F: FnMut(DownloadProgress),
+    {
+        self.download_segment_abortable(
+            address,
+            data,
+            options,
+            &AtomicBool::new(false),
+            on_progress,
+        )
+        .await
+    }
 
-        if find_bytes(&response, &[0x7E]).is_some() {
-            self.log("info", "Tester present granted");
-            Ok(true)
+    /// 可中止的内存段下载，每个数据块发送前检查中止标志
+    pub async fn download_segment_abortable<F>(
+        &mut self,
+        address: u64,
+        data: &[u8],
+        options: &DownloadOptions,
+        abort: &AtomicBool,
+        on_progress: F,
+    ) -> UdsResult<usize>
+    where
+        F: FnMut(DownloadProgress),
+    {
+        let codec = options.encoding.build()?;
+        self.download_segment_with_codec(address, data, options, &codec, abort, on_progress)
+            .await
+    }
+
+    /// 使用指定编解码器压缩/加密后下载内存段，进度按原始数据长度上报
+    pub async fn download_segment_with_codec<F>(
+        &mut self,
+        address: u64,
+        data: &[u8],
+        options: &DownloadOptions,
+        codec: &DataCodec,
+        abort: &AtomicBool,
+        mut on_progress: F,
+    ) -> UdsResult<usize>
+    where
+        F: FnMut(DownloadProgress),
+    {
+        self.download_segment_checkpointed(address, data, options, codec, None, abort, |p, _| {
+            on_progress(p)
+        })
+        .await
+    }
+
+    /// 下载内存段，每个数据块被 ECU 确认后回调传输断点。
+    /// 提供 resume 时不再发送 RequestDownload，从断点之后的数据块继续传输（需引导程序支持）
+    #[allow(clippy::too_many_arguments)]
+    pub async fn download_segment_checkpointed<F>(
+        &mut self,
+        address: u64,
+        data: &[u8],
+        options: &DownloadOptions,
+        codec: &DataCodec,
+        resume: Option<TransferCheckpoint>,
+        abort: &AtomicBool,
+        mut on_block: F,
+    ) -> UdsResult<usize>
+    where
+        F: FnMut(DownloadProgress, TransferCheckpoint),
+    {
+        let format = AddressAndLengthFormat::from_identifier(options.address_and_length_format)?;
+        let data_format_identifier = if codec.is_identity() {
+            options.data_format_identifier
         } else {
-            self.log("error", "Tester present denied or error occurred");
-            Err(UdsError::RequestDenied("Tester present denied".to_string()))
+            codec.identifier()
+        };
+        // 逐块编码，不预先压缩、加密整个内存段
+        let mut stream = codec.encode_stream(data);
+
+        let mut checkpoint = match resume {
+            Some(checkpoint) => {
+                self.log(
+                    "info",
+                    &format!(
+                        "Resuming download to 0x{:X} after block 0x{:02X} ({} bytes confirmed)",
+                        address, checkpoint.block_sequence_counter, checkpoint.confirmed_bytes
+                    ),
+                );
+                checkpoint
+            }
+            None => {
+                // memorySize 为解压解密后的数据长度
+                let ecu_max_block_length = self
+                    .request_download(data_format_identifier, format, address, data.len() as u64)
+                    .await?;
+
+                // maxNumberOfBlockLength 包含 SID 和块序号
+                TransferCheckpoint {
+                    block_sequence_counter: 0x00,
+                    confirmed_bytes: 0,
+                    max_block_length: options
+                        .max_block_length
+                        .map_or(ecu_max_block_length, |limit| {
+                            limit.min(ecu_max_block_length)
+                        }),
+                }
+            }
+        };
+        if checkpoint.max_block_length < 3 {
+            return Err(UdsError::InvalidParameter(format!(
+                "Block length {} leaves no room for data",
+                checkpoint.max_block_length
+            )));
+        }
+        // 重新编码已确认的部分，编码结果确定，断点之后的数据块与中断前一致
+        while stream.produced() < checkpoint.confirmed_bytes {
+            let skip = checkpoint.confirmed_bytes - stream.produced();
+            if stream.next_block(skip)?.is_none() {
+                return Err(UdsError::InvalidParameter(format!(
+                    "Checkpoint at {} bytes is beyond the {} byte payload",
+                    checkpoint.confirmed_bytes,
+                    stream.produced()
+                )));
+            }
+        }
+
+        // 批量传输期间对逐帧收发日志采样
+        self.client
+            .set_log_sample_interval(TRANSFER_LOG_SAMPLE_INTERVAL);
+        let transferred = self
+            .transfer_blocks(
+                address,
+                data.len(),
+                &mut stream,
+                &mut checkpoint,
+                abort,
+                &mut on_block,
+            )
+            .await;
+        self.client.set_log_sample_interval(1);
+        let block_count = transferred?;
+
+        self.request_transfer_exit(&[]).await?;
+        self.log(
+            "info",
+            &format!(
+                "Downloaded {} bytes ({} bytes on the wire) to 0x{:X} in {} blocks",
+                data.len(),
+                stream.produced(),
+                address,
+                block_count
+            ),
+        );
+        Ok(block_count)
+    }
+
+    /// 从断点之后逐块发送 TransferData，返回发送的块数
+    async fn transfer_blocks<F>(
+        &mut self,
+        address: u64,
+        data_len: usize,
+        stream: &mut EncodeStream<'_>,
+        checkpoint: &mut TransferCheckpoint,
+        abort: &AtomicBool,
+        on_block: &mut F,
+    ) -> UdsResult<usize>
+    where
+        F: FnMut(DownloadProgress, TransferCheckpoint),
+    {
+        let mut block_count = 0;
+
+        while let Some(chunk) = stream.next_block(checkpoint.max_block_length - 2)? {
+            if abort.load(Ordering::SeqCst) {
+                self.log(
+                    "info",
+                    &format!(
+                        "Download to 0x{:X} aborted after {} bytes",
+                        address, checkpoint.confirmed_bytes
+                    ),
+                );
+                return Err(UdsError::Aborted);
+            }
+            if let Some(limiter) = self.bandwidth_limiter.clone() {
+                limiter.acquire(chunk.len()).await;
+            }
+            let block_sequence_counter = checkpoint.block_sequence_counter.wrapping_add(1);
+            self.transfer_data(block_sequence_counter, &chunk).await?;
+            checkpoint.block_sequence_counter = block_sequence_counter;
+            checkpoint.confirmed_bytes += chunk.len();
+            block_count += 1;
+            on_block(
+                DownloadProgress {
+                    address,
+                    total_bytes: data_len,
+                    transferred_bytes: stream.progress().min(data_len),
+                    block_sequence_counter,
+                },
+                *checkpoint,
+            );
+        }
+        Ok(block_count)
+    }
+
+    /// 上传一个内存区域：RequestUpload + 多次 TransferData + RequestTransferExit
+    ///
+    /// 块序号规则与下载相同，ECU 返回的数据累计达到请求长度后退出传输。
+    pub async fn upload_segment<F>(
+        &mut self,
+        address: u64,
+        size: usize,
+        options: &UploadOptions,
+        abort: &AtomicBool,
+        mut on_progress: F,
+    ) -> UdsResult<Vec<u8>>
+    where
+        F: FnMut(UploadProgress),
+    {
+        if size == 0 {
+            return Err(UdsError::InvalidParameter(
+                "Upload size must not be zero".to_string(),
+            ));
+        }
+        let format = AddressAndLengthFormat::from_identifier(options.address_and_length_format)?;
+        let max_block_length = self
+            .request_upload(0x00, format, address, size as u64)
+            .await?;
+
+        self.client
+            .set_log_sample_interval(TRANSFER_LOG_SAMPLE_INTERVAL);
+        let uploaded = self
+            .upload_blocks(address, size, max_block_length, abort, &mut on_progress)
+            .await;
+        self.client.set_log_sample_interval(1);
+        let data = uploaded?;
+
+        self.request_transfer_exit(&[]).await?;
+        self.log(
+            "info",
+            &format!("Uploaded {} bytes from 0x{:X}", data.len(), address),
+        );
+        Ok(data)
+    }
+
+    /// 逐块请求上传数据直到收满 size 字节
+    async fn upload_blocks<F>(
+        &mut self,
+        address: u64,
+        size: usize,
+        max_block_length: usize,
+        abort: &AtomicBool,
+        on_progress: &mut F,
+    ) -> UdsResult<Vec<u8>>
+    where
+        F: FnMut(UploadProgress),
+    {
+        let mut data = Vec::with_capacity(size);
+        let mut block_sequence_counter = 0x00u8;
+
+        while data.len() < size {
+            if abort.load(Ordering::SeqCst) {
+                self.log(
+                    "info",
+                    &format!(
+                        "Upload from 0x{:X} aborted after {} of {} bytes",
+                        address,
+                        data.len(),
+                        size
+                    ),
+                );
+                return Err(UdsError::Aborted);
+            }
+            block_sequence_counter = block_sequence_counter.wrapping_add(1);
+            let block = self.transfer_data(block_sequence_counter, &[]).await?;
+            // maxNumberOfBlockLength 包含 SID 和块序号
+            if block.is_empty() || block.len() > max_block_length - 2 {
+                return Err(UdsError::InvalidResponse(format!(
+                    "Upload block 0x{:02X} carries {} bytes, expected 1 to {}",
+                    block_sequence_counter,
+                    block.len(),
+                    max_block_length - 2
+                )));
+            }
+            if data.len() + block.len() > size {
+                return Err(UdsError::InvalidResponse(format!(
+                    "ECU returned {} bytes, more than the {} bytes requested",
+                    data.len() + block.len(),
+                    size
+                )));
+            }
+            data.extend_from_slice(&block);
+            on_progress(UploadProgress {
+                address,
+                total_bytes: size,
+                transferred_bytes: data.len(),
+                block_sequence_counter,
+            });
+        }
+        Ok(data)
+    }
+
+    /// 按地址读内存（0x23），返回 memorySize 字节数据
+    pub async fn read_memory_by_address(
+        &mut self,
+        format: AddressAndLengthFormat,
+        address: u64,
+        size: u64,
+    ) -> UdsResult<Vec<u8>> {
+        let mut request = vec![0x23];
+        request.extend(format.encode(address, size)?);
+
+        let response = self.request(&request).await?;
+        let data = &response[1..];
+        if data.len() as u64 != size {
+            return Err(UdsError::InvalidResponse(format!(
+                "ReadMemoryByAddress 0x{:X} returned {} bytes, expected {}",
+                address,
+                data.len(),
+                size
+            )));
+        }
+        Ok(data.to_vec())
+    }
+
+    /// 按地址写内存（0x3D）
+    pub async fn write_memory_by_address(
+        &mut self,
+        format: AddressAndLengthFormat,
+        address: u64,
+        data: &[u8],
+    ) -> UdsResult<()> {
+        let mut request = vec![0x3D];
+        request.extend(format.encode(address, data.len() as u64)?);
+        self.request_parts(&request, data).await?;
+        Ok(())
+    }
+
+    /// 读取任意长度的内存区域，按 ECU 最大响应长度分块；
+    /// ECU 返回 responseTooLong 时减半块长度重试
+    pub async fn read_memory(
+        &mut self,
+        address: u64,
+        length: usize,
+        options: &MemoryAccessOptions,
+    ) -> UdsResult<Vec<u8>> {
+        let format = AddressAndLengthFormat::from_identifier(options.address_and_length_format)?;
+        let mut chunk_len = options.max_response_length.saturating_sub(1);
+        if chunk_len == 0 {
+            return Err(UdsError::InvalidParameter(format!(
+                "Max response length {} leaves no room for data",
+                options.max_response_length
+            )));
+        }
+
+        let mut data = Vec::with_capacity(length);
+        while data.len() < length {
+            let offset = address + data.len() as u64;
+            let size = chunk_len.min(length - data.len());
+            match self
+                .read_memory_by_address(format, offset, size as u64)
+                .await
+            {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(e) if e.nrc() == Some(NegativeResponseCode::ResponseTooLong) && size > 1 => {
+                    chunk_len = size / 2;
+                    self.log(
+                        "info",
+                        &format!(
+                            "Read at 0x{:X} too long, retrying with {} byte chunks",
+                            offset, chunk_len
+                        ),
+                    );
+                }
+                Err(e) => {
+                    self.log(
+                        "error",
+                        &format!("Read memory at 0x{:X} failed: {}", offset, e),
+                    );
+                    return Err(e);
+                }
+            }
+        }
+        Ok(data)
+    }
+
+    /// 写入任意长度的数据，按 ECU 最大请求长度分块
+    pub async fn write_memory(
+        &mut self,
+        address: u64,
+        data: &[u8],
+        options: &MemoryAccessOptions,
+    ) -> UdsResult<()> {
+        let format = AddressAndLengthFormat::from_identifier(options.address_and_length_format)?;
+        // SID + ALFID + 地址 + 长度
+        let overhead = 2 + (format.address_bytes + format.length_bytes) as usize;
+        let chunk_len = options.max_request_length.saturating_sub(overhead);
+        if chunk_len == 0 {
+            return Err(UdsError::InvalidParameter(format!(
+                "Max request length {} leaves no room for data",
+                options.max_request_length
+            )));
+        }
+
+        for (i, chunk) in data.chunks(chunk_len).enumerate() {
+            let offset = address + (i * chunk_len) as u64;
+            if let Err(e) = self.write_memory_by_address(format, offset, chunk).await {
+                self.log(
+                    "error",
+                    &format!("Write memory at 0x{:X} failed: {}", offset, e),
+                );
+                return Err(e);
+            }
+        }
+        self.log(
+            "info",
+            &format!("Wrote {} bytes to 0x{:X}", data.len(), address),
+        );
+        Ok(())
+    }
+
+    /// 测试器在线
+    pub async fn tester_present(&mut self, suppress_response: bool) -> UdsResult<bool> {
+        // 抑制响应模式下仍在 P2client 内等待可能的否定响应
+        let sub_function = if suppress_response { 0x80 } else { 0x00 };
+
+        match self.request_optional(&[0x3E, sub_function]).await {
+            Ok(_) => {
+                self.log("info", "Tester present granted");
+                Ok(true)
+            }
+            Err(e) => {
+                self.log("error", &format!("Tester present denied: {}", e));
+                Err(e)
+            }
+        }
+    }
+
+    /// 发送原始 UDS 请求，返回目标 ECU 发来的所有诊断报文、最终响应、NRC 与耗时
+    ///
+    /// 响应挂起和周期数据原样记录，不做响应校验。等待时间与普通请求相同（P2client，
+    /// 响应挂起后 P2*client），超时作为结果的一部分返回而不是错误。
+    pub async fn send_raw_request(&mut self, payload: &[u8]) -> UdsResult<RawUdsResponse> {
+        if payload.is_empty() {
+            return Err(UdsError::InvalidParameter(
+                "UDS payload must not be empty".to_string(),
+            ));
+        }
+        let response_required = !suppresses_positive_response(payload);
+
+        let start_time = Instant::now();
+        // 周期数据照常送入通道，同时交给本次请求记录
+        self.capture_periodic.store(true, Ordering::SeqCst);
+        let received = self.raw_exchange(payload, start_time).await;
+        self.capture_periodic.store(false, Ordering::SeqCst);
+        let (frames, response, pending_count) = received?;
+        let elapsed = start_time.elapsed();
+        let timed_out = response.is_none() && (response_required || pending_count > 0);
+
+        let nrc = match response.as_deref() {
+            Some([0x7F, _, nrc, ..]) => Some(*nrc),
+            _ => None,
+        };
+
+        if timed_out {
+            self.log(
+                "info",
+                &format!(
+                    "Raw request {:02X?} got no final response within {:.1} ms",
+                    payload,
+                    elapsed.as_secs_f64() * 1000.0
+                ),
+            );
+        } else {
+            self.log(
+                "info",
+                &format!(
+                    "Raw request {:02X?} answered in {:.1} ms",
+                    payload,
+                    elapsed.as_secs_f64() * 1000.0
+                ),
+            );
+        }
+
+        Ok(RawUdsResponse {
+            request: payload.to_vec(),
+            frames,
+            response,
+            nrc,
+            timed_out,
+            elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        })
+    }
+
+    /// 发送原始请求并记录目标 ECU 发来的诊断报文，返回报文、最终响应和响应挂起次数
+    async fn raw_exchange(
+        &mut self,
+        payload: &[u8],
+        start_time: Instant,
+    ) -> UdsResult<(Vec<RawUdsFrame>, Option<Vec<u8>>, u32)> {
+        let ecu_address = u16::from_be_bytes([self.server_address[0], self.server_address[1]]);
+        let tester_address = u16::from_be_bytes([self.client_address[0], self.client_address[1]]);
+        self.send_request(payload).await?;
+
+        let mut frames = Vec::new();
+        let mut pending_count = 0;
+        let mut deadline = Instant::now() + self.p2_client;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let data = match self.client.poll_frame(remaining).await {
+                Ok(Some(data)) => data,
+                Ok(None) => return Ok((frames, None, pending_count)),
+                Err(e) => return Err(UdsError::DoipError(e)),
+            };
+            let frame = DoipFrame::parse(&data)?;
+            let ReceivedFrame::Response(uds) = classify_frame(&frame, ecu_address, tester_address)?
+            else {
+                continue;
+            };
+            frames.push(RawUdsFrame {
+                data: uds.clone(),
+                elapsed_ms: start_time.elapsed().as_secs_f64() * 1000.0,
+            });
+            if parse_periodic_message(&uds).is_some() {
+                continue;
+            }
+            if is_response_pending(payload[0], &uds) {
+                pending_count += 1;
+                if pending_count > self.timing.max_pending_count {
+                    return Ok((frames, None, pending_count));
+                }
+                deadline = Instant::now() + self.p2_star_client;
+                continue;
+            }
+            return Ok((frames, Some(uds), pending_count));
         }
     }
 
@@ -700,8 +1878,1001 @@ impl UdsService {
         match level {
             "info" => log::info!("[{}] [UDS] {}", timestamp, message),
             "debug" => log::debug!("[{}] [UDS] {}", timestamp, message),
+            "warn" => log::warn!("[{}] [UDS] {}", timestamp, message),
             "error" => log::error!("[{}] [UDS] {}", timestamp, message),
             _ => log::info!("[{}] [UDS] {}", timestamp, message),
         }
     }
 }
+
+/// 接收任务的帧路由：目标 ECU 的周期数据在收到时解码送入通道，
+/// capture 置位时仍交给等待响应的调用方
+fn periodic_router(
+    ecu_address: u16,
+    tester_address: u16,
+    periodic_tx: mpsc::UnboundedSender<PeriodicSample>,
+    capture: Arc<AtomicBool>,
+) -> FrameRouter {
+    Arc::new(move |data: &[u8]| {
+        let Ok(frame) = DoipFrame::parse(data) else {
+            return false;
+        };
+        let Ok(ReceivedFrame::Response(uds)) = classify_frame(&frame, ecu_address, tester_address)
+        else {
+            return false;
+        };
+        match parse_periodic_message(&uds) {
+            Some(sample) => {
+                let _ = periodic_tx.send(sample);
+                !capture.load(Ordering::SeqCst)
+            }
+            None => false,
+        }
+    })
+}
+
+/// 按 DID 长度拆分组合响应（不含 SID），响应中的 DID 须按请求顺序出现，可省略不支持的 DID
+fn split_did_records(
+    data: &[u8],
+    dids: &[u16],
+    lengths: &BTreeMap<u16, usize>,
+) -> UdsResult<Vec<DidRecord>> {
+    let mut records = Vec::new();
+    let mut expected = dids.iter();
+    let mut pos = 0;
+    while pos < data.len() {
+        let did = data
+            .get(pos..pos + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| UdsError::InvalidResponse(format!("Truncated DID at offset {}", pos)))?;
+        if !expected.any(|&d| d == did) {
+            return Err(UdsError::InvalidResponse(format!(
+                "Unexpected DID 0x{:04X} at offset {}",
+                did, pos
+            )));
+        }
+        let len = lengths[&did];
+        let record = data.get(pos + 2..pos + 2 + len).ok_or_else(|| {
+            UdsError::InvalidResponse(format!(
+                "DID 0x{:04X} needs {} bytes, {} left",
+                did,
+                len,
+                data.len() - pos - 2
+            ))
+        })?;
+        records.push(DidRecord {
+            did,
+            data: record.to_vec(),
+        });
+        pos += 2 + len;
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_format::{CompressionConfig, EncryptionConfig, TransferEncoding};
+    use crate::ecu_simulator::{
+        fast_timing, memory_handler, recording, request_log, sim_memory, EcuSimulator, RequestLog,
+        SimAction,
+    };
+    use crate::types::{CommonRoutines, DidSource, MemoryRange, MemorySegment};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_response_pending_extends_to_p2_star() {
+        let sim = EcuSimulator::start(|request| match request {
+            [0x31, 0x01, 0xFF, 0x00] => vec![
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x71, 0x01, 0xFF, 0x00]),
+            ],
+            _ => vec![],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        // 两次 300 ms 的挂起都超过 P2client (200 ms)，但在 P2*client (500 ms) 内
+        let response = service.request(&[0x31, 0x01, 0xFF, 0x00]).await.unwrap();
+        assert_eq!(response, vec![0x71, 0x01, 0xFF, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_p2_timeout_is_timing_violation() {
+        let sim = EcuSimulator::start(|_| vec![]).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let err = service.request(&[0x22, 0xF1, 0x90]).await.unwrap_err();
+        assert!(matches!(err, UdsError::TimingViolation(_)));
+    }
+
+    #[tokio::test]
+    async fn test_max_pending_count_exceeded() {
+        let sim = EcuSimulator::start(|_| {
+            vec![
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+            ]
+        })
+        .await;
+        let mut service = sim
+            .connect(UdsTimingConfig {
+                max_pending_count: 2,
+                ..fast_timing()
+            })
+            .await;
+
+        let err = service
+            .request(&[0x31, 0x01, 0xFF, 0x00])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::TimingViolation(_)));
+    }
+
+    #[tokio::test]
+    async fn test_busy_repeat_request() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counter = attempts.clone();
+        let sim = EcuSimulator::start(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                vec![SimAction::Respond(vec![0x7F, 0x22, 0x21])]
+            } else {
+                vec![SimAction::Respond(vec![0x62, 0xF1, 0x90, 0x41])]
+            }
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let response = service.request(&[0x22, 0xF1, 0x90]).await.unwrap();
+        assert_eq!(response, vec![0x62, 0xF1, 0x90, 0x41]);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_busy_repeat_exhausted() {
+        let sim = EcuSimulator::start(|_| vec![SimAction::Respond(vec![0x7F, 0x22, 0x21])]).await;
+        let mut service = sim
+            .connect(UdsTimingConfig {
+                busy_repeat_count: 1,
+                ..fast_timing()
+            })
+            .await;
+
+        let err = service.request(&[0x22, 0xF1, 0x90]).await.unwrap_err();
+        assert_eq!(err.nrc(), Some(NegativeResponseCode::BusyRepeatRequest));
+    }
+
+    #[tokio::test]
+    async fn test_session_timing_from_response() {
+        let sim = EcuSimulator::start(|_| {
+            vec![SimAction::Respond(vec![0x50, 0x03, 0x00, 0x32, 0x01, 0xF4])]
+        })
+        .await;
+        let mut service = sim
+            .connect(UdsTimingConfig {
+                network_delay_ms: 100,
+                ..fast_timing()
+            })
+            .await;
+
+        assert!(service.start_session(0x03).await.unwrap());
+        assert_eq!(service.p2_client, Duration::from_millis(150));
+        assert_eq!(service.p2_star_client, Duration::from_millis(5100));
+    }
+
+    #[tokio::test]
+    async fn test_response_from_other_ecu_is_ignored() {
+        let sim = EcuSimulator::start(|_| {
+            vec![
+                SimAction::RespondFrom(0x1002, vec![0x50, 0x03]),
+                SimAction::Respond(vec![0x7F, 0x10, 0x22]),
+            ]
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let err = service.start_session(0x03).await.unwrap_err();
+        assert_eq!(err.nrc(), Some(NegativeResponseCode::ConditionsNotCorrect));
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_request_without_response() {
+        let sim = EcuSimulator::start(|_| vec![]).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        assert!(service.tester_present(true).await.unwrap());
+        assert!(service.start_session(0x83).await.unwrap());
+        assert_eq!(service.request_optional(&[0x11, 0x81]).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_request_reports_negative_response() {
+        let sim = EcuSimulator::start(|_| vec![SimAction::Respond(vec![0x7F, 0x85, 0x22])]).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let err = service.control_dtc_setting(0x82).await.unwrap_err();
+        assert_eq!(err.nrc(), Some(NegativeResponseCode::ConditionsNotCorrect));
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_request_after_pending_returns_response() {
+        let sim = EcuSimulator::start(|_| {
+            vec![
+                SimAction::Respond(vec![0x7F, 0x11, 0x78]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x51, 0x01]),
+            ]
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        // 收到 78 后服务端必须给出最终响应，即使请求设置了抑制位
+        let response = service.request_optional(&[0x11, 0x81]).await.unwrap();
+        assert_eq!(response, Some(vec![0x51, 0x01]));
+    }
+
+    #[tokio::test]
+    async fn test_run_routine_polls_until_complete() {
+        let polls = Arc::new(AtomicU32::new(0));
+        let counter = polls.clone();
+        let sim = EcuSimulator::start(move |request| match request {
+            [0x31, 0x01, 0xFF, 0x00, 0x01] => {
+                vec![SimAction::Respond(vec![0x71, 0x01, 0xFF, 0x00, 0x02])]
+            }
+            [0x31, 0x03, 0xFF, 0x00] => {
+                let info = if counter.fetch_add(1, Ordering::SeqCst) < 2 {
+                    0x02
+                } else {
+                    0x00
+                };
+                vec![SimAction::Respond(vec![0x71, 0x03, 0xFF, 0x00, info, 0xAA])]
+            }
+            _ => vec![SimAction::Respond(vec![0x7F, 0x31, 0x12])],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let poll = RoutinePollConfig {
+            interval_ms: 10,
+            ..RoutinePollConfig::default()
+        };
+        let status = service
+            .run_routine(CommonRoutines::ERASE_MEMORY, &[0x01], &poll)
+            .await
+            .unwrap();
+        assert_eq!(
+            status.control_type,
+            RoutineControlType::REQUEST_ROUTINE_RESULTS
+        );
+        assert_eq!(status.status_record, vec![0x00, 0xAA]);
+        assert_eq!(polls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_routine_synchronous_result() {
+        let sim = EcuSimulator::start(|request| match request {
+            [0x31, 0x01, 0xFF, 0x01] => vec![
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x71, 0x01, 0xFF, 0x01, 0x00]),
+            ],
+            _ => vec![SimAction::Respond(vec![0x7F, 0x31, 0x12])],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let status = service
+            .run_routine(
+                CommonRoutines::CHECK_PROGRAMMING_DEPENDENCIES,
+                &[],
+                &RoutinePollConfig::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(status.control_type, RoutineControlType::START_ROUTINE);
+        assert_eq!(status.routine_info(), Some(0x00));
+    }
+
+    #[tokio::test]
+    async fn test_routine_poll_timeout() {
+        let sim = EcuSimulator::start(|request| {
+            let mut response = request.to_vec();
+            response[0] = 0x71;
+            response.push(0x02);
+            vec![SimAction::Respond(response)]
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let poll = RoutinePollConfig {
+            interval_ms: 10,
+            timeout_ms: 50,
+            ..RoutinePollConfig::default()
+        };
+        let err = service.run_routine(0x0203, &[], &poll).await.unwrap_err();
+        assert!(matches!(err, UdsError::RoutineTimeout(0x0203)));
+    }
+
+    /// 模拟 ECU 的下载状态：接收到的数据和块序号
+    fn download_handler(
+        max_block_length: u16,
+        received: Arc<std::sync::Mutex<(Vec<u8>, Vec<u8>)>>,
+    ) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+        move |request| match request {
+            [0x34, ..] => {
+                let [hi, lo] = max_block_length.to_be_bytes();
+                vec![SimAction::Respond(vec![0x74, 0x20, hi, lo])]
+            }
+            [0x36, counter, data @ ..] => {
+                let mut received = received.lock().unwrap();
+                received.0.extend_from_slice(data);
+                received.1.push(*counter);
+                vec![SimAction::Respond(vec![0x76, *counter])]
+            }
+            [0x37] => vec![SimAction::Respond(vec![0x77])],
+            _ => vec![SimAction::Respond(vec![0x7F, request[0], 0x11])],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_segment() {
+        let received = Arc::new(std::sync::Mutex::new((Vec::new(), Vec::new())));
+        let sim = EcuSimulator::start(download_handler(0x0102, received.clone())).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut progress = Vec::new();
+        let blocks = service
+            .download_segment(0x0800_0000, &data, &DownloadOptions::default(), |p| {
+                progress.push(p.transferred_bytes)
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(blocks, 4);
+        assert_eq!(progress, vec![256, 512, 768, 1000]);
+        let received = received.lock().unwrap();
+        assert_eq!(received.0, data);
+        assert_eq!(received.1, vec![1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_download_block_sequence_counter_wraps() {
+        let received = Arc::new(std::sync::Mutex::new((Vec::new(), Vec::new())));
+        let sim = EcuSimulator::start(download_handler(0x0FFF, received.clone())).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        // 本地限制块长度为 3，每块只携带 1 字节数据
+        let options = DownloadOptions {
+            max_block_length: Some(3),
+            ..DownloadOptions::default()
+        };
+        let data = vec![0x5A; 300];
+        let blocks = service
+            .download_segment(0x1000, &data, &options, |_| {})
+            .await
+            .unwrap();
+
+        assert_eq!(blocks, 300);
+        let received = received.lock().unwrap();
+        assert_eq!(received.1[254], 0xFF);
+        assert_eq!(received.1[255], 0x00);
+        assert_eq!(received.1[256], 0x01);
+        assert_eq!(received.1[299], 44);
+    }
+
+    /// 吞吐量基准：向本地模拟器下载 4 MB，4 KB 数据块，带报文事件订阅和日志，与界面运行时一致
+    /// 运行：RUST_LOG=info cargo test --release bench_download_throughput -- --ignored --nocapture
+    #[tokio::test]
+    #[ignore]
+    async fn bench_download_throughput() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let received = Arc::new(std::sync::Mutex::new((Vec::new(), Vec::new())));
+        let sim = EcuSimulator::start(download_handler(4096 + 2, received.clone())).await;
+        let events = Arc::new(AtomicU32::new(0));
+        let counter = events.clone();
+        let sink: TrafficSink = Arc::new(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let mut service = UdsService::connect(&sim.connection_config(fast_timing()), Some(sink))
+            .await
+            .unwrap();
+
+        let data: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i * 31) as u8).collect();
+        let start = Instant::now();
+        let blocks = service
+            .download_segment(0x0800_0000, &data, &DownloadOptions::default(), |_| {})
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+
+        log::info!(
+            "Downloaded {} bytes in {} blocks: {:.1} ms, {:.2} MB/s, {:.1} us/block, {} traffic events",
+            data.len(),
+            blocks,
+            elapsed.as_secs_f64() * 1000.0,
+            data.len() as f64 / elapsed.as_secs_f64() / (1024.0 * 1024.0),
+            elapsed.as_secs_f64() * 1e6 / blocks as f64,
+            events.load(Ordering::Relaxed)
+        );
+        assert_eq!(blocks, 1024);
+        assert_eq!(received.lock().unwrap().0.len(), data.len());
+    }
+
+    #[tokio::test]
+    async fn test_security_unlock_levels() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(recording(requests.clone(), move |request| {
+            let response = match request {
+                [0x27, level] => vec![0x67, *level, 0x12, 0x34, 0x56, 0x78],
+                [0x27, level, ..] => vec![0x67, *level],
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        }))
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        // 非请求种子级别直接拒绝，不发送请求
+        for level in [0x02, 0x7F, 0xFF] {
+            assert!(matches!(
+                service.security_unlock(level, 0x1234).await,
+                Err(UdsError::InvalidParameter(_))
+            ));
+        }
+        assert!(requests.lock().unwrap().is_empty());
+
+        // 厂商扩展级别
+        service.security_unlock(0x41, 0x1234).await.unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0], vec![0x27, 0x41]);
+        assert_eq!(requests[1][..2], [0x27, 0x42]);
+    }
+
+    #[tokio::test]
+    async fn test_io_control_returned_on_session_change() {
+        let requests = request_log();
+        let sim = EcuSimulator::start(recording(requests.clone(), move |request| {
+            let response = match request {
+                [0x2F, hi, lo, parameter, ..] => vec![0x6F, *hi, *lo, *parameter, 0x64],
+                [0x10, session] => vec![0x50, *session, 0x00, 0x32, 0x01, 0xF4],
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        }))
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        // 带控制使能掩码的短期调整和冻结
+        let status = service
+            .input_output_control(
+                0x4101,
+                IoControlParameter::SHORT_TERM_ADJUSTMENT,
+                &[0x64, 0x00],
+                &[0x80, 0x00],
+            )
+            .await
+            .unwrap();
+        assert_eq!(status, vec![0x64]);
+        service
+            .input_output_control(0x4102, IoControlParameter::FREEZE_CURRENT_STATE, &[], &[])
+            .await
+            .unwrap();
+        assert_eq!(service.active_io_controls(), vec![0x4101, 0x4102]);
+
+        requests.lock().unwrap().clear();
+        service.start_session(0x01).await.unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                vec![0x2F, 0x41, 0x01, 0x00, 0x80, 0x00],
+                vec![0x2F, 0x41, 0x02, 0x00],
+                vec![0x10, 0x01],
+            ]
+        );
+        assert!(service.active_io_controls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_io_control_return_failure_kept() {
+        let sim = EcuSimulator::start(|request| {
+            let response = match request {
+                [0x2F, 0x41, 0x02, 0x00, ..] => vec![0x7F, 0x2F, 0x22],
+                [0x2F, hi, lo, parameter, ..] => vec![0x6F, *hi, *lo, *parameter],
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        for did in [0x4101, 0x4102] {
+            service
+                .input_output_control(did, IoControlParameter::FREEZE_CURRENT_STATE, &[], &[])
+                .await
+                .unwrap();
+        }
+
+        // 交还失败的 DID 仍在接管列表中
+        assert_eq!(service.return_io_controls().await, vec![0x4102]);
+        assert_eq!(service.active_io_controls(), vec![0x4102]);
+    }
+
+    #[tokio::test]
+    async fn test_raw_request_records_every_frame() {
+        let sim = EcuSimulator::start(|request| match request {
+            [0x31, 0x01, 0xFF, 0x00] => vec![
+                SimAction::Respond(vec![0x7F, 0x31, 0x78]),
+                SimAction::Respond(vec![0x6A, 0x01, 0xAA]),
+                SimAction::Delay(300),
+                SimAction::Respond(vec![0x71, 0x01, 0xFF, 0x00]),
+            ],
+            // 只发挂起，不给最终响应
+            [0x31, 0x01, 0xFF, 0x01] => vec![SimAction::Respond(vec![0x7F, 0x31, 0x78])],
+            _ => vec![],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let raw = service
+            .send_raw_request(&[0x31, 0x01, 0xFF, 0x00])
+            .await
+            .unwrap();
+        let frames: Vec<_> = raw.frames.iter().map(|f| f.data.clone()).collect();
+        assert_eq!(
+            frames,
+            vec![
+                vec![0x7F, 0x31, 0x78],
+                vec![0x6A, 0x01, 0xAA],
+                vec![0x71, 0x01, 0xFF, 0x00],
+            ]
+        );
+        assert!(raw.frames[2].elapsed_ms >= 300.0);
+        assert!(raw.frames[2].elapsed_ms <= raw.elapsed_ms);
+        assert_eq!(raw.response, Some(vec![0x71, 0x01, 0xFF, 0x00]));
+        assert!(!raw.timed_out);
+
+        // 超时作为结果返回，保留已收到的挂起帧
+        let raw = service
+            .send_raw_request(&[0x31, 0x01, 0xFF, 0x01])
+            .await
+            .unwrap();
+        assert_eq!(raw.frames.len(), 1);
+        assert_eq!(raw.response, None);
+        assert!(raw.timed_out);
+        assert!(raw.elapsed_ms >= 500.0);
+
+        let raw = service.send_raw_request(&[0x22, 0xF1, 0x90]).await.unwrap();
+        assert!(raw.frames.is_empty());
+        assert!(raw.timed_out);
+
+        // 抑制正响应且无响应不算超时
+        let raw = service.send_raw_request(&[0x3E, 0x80]).await.unwrap();
+        assert_eq!(raw.response, None);
+        assert!(!raw.timed_out);
+    }
+
+    #[tokio::test]
+    async fn test_periodic_data_routed_to_sink() {
+        let sim = EcuSimulator::start(|request| match request {
+            [0x2A, 0x04, ..] => vec![SimAction::Respond(vec![0x6A])],
+            [0x2A, _, ids @ ..] => {
+                let mut actions = vec![SimAction::Respond(vec![0x6A])];
+                for (i, &id) in ids.iter().enumerate() {
+                    actions.push(SimAction::Respond(vec![0x6A, id, i as u8, 0x10]));
+                }
+                actions
+            }
+            // 周期报文先于响应到达
+            [0x3E, 0x00] => vec![
+                SimAction::Respond(vec![0x6A, 0x01, 0xAA]),
+                SimAction::Respond(vec![0x7E, 0x00]),
+            ],
+            [0x10, session] => vec![SimAction::Respond(vec![
+                0x50, *session, 0x00, 0x32, 0x01, 0xF4,
+            ])],
+            _ => vec![SimAction::Respond(vec![0x7F, request[0], 0x11])],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+        let mut samples = service.take_periodic_receiver().unwrap();
+        assert!(service.take_periodic_receiver().is_none());
+        let mut next_sample = || {
+            let sample = samples.try_recv().unwrap();
+            (sample.did, sample.data)
+        };
+
+        service
+            .read_data_by_periodic_identifier(
+                PeriodicTransmissionMode::SEND_AT_FAST_RATE,
+                &[0x01, 0x02],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            service.active_periodic_ids(),
+            vec![(0x01, 0x03), (0x02, 0x03)]
+        );
+        // 空闲时不经过调用方，由接收任务送入通道
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(next_sample(), (0xF201, "00 10".to_string()));
+        assert_eq!(next_sample(), (0xF202, "01 10".to_string()));
+
+        // 先于响应到达的周期报文不影响请求
+        service.tester_present(false).await.unwrap();
+        assert_eq!(next_sample(), (0xF201, "aa".to_string()));
+
+        service
+            .read_data_by_periodic_identifier(PeriodicTransmissionMode::STOP_SENDING, &[0x01])
+            .await
+            .unwrap();
+        assert_eq!(service.active_periodic_ids(), vec![(0x02, 0x03)]);
+        service.start_session(0x01).await.unwrap();
+        assert!(service.active_periodic_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_dids_restored_after_session_change() {
+        let requests = request_log();
+        let mut defined = std::collections::BTreeSet::new();
+        let sim = EcuSimulator::start(recording(requests.clone(), move |request| {
+            let response = match request {
+                [0x2C, 0x03] => {
+                    defined.clear();
+                    vec![0x6C, 0x03]
+                }
+                [0x2C, 0x03, hi, lo] => {
+                    if defined.remove(&[*hi, *lo]) {
+                        vec![0x6C, 0x03, *hi, *lo]
+                    } else {
+                        vec![0x7F, 0x2C, 0x31]
+                    }
+                }
+                [0x2C, sub @ (0x01 | 0x02), hi, lo, ..] => {
+                    defined.insert([*hi, *lo]);
+                    vec![0x6C, *sub, *hi, *lo]
+                }
+                // 会话切换时 ECU 丢弃所有动态 DID
+                [0x10, session] => {
+                    defined.clear();
+                    vec![0x50, *session, 0x00, 0x32, 0x01, 0xF4]
+                }
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        }))
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let by_identifier = DynamicDidDefinition::ByIdentifier {
+            sources: vec![
+                DidSource {
+                    source_did: 0x1234,
+                    position: 1,
+                    size: 2,
+                },
+                DidSource {
+                    source_did: 0x5678,
+                    position: 3,
+                    size: 1,
+                },
+            ],
+        };
+        let by_memory = DynamicDidDefinition::ByMemoryAddress {
+            address_and_length_format: 0x14,
+            sources: vec![MemoryRange {
+                address: 0x2000_0010,
+                length: 4,
+            }],
+        };
+        service
+            .define_dynamic_did(0xF300, &by_identifier)
+            .await
+            .unwrap();
+        service
+            .define_dynamic_did(0xF300, &by_memory)
+            .await
+            .unwrap();
+        service
+            .define_dynamic_did(0xF301, &by_memory)
+            .await
+            .unwrap();
+        service.clear_dynamic_did(Some(0xF301)).await.unwrap();
+        assert_eq!(
+            service.dynamic_dids().keys().copied().collect::<Vec<_>>(),
+            vec![0xF300]
+        );
+
+        requests.lock().unwrap().clear();
+        service.start_session(0x03).await.unwrap();
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                vec![0x10, 0x03],
+                vec![0x2C, 0x03, 0xF3, 0x00],
+                vec![0x2C, 0x01, 0xF3, 0x00, 0x12, 0x34, 0x01, 0x02, 0x56, 0x78, 0x03, 0x01],
+                vec![0x2C, 0x02, 0xF3, 0x00, 0x14, 0x20, 0x00, 0x00, 0x10, 0x04],
+            ]
+        );
+
+        // 编程会话不恢复动态 DID
+        requests.lock().unwrap().clear();
+        service.start_session(0x02).await.unwrap();
+        assert_eq!(*requests.lock().unwrap(), vec![vec![0x10, 0x02]]);
+
+        service.clear_dynamic_did(None).await.unwrap();
+        assert!(service.dynamic_dids().is_empty());
+    }
+
+    /// ECU 支持 F190（17 字节）和 F187（4 字节），combined 为 false 时拒绝多 DID 请求
+    fn did_handler(
+        combined: bool,
+        requests: RequestLog,
+    ) -> impl FnMut(&[u8]) -> Vec<SimAction> + Send + 'static {
+        recording(requests, move |request| {
+            let response = match request {
+                [0x22, dids @ ..] if dids.len() > 2 && !combined => vec![0x7F, 0x22, 0x13],
+                [0x22, dids @ ..] => {
+                    let mut response = vec![0x62];
+                    for did in dids.chunks(2) {
+                        match did {
+                            [0xF1, 0x90] => {
+                                response.extend_from_slice(b"\xF1\x90WDD1234567890ABCD")
+                            }
+                            [0xF1, 0x87] => response.extend_from_slice(&[0xF1, 0x87, 1, 2, 3, 4]),
+                            _ => {}
+                        }
+                    }
+                    if response.len() == 1 {
+                        vec![0x7F, 0x22, 0x31]
+                    } else {
+                        response
+                    }
+                }
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        })
+    }
+
+    #[tokio::test]
+    async fn test_read_multiple_dids() {
+        let dids = [0xF190, 0x0100, 0xF187];
+        let lengths = BTreeMap::from([(0xF190, 17), (0x0100, 2), (0xF187, 4)]);
+        let expected = vec![
+            DidRecord {
+                did: 0xF190,
+                data: b"WDD1234567890ABCD".to_vec(),
+            },
+            DidRecord {
+                did: 0xF187,
+                data: vec![1, 2, 3, 4],
+            },
+        ];
+
+        // 组合请求：响应省略不支持的 0100
+        let requests = request_log();
+        let sim = EcuSimulator::start(did_handler(true, requests.clone())).await;
+        let mut service = sim.connect(fast_timing()).await;
+        let records = service
+            .read_data_by_identifiers(&dids, &lengths)
+            .await
+            .unwrap();
+        assert_eq!(records, expected);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // 首个 DID 不支持时响应不回显该 DID，仍按组合响应拆分
+        requests.lock().unwrap().clear();
+        let records = service
+            .read_data_by_identifiers(&[0x0100, 0xF190, 0xF187], &lengths)
+            .await
+            .unwrap();
+        assert_eq!(records, expected);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        // ECU 拒绝组合请求，逐个读取
+        let requests = request_log();
+        let sim = EcuSimulator::start(did_handler(false, requests.clone())).await;
+        let mut service = sim.connect(fast_timing()).await;
+        let records = service
+            .read_data_by_identifiers(&dids, &lengths)
+            .await
+            .unwrap();
+        assert_eq!(records, expected);
+        assert_eq!(requests.lock().unwrap().len(), 4);
+
+        // 长度未知时不发送组合请求
+        requests.lock().unwrap().clear();
+        let records = service
+            .read_data_by_identifiers(&dids, &BTreeMap::new())
+            .await
+            .unwrap();
+        assert_eq!(records, expected);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_write_data_verified_is_binary_safe() {
+        // ECU 只保存前 4 字节，F1A0 只可写不可读
+        let stored = Arc::new(std::sync::Mutex::new(BTreeMap::<u16, Vec<u8>>::new()));
+        let store = stored.clone();
+        let sim = EcuSimulator::start(move |request| {
+            let mut store = store.lock().unwrap();
+            let response = match request {
+                [0x2E, hi, lo, data @ ..] => {
+                    let did = u16::from_be_bytes([*hi, *lo]);
+                    store.insert(did, data[..data.len().min(4)].to_vec());
+                    vec![0x6E, *hi, *lo]
+                }
+                [0x22, 0xF1, 0xA0] => vec![0x7F, 0x22, 0x31],
+                [0x22, hi, lo] => match store.get(&u16::from_be_bytes([*hi, *lo])) {
+                    Some(data) => [&[0x62, *hi, *lo][..], data].concat(),
+                    None => vec![0x7F, 0x22, 0x31],
+                },
+                _ => vec![0x7F, request[0], 0x11],
+            };
+            vec![SimAction::Respond(response)]
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let data = [0xFF, 0x80, 0x00, 0xC3];
+        assert_eq!(
+            service.write_data_verified(0xF18C, &data).await.unwrap(),
+            WriteVerification::Verified(data.to_vec())
+        );
+        assert_eq!(stored.lock().unwrap()[&0xF18C], data.to_vec());
+
+        let result = service
+            .write_data_verified(0xF18C, &[0x01, 0x02, 0x03, 0x04, 0x05])
+            .await;
+        assert!(matches!(result, Err(UdsError::VerificationFailed(_))));
+
+        // 读回被拒绝时写入仍然成功，结果为未校验
+        let result = service.write_data_verified(0xF1A0, &data).await.unwrap();
+        assert!(matches!(result, WriteVerification::Unverified(_)));
+        assert_eq!(stored.lock().unwrap()[&0xF1A0], data.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_read_and_write_memory_in_chunks() {
+        // 0x2000 起 64 字节内存，单次最多读 8 字节
+        let memory = sim_memory(MemorySegment::new(0x2000, (0..64u8).collect()));
+        let requests = request_log();
+        let sim = EcuSimulator::start(recording(
+            requests.clone(),
+            memory_handler(memory.clone(), 8),
+        ))
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+        let options = MemoryAccessOptions {
+            address_and_length_format: 0x12,
+            max_response_length: 17,
+            max_request_length: 13,
+        };
+
+        // 16 字节的块被拒绝（responseTooLong）后改为 8 字节
+        let data = service.read_memory(0x2004, 20, &options).await.unwrap();
+        assert_eq!(data, (4..24u8).collect::<Vec<u8>>());
+        let sizes: Vec<u8> = requests.lock().unwrap().iter().map(|r| r[4]).collect();
+        assert_eq!(sizes, vec![16, 8, 8, 4]);
+
+        requests.lock().unwrap().clear();
+        service
+            .write_memory(0x2010, &[0xEE; 10], &options)
+            .await
+            .unwrap();
+        let sizes: Vec<u8> = requests.lock().unwrap().iter().map(|r| r[4]).collect();
+        assert_eq!(sizes, vec![8, 2]);
+        assert_eq!(memory.lock().unwrap().data[0x10..0x1A], [0xEE; 10]);
+        assert_eq!(memory.lock().unwrap().data[0x1A], 0x1A);
+    }
+
+    #[tokio::test]
+    async fn test_upload_rejects_excess_data() {
+        // ECU 每块返回 4 字节，请求只读 6 字节
+        let sim = EcuSimulator::start(|request| match request {
+            [0x35, ..] => vec![SimAction::Respond(vec![0x75, 0x10, 0x06])],
+            [0x36, counter] => vec![SimAction::Respond(vec![0x76, *counter, 1, 2, 3, 4])],
+            _ => vec![SimAction::Respond(vec![0x7F, request[0], 0x11])],
+        })
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let mut progress = Vec::new();
+        let err = service
+            .upload_segment(
+                0x1000,
+                6,
+                &UploadOptions::default(),
+                &AtomicBool::new(false),
+                |p| progress.push(p.transferred_bytes),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+        assert_eq!(progress, vec![4]);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_data_wrong_counter_echo_rejected() {
+        let sim = EcuSimulator::start(|_| vec![SimAction::Respond(vec![0x76, 0x02])]).await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let err = service.transfer_data(0x01, &[0xAA]).await.unwrap_err();
+        assert!(matches!(err, UdsError::InvalidResponse(_)));
+    }
+
+    /// 以指定编码下载并在模拟器端解码校验，返回 RequestDownload 请求
+    async fn download_encoded(encoding: TransferEncoding, data: &[u8]) -> Vec<u8> {
+        let received = Arc::new(std::sync::Mutex::new((Vec::new(), Vec::new())));
+        let requests = request_log();
+        let sim = EcuSimulator::start(recording(
+            requests.clone(),
+            download_handler(0x0082, received.clone()),
+        ))
+        .await;
+        let mut service = sim.connect(fast_timing()).await;
+
+        let options = DownloadOptions {
+            encoding: encoding.clone(),
+            ..DownloadOptions::default()
+        };
+        let mut last_progress = 0;
+        service
+            .download_segment(0x0800_0000, data, &options, |p| {
+                last_progress = p.transferred_bytes
+            })
+            .await
+            .unwrap();
+        assert_eq!(last_progress, data.len());
+
+        let payload = received.lock().unwrap().0.clone();
+        let codec = encoding.build().unwrap();
+        assert_eq!(codec.decode(&payload).unwrap(), data);
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert_eq!(request[0], 0x34);
+        // memorySize 为原始数据长度
+        assert_eq!(&request[7..11], &(data.len() as u32).to_be_bytes());
+        request
+    }
+
+    #[tokio::test]
+    async fn test_download_compressed_and_encrypted() {
+        let mut data = b"flash block ".repeat(200);
+        data.extend(vec![0xFF; 1000]);
+
+        let request = download_encoded(
+            TransferEncoding {
+                compression: Some(CompressionConfig::Lzss { id: 0x1 }),
+                encryption: None,
+            },
+            &data,
+        )
+        .await;
+        assert_eq!(request[1], 0x10);
+
+        let request = download_encoded(
+            TransferEncoding {
+                compression: Some(CompressionConfig::Lzma { id: 0x2 }),
+                encryption: Some(EncryptionConfig::AesCtr {
+                    id: 0x1,
+                    key: (0..16).collect(),
+                    iv: vec![0x00; 16],
+                }),
+            },
+            &data,
+        )
+        .await;
+        assert_eq!(request[1], 0x21);
+
+        let request = download_encoded(TransferEncoding::default(), &data).await;
+        assert_eq!(request[1], 0x00);
+    }
+}
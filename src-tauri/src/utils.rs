@@ -107,50 +107,6 @@ pub fn starts_with(data: &[u8], prefix: &[u8]) -> bool {
     data[..prefix.len()] == *prefix
 }
 
-/// 检查字节数组是否以指定序列结束
-pub fn ends_with(data: &[u8], suffix: &[u8]) -> bool {
-    if data.len() < suffix.len() {
-        return false;
-    }
-
-    let start = data.len() - suffix.len();
-    data[start..] == *suffix
-}
-
-/// 检查是否为连续帧
-pub fn is_continuous_frames(data: &[u8], start_marker: &[u8]) -> bool {
-    let mut count = 0;
-    let mut pos = 0;
-
-    while pos < data.len() {
-        if let Some(found) = find_bytes(&data[pos..], start_marker) {
-            count += 1;
-            pos = pos + found + 1;
-        } else {
-            break;
-        }
-    }
-
-    count > 1
-}
-
-/// 提取最后一帧
-pub fn extract_last_frame(data: &[u8], start_marker: &[u8]) -> Option<Vec<u8>> {
-    let mut last_index = None;
-    let mut pos = 0;
-
-    while pos < data.len() {
-        if let Some(found) = find_bytes(&data[pos..], start_marker) {
-            last_index = Some(pos + found);
-            pos = pos + found + 1;
-        } else {
-            break;
-        }
-    }
-
-    last_index.map(|index| data[index..].to_vec())
-}
-
 /// 字节转ASCII字符串（处理不可打印字符）
 pub fn bytes_to_ascii(data: &[u8]) -> String {
     data.iter()
@@ -0,0 +1,684 @@
+/**
+ * VBF（Versioned Binary Format）刷写容器
+ * 解析 ASCII 头部中的元数据（零件号、擦除范围、校验块、调用地址等），
+ * 读取带 CRC16 的二进制数据块，并转换为刷写任务
+ */
+use crate::checksum::{crc16_ccitt, crc32};
+use crate::flash_job::{FlashJob, FlashJobConfig};
+use crate::types::{hex_bytes, ImageError, ImageResult, MemoryRange, MemorySegment};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// 头部字段值
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum VbfValue {
+    /// 带引号的字符串
+    Text(String),
+    /// 数字或标识符，保留原文
+    Word(String),
+    /// 花括号列表
+    List(Vec<VbfValue>),
+}
+
+impl VbfValue {
+    /// 字符串或标识符
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            VbfValue::Text(s) | VbfValue::Word(s) => Some(s),
+            VbfValue::List(_) => None,
+        }
+    }
+
+    /// 十六进制（0x 前缀）或十进制数值
+    pub fn as_u64(&self) -> Option<u64> {
+        let VbfValue::Word(word) = self else {
+            return None;
+        };
+        match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => word.parse().ok(),
+        }
+    }
+
+    /// 任意长度的十六进制数值（如哈希值）按字节返回
+    pub fn as_hex_bytes(&self) -> Option<Vec<u8>> {
+        let VbfValue::Word(word) = self else {
+            return None;
+        };
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))?;
+        let padded = if digits.len() % 2 == 1 {
+            format!("0{}", digits)
+        } else {
+            digits.to_string()
+        };
+        hex::decode(padded).ok()
+    }
+
+    /// 列表元素
+    pub fn as_list(&self) -> Option<&[VbfValue]> {
+        match self {
+            VbfValue::List(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+/// 校验块信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VerificationBlock {
+    pub start: u64,
+    pub length: u64,
+    #[serde(with = "hex_bytes")]
+    pub root_hash: Vec<u8>,
+}
+
+/// VBF 头部
+#[derive(Debug, Clone, Serialize)]
+pub struct VbfHeader {
+    pub vbf_version: String,
+    pub sw_part_number: Option<String>,
+    pub sw_part_type: Option<String>,
+    pub ecu_address: Option<u64>,
+    pub erase: Vec<MemoryRange>,
+    pub verification_block: Option<VerificationBlock>,
+    pub call: Option<u64>,
+    pub file_checksum: Option<u32>,
+    pub data_format_identifier: Option<u8>,
+    pub fields: BTreeMap<String, VbfValue>, // 全部原始字段
+}
+
+impl VbfHeader {
+    fn from_fields(vbf_version: String, fields: BTreeMap<String, VbfValue>) -> ImageResult<Self> {
+        let number = |key: &str| -> ImageResult<Option<u64>> {
+            match fields.get(key) {
+                Some(value) => value
+                    .as_u64()
+                    .map(Some)
+                    .ok_or_else(|| ImageError::InvalidVbf(format!("'{}' is not a number", key))),
+                None => Ok(None),
+            }
+        };
+        let text = |key: &str| fields.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        // 按字段宽度收窄，超出范围视为头部错误而不是截断
+        let narrow = |key: &str, value: Option<u64>, max: u64| -> ImageResult<Option<u64>> {
+            match value {
+                Some(v) if v > max => Err(ImageError::InvalidVbf(format!(
+                    "'{}' value 0x{:X} exceeds 0x{:X}",
+                    key, v, max
+                ))),
+                _ => Ok(value),
+            }
+        };
+
+        let erase = match fields.get("erase") {
+            Some(value) => parse_ranges(value)?,
+            None => Vec::new(),
+        };
+
+        let verification_block = match (
+            number("verification_block_start")?,
+            number("verification_block_length")?,
+        ) {
+            (Some(start), Some(length)) => Some(VerificationBlock {
+                start,
+                length,
+                root_hash: fields
+                    .get("verification_block_root_hash")
+                    .and_then(|v| v.as_hex_bytes())
+                    .unwrap_or_default(),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            vbf_version,
+            sw_part_number: text("sw_part_number"),
+            sw_part_type: text("sw_part_type"),
+            ecu_address: number("ecu_address")?,
+            erase,
+            verification_block,
+            call: number("call")?,
+            file_checksum: narrow("file_checksum", number("file_checksum")?, u32::MAX as u64)?
+                .map(|v| v as u32),
+            data_format_identifier: narrow(
+                "data_format_identifier",
+                number("data_format_identifier")?,
+                u8::MAX as u64,
+            )?
+            .map(|v| v as u8),
+            fields,
+        })
+    }
+}
+
+/// VBF 数据块
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VbfBlock {
+    #[serde(flatten)]
+    pub segment: MemorySegment,
+    pub crc: u16,
+}
+
+/// VBF 文件
+#[derive(Debug, Clone, Serialize)]
+pub struct VbfFile {
+    pub header: VbfHeader,
+    pub blocks: Vec<VbfBlock>,
+}
+
+impl VbfFile {
+    /// 读取并解析 VBF 文件
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// 解析头部和数据块，校验每个数据块的 CRC16 及 file_checksum
+    pub fn parse(data: &[u8]) -> ImageResult<Self> {
+        let (header, data_offset) = parse_header(data)?;
+        let blocks = parse_blocks(data, data_offset)?;
+
+        if let Some(expected) = header.file_checksum {
+            let found = crc32(&data[data_offset..]);
+            if found != expected {
+                return Err(ImageError::InvalidVbf(format!(
+                    "file_checksum 0x{:08X} does not match data 0x{:08X}",
+                    expected, found
+                )));
+            }
+        }
+
+        Ok(Self { header, blocks })
+    }
+
+    /// 数据块对应的内存段
+    pub fn segments(&self) -> Vec<MemorySegment> {
+        self.blocks.iter().map(|b| b.segment.clone()).collect()
+    }
+
+    /// 生成刷写任务：使用头部的擦除范围、数据格式和每块 CRC
+    ///
+    /// 头部数据格式非 0 时数据块已压缩或加密，不能再配置传输编码。
+    pub fn to_flash_job(&self, mut config: FlashJobConfig) -> ImageResult<FlashJob> {
+        if let Some(data_format_identifier) = self.header.data_format_identifier {
+            let encoding = &config.download.encoding;
+            if data_format_identifier != 0
+                && (encoding.compression.is_some() || encoding.encryption.is_some())
+            {
+                return Err(ImageError::InvalidVbf(format!(
+                    "data_format_identifier 0x{:02X} marks encoded blocks, transfer encoding must not be configured",
+                    data_format_identifier
+                )));
+            }
+            config.download.data_format_identifier = data_format_identifier;
+        }
+
+        let checksums = self
+            .blocks
+            .iter()
+            .map(|b| b.crc.to_be_bytes().to_vec())
+            .collect();
+        let job = FlashJob::new(config, self.segments()).with_segment_checksums(checksums);
+
+        Ok(if self.header.erase.is_empty() {
+            job
+        } else {
+            job.with_erase_ranges(self.header.erase.clone())
+        })
+    }
+}
+
+/// 解析 erase = { { start, length }, ... }
+fn parse_ranges(value: &VbfValue) -> ImageResult<Vec<MemoryRange>> {
+    let invalid = || ImageError::InvalidVbf("malformed erase ranges".to_string());
+
+    value
+        .as_list()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|range| match range.as_list() {
+            Some([address, length]) => Ok(MemoryRange {
+                address: address.as_u64().ok_or_else(invalid)?,
+                length: length.as_u64().ok_or_else(invalid)?,
+            }),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+/// 解析数据块：地址（4 字节）+ 长度（4 字节）+ 数据 + CRC16
+fn parse_blocks(data: &[u8], mut pos: usize) -> ImageResult<Vec<VbfBlock>> {
+    let mut blocks = Vec::new();
+
+    while pos < data.len() {
+        if data.len() - pos < 8 {
+            return Err(ImageError::InvalidVbf(format!(
+                "truncated block header at offset {}",
+                pos
+            )));
+        }
+        let address = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        let length =
+            u32::from_be_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+                as usize;
+        pos += 8;
+
+        if data.len() - pos < length + 2 {
+            return Err(ImageError::InvalidVbf(format!(
+                "truncated block at 0x{:08X}",
+                address
+            )));
+        }
+        let block_data = &data[pos..pos + length];
+        let crc = u16::from_be_bytes([data[pos + length], data[pos + length + 1]]);
+        pos += length + 2;
+
+        let computed = crc16_ccitt(block_data);
+        if computed != crc {
+            return Err(ImageError::BlockChecksumMismatch {
+                address: address as u64,
+                expected: crc as u32,
+                found: computed as u32,
+            });
+        }
+
+        blocks.push(VbfBlock {
+            segment: MemorySegment::new(address as u64, block_data.to_vec()),
+            crc,
+        });
+    }
+
+    Ok(blocks)
+}
+
+/// 头部词法单元
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Punct(u8),
+}
+
+/// 头部词法分析器，跳过空白和注释
+struct Lexer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn next_token(&mut self) -> ImageResult<Token> {
+        self.skip_trivia()?;
+
+        let start = self.pos;
+        let Some(&byte) = self.data.get(start) else {
+            return Err(ImageError::InvalidVbf(
+                "unexpected end of header".to_string(),
+            ));
+        };
+
+        match byte {
+            b'"' => {
+                let len = self.data[start + 1..]
+                    .iter()
+                    .position(|&b| b == b'"')
+                    .ok_or_else(|| {
+                        ImageError::InvalidVbf(format!("unterminated string at offset {}", start))
+                    })?;
+                self.pos = start + len + 2;
+                Ok(Token::Text(
+                    String::from_utf8_lossy(&self.data[start + 1..start + 1 + len]).into_owned(),
+                ))
+            }
+            b'{' | b'}' | b'=' | b';' | b',' => {
+                self.pos += 1;
+                Ok(Token::Punct(byte))
+            }
+            b if is_word_byte(b) => {
+                while self.pos < self.data.len() && is_word_byte(self.data[self.pos]) {
+                    self.pos += 1;
+                }
+                Ok(Token::Word(
+                    String::from_utf8_lossy(&self.data[start..self.pos]).into_owned(),
+                ))
+            }
+            _ => Err(ImageError::InvalidVbf(format!(
+                "unexpected byte 0x{:02X} at offset {}",
+                byte, start
+            ))),
+        }
+    }
+
+    fn skip_trivia(&mut self) -> ImageResult<()> {
+        loop {
+            let rest = &self.data[self.pos..];
+            if rest.first().is_some_and(|b| b.is_ascii_whitespace()) {
+                self.pos += 1;
+            } else if rest.starts_with(b"//") {
+                self.pos += rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+            } else if rest.starts_with(b"/*") {
+                let end = rest.windows(2).position(|w| w == b"*/").ok_or_else(|| {
+                    ImageError::InvalidVbf("unterminated comment in header".to_string())
+                })?;
+                self.pos += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> ImageResult<()> {
+        let token = self.next_token()?;
+        if token != expected {
+            return Err(ImageError::InvalidVbf(format!(
+                "expected {:?}, found {:?}",
+                expected, token
+            )));
+        }
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> ImageResult<VbfValue> {
+        let token = self.next_token()?;
+        self.value_from(token)
+    }
+
+    fn value_from(&mut self, token: Token) -> ImageResult<VbfValue> {
+        match token {
+            Token::Text(text) => Ok(VbfValue::Text(text)),
+            Token::Word(word) => Ok(VbfValue::Word(word)),
+            Token::Punct(b'{') => {
+                let mut items = Vec::new();
+                let first = self.next_token()?;
+                if first == Token::Punct(b'}') {
+                    return Ok(VbfValue::List(items));
+                }
+                items.push(self.value_from(first)?);
+                loop {
+                    match self.next_token()? {
+                        Token::Punct(b',') => items.push(self.parse_value()?),
+                        Token::Punct(b'}') => return Ok(VbfValue::List(items)),
+                        token => {
+                            return Err(ImageError::InvalidVbf(format!(
+                                "unexpected {:?} in list",
+                                token
+                            )))
+                        }
+                    }
+                }
+            }
+            token => Err(ImageError::InvalidVbf(format!(
+                "unexpected {:?} in value",
+                token
+            ))),
+        }
+    }
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-' | b'+')
+}
+
+/// 解析头部，返回头部信息和数据区起始偏移
+fn parse_header(data: &[u8]) -> ImageResult<(VbfHeader, usize)> {
+    let mut lexer = Lexer { data, pos: 0 };
+
+    lexer.expect(Token::Word("vbf_version".to_string()))?;
+    lexer.expect(Token::Punct(b'='))?;
+    let vbf_version = match lexer.next_token()? {
+        Token::Word(version) => version,
+        token => {
+            return Err(ImageError::InvalidVbf(format!(
+                "invalid vbf_version {:?}",
+                token
+            )))
+        }
+    };
+    lexer.expect(Token::Punct(b';'))?;
+    lexer.expect(Token::Word("header".to_string()))?;
+    lexer.expect(Token::Punct(b'{'))?;
+
+    let mut fields = BTreeMap::new();
+    loop {
+        match lexer.next_token()? {
+            Token::Punct(b'}') => break,
+            Token::Word(key) => {
+                lexer.expect(Token::Punct(b'='))?;
+                let value = lexer.parse_value()?;
+                lexer.expect(Token::Punct(b';'))?;
+                fields.insert(key, value);
+            }
+            token => {
+                return Err(ImageError::InvalidVbf(format!(
+                    "unexpected {:?} in header",
+                    token
+                )))
+            }
+        }
+    }
+
+    Ok((VbfHeader::from_fields(vbf_version, fields)?, lexer.pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_format::CompressionConfig;
+    use crate::ecu_simulator::{fast_timing, flash_handler, request_log, EcuSimulator};
+    use crate::flash_job::{FlashAction, FlashStep};
+    use crate::types::{CommonRoutines, RoutinePollConfig};
+
+    fn block(address: u32, data: &[u8]) -> Vec<u8> {
+        let mut block = address.to_be_bytes().to_vec();
+        block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        block.extend_from_slice(data);
+        block.extend_from_slice(&crc16_ccitt(data).to_be_bytes());
+        block
+    }
+
+    fn build_vbf(extra_header: &str, blocks: &[u8]) -> Vec<u8> {
+        let header = format!(
+            r#"vbf_version = 2.2;
+
+header {{
+    // 零件信息
+    sw_part_number = "32212345";
+    sw_part_type = EXE;
+    network = DOIP;
+    ecu_address = 0x1001;
+    /* 擦除 {{ 两个区域 }} */
+    erase = {{ {{ 0x00008000, 0x00010000 }}, {{ 0x00020000, 0x400 }} }};
+    verification_block_start = 0x00008000;
+    verification_block_length = 0x00000040;
+    verification_block_root_hash = 0x0A0B0C;
+    call = 0x00008000;
+    file_checksum = 0x{:08X};
+    data_format_identifier = 0x00;
+    {}
+}}"#,
+            crc32(blocks),
+            extra_header
+        );
+        let mut file = header.into_bytes();
+        file.extend_from_slice(blocks);
+        file
+    }
+
+    #[test]
+    fn test_parse_vbf() {
+        let mut blocks = block(0x8000, &[0x01, 0x02, 0x03, 0x04]);
+        // 数据中出现 '}' 不影响头部结束位置
+        blocks.extend(block(0x20000, b"}}data"));
+        let vbf = VbfFile::parse(&build_vbf("sw_signature = { \"a\", \"b\" };", &blocks)).unwrap();
+
+        let header = &vbf.header;
+        assert_eq!(header.vbf_version, "2.2");
+        assert_eq!(header.sw_part_number.as_deref(), Some("32212345"));
+        assert_eq!(header.sw_part_type.as_deref(), Some("EXE"));
+        assert_eq!(header.ecu_address, Some(0x1001));
+        assert_eq!(
+            header.erase,
+            vec![
+                MemoryRange {
+                    address: 0x8000,
+                    length: 0x10000
+                },
+                MemoryRange {
+                    address: 0x20000,
+                    length: 0x400
+                },
+            ]
+        );
+        assert_eq!(
+            header.verification_block,
+            Some(VerificationBlock {
+                start: 0x8000,
+                length: 0x40,
+                root_hash: vec![0x0A, 0x0B, 0x0C],
+            })
+        );
+        assert_eq!(header.call, Some(0x8000));
+        assert_eq!(
+            header.fields["sw_signature"],
+            VbfValue::List(vec![
+                VbfValue::Text("a".to_string()),
+                VbfValue::Text("b".to_string())
+            ])
+        );
+
+        assert_eq!(vbf.blocks.len(), 2);
+        assert_eq!(vbf.blocks[0].segment.address, 0x8000);
+        assert_eq!(vbf.blocks[1].segment.data, b"}}data".to_vec());
+        assert_eq!(vbf.blocks[0].crc, crc16_ccitt(&[0x01, 0x02, 0x03, 0x04]));
+    }
+
+    #[test]
+    fn test_block_crc_mismatch() {
+        let mut blocks = block(0x8000, &[0x01, 0x02, 0x03, 0x04]);
+        let last = blocks.len() - 1;
+        blocks[last] ^= 0xFF;
+        let mut file = build_vbf("", &blocks);
+        // file_checksum 按损坏后的数据计算，错误由块 CRC 发现
+        let err = VbfFile::parse(&file).unwrap_err();
+        assert!(matches!(
+            err,
+            ImageError::BlockChecksumMismatch {
+                address: 0x8000,
+                ..
+            }
+        ));
+
+        // 截断的数据块
+        file.truncate(file.len() - 3);
+        assert!(matches!(
+            VbfFile::parse(&file).unwrap_err(),
+            ImageError::InvalidVbf(_)
+        ));
+    }
+
+    #[test]
+    fn test_file_checksum_mismatch() {
+        let blocks = block(0x8000, &[0xAA; 16]);
+        let mut file = build_vbf("", &blocks);
+        // 追加一个有效块，块 CRC 正确但 file_checksum 不再匹配
+        file.extend(block(0x9000, &[0x55]));
+        let err = VbfFile::parse(&file).unwrap_err();
+        assert!(matches!(err, ImageError::InvalidVbf(msg) if msg.contains("file_checksum")));
+    }
+
+    #[test]
+    fn test_malformed_header() {
+        assert!(VbfFile::parse(b"vbf_version = 2.2; header { erase = { 1, 2 }; }").is_err());
+        assert!(VbfFile::parse(b"vbf_version = 2.2; header { call = 0x10 }").is_err());
+        assert!(VbfFile::parse(b"header { }").is_err());
+        let vbf = VbfFile::parse(b"vbf_version = 2.2; header { call = 0x10; }").unwrap();
+        assert!(vbf.blocks.is_empty());
+        assert_eq!(vbf.header.call, Some(0x10));
+    }
+
+    #[test]
+    fn test_header_value_out_of_range() {
+        assert!(matches!(
+            VbfFile::parse(b"vbf_version = 2.2; header { file_checksum = 0x100000000; }"),
+            Err(ImageError::InvalidVbf(_))
+        ));
+        assert!(matches!(
+            VbfFile::parse(b"vbf_version = 2.2; header { data_format_identifier = 0x100; }"),
+            Err(ImageError::InvalidVbf(_))
+        ));
+        let vbf = VbfFile::parse(&build_vbf("data_format_identifier = 0xFF;", &[])).unwrap();
+        assert_eq!(vbf.header.data_format_identifier, Some(0xFF));
+    }
+
+    #[tokio::test]
+    async fn test_to_flash_job_uses_header() {
+        let blocks = block(0x8000, &[0x01; 8]);
+        let vbf = VbfFile::parse(&build_vbf("data_format_identifier = 0x10;", &blocks)).unwrap();
+
+        let poll = RoutinePollConfig::default();
+        let config = FlashJobConfig {
+            steps: vec![
+                FlashStep::new(
+                    "擦除",
+                    FlashAction::EraseMemory { poll: poll.clone() },
+                    None,
+                ),
+                FlashStep::new("下载", FlashAction::Download, None),
+                FlashStep::new(
+                    "校验",
+                    FlashAction::CheckMemory {
+                        routine_id: CommonRoutines::CHECK_MEMORY,
+                        poll,
+                    },
+                    None,
+                ),
+            ],
+            ..FlashJobConfig::default()
+        };
+        let job = vbf.to_flash_job(config.clone()).unwrap();
+        assert_eq!(job.segment_count(), 1);
+        assert_eq!(job.total_bytes(), 8);
+
+        let requests = request_log();
+        let sim = EcuSimulator::start(flash_handler(requests.clone(), None)).await;
+        let mut service = sim.connect(fast_timing()).await;
+        job.run(&mut service, |_| {}).await.unwrap();
+
+        // 头部的两个擦除范围、数据格式和数据块 CRC16
+        let requests = requests.lock().unwrap();
+        let erase: Vec<&Vec<u8>> = requests
+            .iter()
+            .filter(|r| r.starts_with(&[0x31, 0x01, 0xFF, 0x00]))
+            .collect();
+        assert_eq!(
+            erase,
+            vec![
+                &vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x00, 0x00, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00],
+                &vec![0x31, 0x01, 0xFF, 0x00, 0x44, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00],
+            ]
+        );
+        let download = requests.iter().find(|r| r[0] == 0x34).unwrap();
+        assert_eq!(
+            download,
+            &vec![0x34, 0x10, 0x44, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x08]
+        );
+        let check = requests
+            .iter()
+            .find(|r| r.starts_with(&[0x31, 0x01, 0x02, 0x02]))
+            .unwrap();
+        assert_eq!(
+            check[check.len() - 2..],
+            crc16_ccitt(&[0x01; 8]).to_be_bytes()
+        );
+
+        // 已编码的数据块不能再配置传输编码
+        let mut encoded = config;
+        encoded.download.encoding.compression = Some(CompressionConfig::Lzss { id: 0x10 });
+        assert!(matches!(
+            vbf.to_flash_job(encoded),
+            Err(ImageError::InvalidVbf(_))
+        ));
+    }
+}
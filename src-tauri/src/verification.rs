@@ -0,0 +1,445 @@
+/**
+ * 刷写数据校验
+ * 发送到 ECU 之前按内存段计算校验值并与期望值比对，
+ * 可选使用配置的公钥验证整个镜像的签名
+ */
+use crate::checksum::ChecksumAlgorithm;
+use crate::types::{hex_bytes, MemorySegment, UdsError, UdsResult};
+use serde::{Deserialize, Serialize};
+
+/// 签名算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureAlgorithm {
+    Ed25519,
+    RsaPkcs1Sha256,
+    RsaPssSha256,
+    EcdsaP256Sha256,
+}
+
+/// 签名配置，签名内容见 signed_message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureConfig {
+    pub algorithm: SignatureAlgorithm,
+    pub public_key: String, // PEM 或十六进制（Ed25519 原始公钥、RSA DER 公钥、P-256 SEC1 公钥）
+    #[serde(with = "hex_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// 校验配置
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerificationConfig {
+    pub checksum: Option<ChecksumAlgorithm>, // 设置后校验值同时作为校验内存例程的参数
+    pub expected_checksums: Vec<String>, // 按内存段顺序的期望校验值（十六进制），为空或与内存段一一对应
+    pub signature: Option<SignatureConfig>,
+}
+
+/// 内存段校验结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentVerification {
+    pub address: u64,
+    pub length: usize,
+    pub algorithm: ChecksumAlgorithm,
+    pub checksum: String,
+    pub expected: Option<String>,
+    pub matched: Option<bool>,
+}
+
+/// 签名校验结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureVerification {
+    pub algorithm: SignatureAlgorithm,
+    pub valid: bool,
+    pub error: Option<String>,
+}
+
+/// 校验报告
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub segments: Vec<SegmentVerification>,
+    pub signature: Option<SignatureVerification>,
+}
+
+impl VerificationReport {
+    /// 所有校验值一致且签名有效
+    pub fn passed(&self) -> bool {
+        self.segments.iter().all(|s| s.matched != Some(false))
+            && self.signature.as_ref().is_none_or(|s| s.valid)
+    }
+
+    /// 失败项说明
+    pub fn failures(&self) -> Vec<String> {
+        let mut failures: Vec<String> = self
+            .segments
+            .iter()
+            .filter(|s| s.matched == Some(false))
+            .map(|s| {
+                format!(
+                    "segment 0x{:X} checksum {} does not match {}",
+                    s.address,
+                    s.checksum,
+                    s.expected.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        if let Some(signature) = self.signature.as_ref().filter(|s| !s.valid) {
+            failures.push(format!(
+                "{:?} signature invalid: {}",
+                signature.algorithm,
+                signature.error.as_deref().unwrap_or_default()
+            ));
+        }
+        failures
+    }
+}
+
+/// 计算各内存段校验值并验证签名，配置格式错误时返回错误，校验不一致记录在报告中
+pub fn verify_image(
+    segments: &[MemorySegment],
+    config: &VerificationConfig,
+) -> UdsResult<VerificationReport> {
+    let mut report = VerificationReport::default();
+
+    if !config.expected_checksums.is_empty() {
+        if config.checksum.is_none() {
+            return Err(UdsError::InvalidParameter(
+                "Expected checksums given without a checksum algorithm".to_string(),
+            ));
+        }
+        if config.expected_checksums.len() != segments.len() {
+            return Err(UdsError::InvalidParameter(format!(
+                "{} expected checksums given for {} segments",
+                config.expected_checksums.len(),
+                segments.len()
+            )));
+        }
+    }
+
+    if let Some(algorithm) = config.checksum {
+        for (index, segment) in segments.iter().enumerate() {
+            let checksum = algorithm.compute(&segment.data);
+            let expected = match config.expected_checksums.get(index) {
+                Some(text) => Some(hex::decode(text.trim()).map_err(|e| {
+                    UdsError::InvalidParameter(format!("Invalid expected checksum: {}", e))
+                })?),
+                None => None,
+            };
+            report.segments.push(SegmentVerification {
+                address: segment.address,
+                length: segment.data.len(),
+                algorithm,
+                checksum: hex::encode_upper(&checksum),
+                matched: expected.as_ref().map(|e| *e == checksum),
+                expected: expected.map(hex::encode_upper),
+            });
+        }
+    }
+
+    if let Some(signature) = &config.signature {
+        let message = signed_message(segments)?;
+        let result = verify_signature(
+            signature.algorithm,
+            &signature.public_key,
+            &message,
+            &signature.signature,
+        );
+        report.signature = Some(SignatureVerification {
+            algorithm: signature.algorithm,
+            valid: result.is_ok(),
+            error: result.err(),
+        });
+    }
+
+    Ok(report)
+}
+
+/// 签名内容：依次为各内存段的 4 字节地址、4 字节长度（大端）和数据，
+/// 地址和长度参与签名，数据被移动到其他地址时签名失效
+pub fn signed_message(segments: &[MemorySegment]) -> UdsResult<Vec<u8>> {
+    let mut message = Vec::with_capacity(segments.iter().map(|s| s.data.len() + 8).sum());
+    for segment in segments {
+        let address = u32::try_from(segment.address).map_err(|_| {
+            UdsError::InvalidParameter(format!(
+                "Segment address 0x{:X} does not fit the signed 32-bit address",
+                segment.address
+            ))
+        })?;
+        let length = u32::try_from(segment.data.len()).map_err(|_| {
+            UdsError::InvalidParameter(format!(
+                "Segment at 0x{:X} is too large to sign",
+                segment.address
+            ))
+        })?;
+        message.extend_from_slice(&address.to_be_bytes());
+        message.extend_from_slice(&length.to_be_bytes());
+        message.extend_from_slice(&segment.data);
+    }
+    Ok(message)
+}
+
+/// 验证签名，RSA/ECDSA 对消息做 SHA-256 摘要，Ed25519 直接签名消息
+pub fn verify_signature(
+    algorithm: SignatureAlgorithm,
+    public_key: &str,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    let public_key = public_key.trim();
+    let is_pem = public_key.starts_with("-----BEGIN");
+    let key_bytes = || hex::decode(public_key).map_err(|e| format!("invalid public key: {}", e));
+
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => {
+            use ed25519_dalek::pkcs8::DecodePublicKey;
+            use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+            let key = if is_pem {
+                VerifyingKey::from_public_key_pem(public_key).map_err(|e| e.to_string())?
+            } else {
+                let key: [u8; 32] = key_bytes()?
+                    .try_into()
+                    .map_err(|_| "Ed25519 public key must be 32 bytes".to_string())?;
+                VerifyingKey::from_bytes(&key).map_err(|e| e.to_string())?
+            };
+            let signature = Signature::from_slice(signature).map_err(|e| e.to_string())?;
+            key.verify(message, &signature).map_err(|e| e.to_string())
+        }
+        SignatureAlgorithm::RsaPkcs1Sha256 | SignatureAlgorithm::RsaPssSha256 => {
+            use rsa::pkcs1::DecodeRsaPublicKey;
+            use rsa::pkcs8::DecodePublicKey;
+            use rsa::signature::Verifier;
+            use rsa::RsaPublicKey;
+            use sha2::Sha256;
+
+            let key = if is_pem {
+                RsaPublicKey::from_public_key_pem(public_key)
+                    .or_else(|_| RsaPublicKey::from_pkcs1_pem(public_key))
+                    .map_err(|e| e.to_string())?
+            } else {
+                RsaPublicKey::from_public_key_der(&key_bytes()?).map_err(|e| e.to_string())?
+            };
+
+            if algorithm == SignatureAlgorithm::RsaPkcs1Sha256 {
+                let signature =
+                    rsa::pkcs1v15::Signature::try_from(signature).map_err(|e| e.to_string())?;
+                rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key)
+                    .verify(message, &signature)
+                    .map_err(|e| e.to_string())
+            } else {
+                let signature =
+                    rsa::pss::Signature::try_from(signature).map_err(|e| e.to_string())?;
+                rsa::pss::VerifyingKey::<Sha256>::new(key)
+                    .verify(message, &signature)
+                    .map_err(|e| e.to_string())
+            }
+        }
+        SignatureAlgorithm::EcdsaP256Sha256 => {
+            use p256::ecdsa::signature::Verifier;
+            use p256::ecdsa::{Signature, VerifyingKey};
+            use p256::pkcs8::DecodePublicKey;
+
+            let key = if is_pem {
+                VerifyingKey::from_public_key_pem(public_key).map_err(|e| e.to_string())?
+            } else {
+                VerifyingKey::from_sec1_bytes(&key_bytes()?).map_err(|e| e.to_string())?
+            };
+            // 签名可为 64 字节 r||s 或 DER 编码
+            let signature = Signature::from_slice(signature)
+                .or_else(|_| Signature::from_der(signature))
+                .map_err(|e| e.to_string())?;
+            key.verify(message, &signature).map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSA_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQCe5//DW/MnNuQnvMvV4ADzbNO+
+plMDy15OqsbbsZo5m5DpUXxc+SjGucD3uLD2N71W9JbsXByfxlmeW1wA++UjSqza
+ByjxdzymBcLJWQEVn8H6dyoyyVCYmprgEmpeRC5fPeflrqipvEdaCLwc6Vr9YKt7
+wekOUIu4A4l2YeTELwIDAQAB
+-----END PUBLIC KEY-----";
+    const RSA_PKCS1_SIGNATURE: &str = "183ae2dde037990e1db048b645fa907833685580176f8acf54bd18ced370844c3bbe01d61e08b4421a37324e80a741f22ea81ec22486a0d81c933fd4d38d251a1c5d6c24b35fed7acf408455bc84019cf9a8876b8c0a2ad647d4b0b8a7e07d665a0e18eb8291465dbb0d2b8b77d36e78ad673e9c24b3e2d95565926a4ff75d33";
+    const RSA_PSS_SIGNATURE: &str = "9e608e24fd4fba814788aea25a3a3d54d451b0e0f28b5d800381dff6cd3e5d7d97d5ab132fffdf49143d34e674aa6eaae59c2cd58999cc8a693a4935d3683c86f9f534c4e105fa9754e815384481fd39917661bfe10e7403af6f0a80253bc2f392e3b82d8277cadbb0655090a87e651a8d540780cb88fb4d6ba4eb13b250f6c0";
+
+    /// 签名内容为 00001000 00000006 "hello " 00002000 00000005 "flash"
+    fn segments() -> Vec<MemorySegment> {
+        vec![
+            MemorySegment::new(0x1000, b"hello ".to_vec()),
+            MemorySegment::new(0x2000, b"flash".to_vec()),
+        ]
+    }
+
+    #[test]
+    fn test_segment_checksums() {
+        let config = VerificationConfig {
+            checksum: Some(ChecksumAlgorithm::Crc16Ccitt),
+            expected_checksums: vec![
+                hex::encode(ChecksumAlgorithm::Crc16Ccitt.compute(b"hello ")),
+                "0000".to_string(),
+            ],
+            signature: None,
+        };
+        let report = verify_image(&segments(), &config).unwrap();
+        assert_eq!(report.segments[0].matched, Some(true));
+        assert_eq!(report.segments[1].matched, Some(false));
+        assert!(!report.passed());
+        assert_eq!(report.failures().len(), 1);
+
+        let invalid = VerificationConfig {
+            expected_checksums: vec!["zz".to_string(), "0000".to_string()],
+            ..config.clone()
+        };
+        assert!(verify_image(&segments(), &invalid).is_err());
+
+        // 期望值数量与内存段不一致，或未指定校验算法
+        let missing = VerificationConfig {
+            expected_checksums: vec!["0000".to_string()],
+            ..config.clone()
+        };
+        assert!(matches!(
+            verify_image(&segments(), &missing),
+            Err(UdsError::InvalidParameter(_))
+        ));
+        let no_algorithm = VerificationConfig {
+            checksum: None,
+            ..config
+        };
+        assert!(matches!(
+            verify_image(&segments(), &no_algorithm),
+            Err(UdsError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_ed25519_and_ecdsa_signatures() {
+        use ed25519_dalek::Signer;
+        let message = b"hello flash";
+
+        let ed_key = ed25519_dalek::SigningKey::from_bytes(&[0x42; 32]);
+        let ed_public = hex::encode(ed_key.verifying_key().to_bytes());
+        let ed_signature = ed_key.sign(message).to_bytes();
+        assert!(verify_signature(
+            SignatureAlgorithm::Ed25519,
+            &ed_public,
+            message,
+            &ed_signature
+        )
+        .is_ok());
+        assert!(verify_signature(
+            SignatureAlgorithm::Ed25519,
+            &ed_public,
+            b"tampered",
+            &ed_signature
+        )
+        .is_err());
+
+        let ed_pem = ed_key
+            .verifying_key()
+            .to_public_key_pem(Default::default())
+            .unwrap();
+        assert!(
+            verify_signature(SignatureAlgorithm::Ed25519, &ed_pem, message, &ed_signature).is_ok()
+        );
+
+        use p256::pkcs8::EncodePublicKey;
+        let ec_key = p256::ecdsa::SigningKey::from_slice(&[0x11; 32]).unwrap();
+        let ec_signature: p256::ecdsa::Signature = ec_key.sign(message);
+        let ec_sec1 = hex::encode(ec_key.verifying_key().to_encoded_point(false).as_bytes());
+        let ec_pem = ec_key
+            .verifying_key()
+            .to_public_key_pem(Default::default())
+            .unwrap();
+        for key in [ec_sec1.as_str(), ec_pem.as_str()] {
+            assert!(verify_signature(
+                SignatureAlgorithm::EcdsaP256Sha256,
+                key,
+                message,
+                &ec_signature.to_bytes()
+            )
+            .is_ok());
+        }
+        assert!(verify_signature(
+            SignatureAlgorithm::EcdsaP256Sha256,
+            &ec_sec1,
+            message,
+            ec_signature.to_der().as_bytes()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_rsa_signatures() {
+        let config = |algorithm, signature: &str| VerificationConfig {
+            checksum: None,
+            expected_checksums: Vec::new(),
+            signature: Some(SignatureConfig {
+                algorithm,
+                public_key: RSA_PUBLIC_KEY.to_string(),
+                signature: hex::decode(signature).unwrap(),
+            }),
+        };
+
+        let report = verify_image(
+            &segments(),
+            &config(SignatureAlgorithm::RsaPkcs1Sha256, RSA_PKCS1_SIGNATURE),
+        )
+        .unwrap();
+        assert!(report.passed());
+
+        let report = verify_image(
+            &segments(),
+            &config(SignatureAlgorithm::RsaPssSha256, RSA_PSS_SIGNATURE),
+        )
+        .unwrap();
+        assert!(report.passed());
+
+        // 签名与算法不匹配
+        let report = verify_image(
+            &segments(),
+            &config(SignatureAlgorithm::RsaPkcs1Sha256, RSA_PSS_SIGNATURE),
+        )
+        .unwrap();
+        assert!(!report.passed());
+        assert!(report.signature.unwrap().error.is_some());
+    }
+
+    #[test]
+    fn test_signature_covers_addresses() {
+        use ed25519_dalek::Signer;
+        let key = ed25519_dalek::SigningKey::from_bytes(&[0x42; 32]);
+        let config = |segments: &[MemorySegment]| VerificationConfig {
+            checksum: None,
+            expected_checksums: Vec::new(),
+            signature: Some(SignatureConfig {
+                algorithm: SignatureAlgorithm::Ed25519,
+                public_key: hex::encode(key.verifying_key().to_bytes()),
+                signature: key
+                    .sign(&signed_message(segments).unwrap())
+                    .to_bytes()
+                    .to_vec(),
+            }),
+        };
+        let config = config(&segments());
+        assert!(verify_image(&segments(), &config).unwrap().passed());
+
+        // 数据相同但地址不同
+        let mut moved = segments();
+        moved[1].address = 0x3000;
+        assert!(!verify_image(&moved, &config).unwrap().passed());
+
+        // 数据拼接相同但段边界不同
+        let split = vec![
+            MemorySegment::new(0x1000, b"hello f".to_vec()),
+            MemorySegment::new(0x2000, b"lash".to_vec()),
+        ];
+        assert!(!verify_image(&split, &config).unwrap().passed());
+
+        let high = vec![MemorySegment::new(0x1_0000_0000, vec![0x00])];
+        assert!(verify_image(&high, &config).is_err());
+    }
+}
@@ -14,11 +14,17 @@ export interface ConnectionConfig {
   timeout?: number;
 }
 
+export interface NrcInfo {
+  code: number;
+  name: string;
+}
+
 export interface DiagnosticResult {
   success: boolean;
   message: string;
   data?: any;
   timestamp: string;
+  nrc?: NrcInfo | null;
 }
 
 export interface PingResult {